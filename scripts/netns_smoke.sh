@@ -0,0 +1,185 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+ROOT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+IFNAME="svpn-smoke"
+NS_NAME="sillyvpn-ns"
+TABLE_ID="51820"
+FWMARK="0x51"
+MODE="${1:-file}"
+
+if [[ "$(id -u)" -ne 0 ]]; then
+  echo "Run as root: the smoke test creates a network namespace and routing rules."
+  exit 1
+fi
+
+for tool in cargo wg ip iptables; do
+  if ! command -v "$tool" >/dev/null 2>&1; then
+    echo "[missing] $tool"
+    exit 1
+  fi
+done
+
+if ip netns list | grep -qw "$NS_NAME"; then
+  echo "Namespace $NS_NAME already exists, disable sillyvpn first."
+  exit 1
+fi
+
+echo "[sillyvpn] Building helper..."
+cd "$ROOT_DIR/src-tauri"
+cargo build --quiet --bin sillyvpn-helper
+HELPER="$ROOT_DIR/src-tauri/target/debug/sillyvpn-helper"
+
+WORK_DIR="$(mktemp -d)"
+trap '"$HELPER" disable >/dev/null 2>&1 || true; rm -rf "$WORK_DIR"' EXIT
+
+CONFIG="$WORK_DIR/smoke.conf"
+cat <<CONF > "$CONFIG"
+[Interface]
+PrivateKey = $(wg genkey)
+Address = 10.99.0.2/32
+
+[Peer]
+PublicKey = $(wg genkey | wg pubkey)
+AllowedIPs = 0.0.0.0/0
+Endpoint = 127.0.0.1:51999
+
+[Peer]
+PublicKey = $(wg genkey | wg pubkey)
+AllowedIPs = 10.99.1.0/24
+Endpoint = 127.0.0.1:51998
+CONF
+
+if [[ "$MODE" == "crlf" ]]; then
+  sed -i 's/^Address = .*/&\nDNS = 10.99.0.1/' "$CONFIG"
+  sed -i 's/$/\r/' "$CONFIG"
+fi
+
+if [[ "$MODE" == "ipv6" ]]; then
+  sed -i 's|^Address = .*|Address = 10.99.0.2/32, fd99::2/128\nDNS = 10.99.0.1, fd99::1|; s|^AllowedIPs = 0.0.0.0/0$|AllowedIPs = 0.0.0.0/0, ::/0|' "$CONFIG"
+fi
+
+if [[ "$MODE" == "reordered" ]]; then
+  {
+    echo "# peers first, interface last"
+    echo ""
+    sed -n '/^\[Peer\]/,$p' "$CONFIG"
+    echo ""
+    echo "[interface]"
+    echo "Table = auto"
+    sed -n '2,/^$/p' "$CONFIG"
+  } > "$CONFIG.reordered"
+  mv "$CONFIG.reordered" "$CONFIG"
+fi
+
+failed=0
+
+expect() {
+  local description="$1"
+  shift
+  if "$@" >/dev/null 2>&1; then
+    echo "[ok] $description"
+  else
+    echo "[fail] $description"
+    failed=1
+  fi
+}
+
+expect_not() {
+  local description="$1"
+  shift
+  if "$@" >/dev/null 2>&1; then
+    echo "[fail] $description"
+    failed=1
+  else
+    echo "[ok] $description"
+  fi
+}
+
+IP_FORWARD_BEFORE="$(cat /proc/sys/net/ipv4/ip_forward)"
+IP6_FORWARD_BEFORE="$(cat /proc/sys/net/ipv6/conf/all/forwarding)"
+
+echo "[sillyvpn] Enabling ($MODE config)..."
+if [[ "$MODE" == "stdin" ]]; then
+  "$HELPER" enable --config-stdin --ifname "$IFNAME" < "$CONFIG"
+elif [[ "$MODE" == "killswitch" ]]; then
+  "$HELPER" enable --config "$CONFIG" --ifname "$IFNAME" --killswitch
+else
+  "$HELPER" enable --config "$CONFIG" --ifname "$IFNAME"
+fi
+
+expect "namespace exists" sh -c "ip netns list | grep -qw $NS_NAME"
+expect "wireguard interface is up" sh -c "ip link show $IFNAME up | grep -q $IFNAME"
+expect "fwmark rule installed" sh -c "ip rule show | grep -q 'fwmark $FWMARK lookup $TABLE_ID'"
+expect "default route in table $TABLE_ID" sh -c "ip route show table $TABLE_ID | grep -q 'default dev $IFNAME'"
+expect "masquerade rule installed" iptables -t nat -C POSTROUTING -s 10.200.0.0/24 -o "$IFNAME" -j MASQUERADE
+expect "namespace default route via veth" sh -c "ip netns exec $NS_NAME ip route | grep -q 'default via 10.200.0.1'"
+expect "both peers configured on the interface" sh -c "test \$(wg show $IFNAME peers | wc -l) -eq 2"
+expect "status reports the interface up" sh -c "'$HELPER' status | grep -q '\"interface_up\":true'"
+expect "peers probe reports both peers" sh -c "test \$('$HELPER' peers | grep -o '\"public_key\"' | wc -l) -eq 2"
+expect "transfer stats report both peers" sh -c "test \$('$HELPER' stats | grep -o '\"rx_bytes\"' | wc -l) -eq 2"
+expect_not "wg-quick did not add its own routing rules" sh -c "ip rule show | grep -q 'not from all fwmark'"
+if [[ "$MODE" == "crlf" ]]; then
+  expect "DNS moved from the config into the namespace" sh -c "ip netns exec $NS_NAME cat /etc/resolv.conf | grep -qx 'nameserver 10.99.0.1'"
+fi
+if [[ "$MODE" == "ipv6" ]]; then
+  expect "IPv6 fwmark rule installed" sh -c "ip -6 rule show | grep -q 'fwmark $FWMARK lookup $TABLE_ID'"
+  expect "namespace veth has an IPv6 address" sh -c "ip netns exec $NS_NAME ip -6 addr show dev svpn1 | grep -q 'fd00:200::2/64'"
+  expect "namespace IPv6 default route via veth" sh -c "ip netns exec $NS_NAME ip -6 route | grep -q 'default via fd00:200::1'"
+  expect "IPv6 forwarding enabled" test "$(cat /proc/sys/net/ipv6/conf/all/forwarding)" = "1"
+  expect "IPv6 nameserver carried into the namespace" sh -c "ip netns exec $NS_NAME cat /etc/resolv.conf | grep -qx 'nameserver fd99::1'"
+else
+  expect_not "no IPv6 fwmark rule for a v4-only tunnel" sh -c "ip -6 rule show | grep -q 'fwmark $FWMARK'"
+fi
+if [[ "$MODE" == "killswitch" ]]; then
+  expect "kill switch drops forwarding outside the tunnel" iptables -C FORWARD -s 10.200.0.0/24 ! -o "$IFNAME" -j DROP
+  expect "blackhole fallback in table $TABLE_ID" sh -c "ip route show table $TABLE_ID | grep -q '^blackhole default'"
+  ip link set "$IFNAME" down
+  expect_not "namespace has no route out with the interface down" ip netns exec "$NS_NAME" ping -c1 -W1 1.1.1.1
+  ip link set "$IFNAME" up
+  ip route replace default dev "$IFNAME" table "$TABLE_ID"
+fi
+
+APP_DIR="$WORK_DIR/app dir=ü"
+APP_BIN="$APP_DIR/my app=проверка.sh"
+mkdir -p "$APP_DIR"
+cat <<'APP' > "$APP_BIN"
+#!/bin/sh -e
+readlink /proc/self/ns/net > "$(dirname "$0")/netns"
+APP
+chmod +x "$APP_BIN"
+expect "app with spaces, '=' and unicode in its path launches" "$HELPER" run --wait --bin "$APP_BIN"
+expect "that app ran inside the namespace" sh -c "test \"\$(cat '$APP_DIR/netns')\" = \"\$(ip netns exec $NS_NAME readlink /proc/self/ns/net)\""
+expect "script launched via an interpreter with an argument" "$HELPER" run --wait --bin "$APP_BIN" --interp "/bin/sh -e"
+ARGS_BIN="$APP_DIR/args.sh"
+cat <<'APP' > "$ARGS_BIN"
+#!/bin/sh
+printf '%s\n' "$#" "$1" "$PWD" > "$(dirname "$0")/args"
+APP
+chmod +x "$ARGS_BIN"
+expect "app launched with arguments and a working directory" "$HELPER" run --wait --bin "$ARGS_BIN" --arg "two words" --arg "--flag" --cwd /tmp
+expect "argument with a space kept as one argv entry" sh -c "test \"\$(cat '$APP_DIR/args')\" = \"\$(printf '2\ntwo words\n/tmp')\""
+expect "app launched into its own cgroup" "$HELPER" run --wait --cgroup smoke-app --bin "$APP_BIN"
+expect "per-app traffic counters reported" sh -c "'$HELPER' app-traffic | grep -q '\"app_id\":\"smoke-app\"'"
+
+echo "[sillyvpn] Disabling..."
+"$HELPER" disable
+
+expect_not "namespace removed" sh -c "ip netns list | grep -qw $NS_NAME"
+expect_not "wireguard interface removed" ip link show "$IFNAME"
+expect_not "fwmark rule removed" sh -c "ip rule show | grep -q 'fwmark $FWMARK lookup $TABLE_ID'"
+expect_not "table $TABLE_ID emptied" sh -c "ip route show table $TABLE_ID | grep -q ."
+expect_not "masquerade rule removed" iptables -t nat -C POSTROUTING -s 10.200.0.0/24 -o "$IFNAME" -j MASQUERADE
+expect_not "kill switch DROP rule removed" iptables -C FORWARD -s 10.200.0.0/24 ! -o "$IFNAME" -j DROP
+expect "IPv4 forwarding restored" test "$(cat /proc/sys/net/ipv4/ip_forward)" = "$IP_FORWARD_BEFORE"
+expect "IPv6 forwarding restored" test "$(cat /proc/sys/net/ipv6/conf/all/forwarding)" = "$IP6_FORWARD_BEFORE"
+expect_not "IPv6 fwmark rule removed" sh -c "ip -6 rule show | grep -q 'fwmark $FWMARK'"
+expect "status reports not connected after disable" sh -c "'$HELPER' status | grep -q '\"interface_up\":false'"
+expect_not "app cgroup removed" test -d /sys/fs/cgroup/sillyvpn/smoke-app
+
+echo ""
+if [[ "$failed" -ne 0 ]]; then
+  echo "Smoke test failed."
+  exit 1
+fi
+echo "Smoke test passed."
@@ -1,269 +1,878 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::fs;
+use std::io;
 use std::io::{Read, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, Output, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 const NS_NAME: &str = "sillyvpn-ns";
 const VETH_HOST: &str = "svpn0";
 const VETH_NS: &str = "svpn1";
-const VETH_HOST_IP: &str = "10.200.0.1/24";
-const VETH_NS_IP: &str = "10.200.0.2/24";
-const VETH_SUBNET: &str = "10.200.0.0/24";
-const TABLE_ID: &str = "51820";
-const FWMARK: &str = "0x51";
+const DEFAULT_VETH_SUBNET: u32 = 0x0ac8_0000;
+const VETH6_PREFIX: &str = "fd00:200::";
+const SUBNET_PICK_ATTEMPTS: usize = 64;
+const DEFAULT_TABLE_ID: u32 = 51820;
+const DEFAULT_FWMARK: u32 = 0x51;
+const FWMARK_RULE_PRIORITY: &str = "31100";
+const BYPASS_RULE_PRIORITY: &str = "31000";
+const KILLSWITCH_METRIC: &str = "4294967295";
+const MTU_MIN: u32 = 1280;
+const MTU_MAX: u32 = 1420;
+const PATH_MTU_MAX: u32 = 1500;
 const STATE_DIR: &str = "/run/sillyvpn";
-const STATE_FILE: &str = "/run/sillyvpn/state.json";
+const FALLBACK_STATE_DIR: &str = "/dev/shm/sillyvpn";
+const STATE_FILE: &str = "state.json";
+const IPV4_FORWARD: &str = "/proc/sys/net/ipv4/ip_forward";
+const IPV6_FORWARD: &str = "/proc/sys/net/ipv6/conf/all/forwarding";
+const LAST_OP_FILE: &str = "last-op.json";
+const LAST_OP_STDERR_MAX: usize = 2000;
+const RECORDED_OPERATIONS: [&str; 4] = ["enable", "disable", "run", "wipe-secrets"];
 const NETNS_ETC_DIR: &str = "/etc/netns/sillyvpn-ns";
+const NETNS_RUN_DIR: &str = "/var/run/netns";
+const RESOLVED_SOCKET: &str = "/run/systemd/resolve/io.systemd.Resolve";
+const DNSMASQ_PID_FILE: &str = "dnsmasq.pid";
+const DIAGNOSTIC_TOOLS: [&str; 7] = ["dig", "nslookup", "curl", "ping", "traceroute", "ip", "ss"];
+const CURL_WRITE_FLAGS: [&str; 8] = [
+  "-o",
+  "--output",
+  "-O",
+  "--remote-name",
+  "-T",
+  "--upload-file",
+  "-K",
+  "--config",
+];
+const DIAGNOSTIC_MAX_ARGS: usize = 32;
+const DIAGNOSTIC_TIMEOUT_SECS: u64 = 15;
+const PROBE_EXIT_GRACE_SECS: u64 = 5;
+const DEBUG_FLAG: &str = "--debug";
+const RLIMIT_NAMES: [&str; 4] = ["as", "nproc", "nofile", "cpu"];
+const JOURNAL_DIR: &str = "/run/systemd/journal";
+const KERNEL_LOG_SINCE_SECS: u64 = 600;
+const KERNEL_LOG_MAX_LINES: usize = 100;
+const RATE_LIMIT_MAX_MBIT: u32 = 10_000;
+const TBF_MIN_BURST_BYTES: u64 = 32 * 1024;
+const TBF_LATENCY: &str = "50ms";
+const HANDSHAKE_TIMEOUT_MAX: u64 = 300;
+const HANDSHAKE_TRIGGER_FALLBACK: &str = "1.1.1.1";
+const AMNEZIA_KEYS: [&str; 9] = ["jc", "jmin", "jmax", "s1", "s2", "h1", "h2", "h3", "h4"];
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_PARENT: &str = "sillyvpn";
+const APP_CONNMARK_BASE: u32 = 0x10000;
+const APP_CGROUP_NAME_MAX: usize = 64;
 
-#[derive(Debug)]
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static DEBUG: AtomicBool = AtomicBool::new(false);
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+static MASQUERADE: AtomicBool = AtomicBool::new(true);
+static VETH_SUBNET: AtomicU32 = AtomicU32::new(DEFAULT_VETH_SUBNET);
+static ROUTE_TABLE: AtomicU32 = AtomicU32::new(DEFAULT_TABLE_ID);
+static FWMARK: AtomicU32 = AtomicU32::new(DEFAULT_FWMARK);
+static RANDOMIZE_SUBNET: AtomicBool = AtomicBool::new(false);
+static RATE_LIMIT_MBIT: AtomicU32 = AtomicU32::new(0);
+static HANDSHAKE_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+static KILLSWITCH: AtomicBool = AtomicBool::new(false);
+static NO_NEW_PRIVS: AtomicBool = AtomicBool::new(false);
+static RLIMITS: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+static APP_CGROUP: Mutex<Option<String>> = Mutex::new(None);
+static APP_ARGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static APP_CWD: Mutex<Option<PathBuf>> = Mutex::new(None);
+static APP_LOGFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+static LAST_FAILURE: Mutex<Option<FailedCommand>> = Mutex::new(None);
+
+struct FailedCommand {
+  command: String,
+  exit_code: Option<i32>,
+  stderr: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum NamespaceDns {
+  Tunnel,
+  Host,
+  Resolved,
+}
+
+enum ConfigSource {
+  File(PathBuf),
+  Stdin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 struct HelperState {
   wg_ifname: String,
   config_path: String,
   temp_config: String,
   ip_forward_prev: String,
+  ip6_forward_prev: String,
+  #[serde(deserialize_with = "deserialize_bypass")]
+  bypass: Vec<String>,
+  masquerade: bool,
+  external_routes: bool,
+  ipv6: bool,
+  resolved_scope: bool,
+  veth_subnet: String,
+  table: u32,
+  fwmark: u32,
+  killswitch: bool,
+  amnezia: bool,
+}
+
+impl Default for HelperState {
+  fn default() -> Self {
+    Self {
+      wg_ifname: "wg0".to_string(),
+      config_path: String::new(),
+      temp_config: String::new(),
+      ip_forward_prev: "0".to_string(),
+      ip6_forward_prev: String::new(),
+      bypass: Vec::new(),
+      masquerade: true,
+      external_routes: false,
+      ipv6: false,
+      resolved_scope: false,
+      veth_subnet: String::new(),
+      table: DEFAULT_TABLE_ID,
+      fwmark: DEFAULT_FWMARK,
+      killswitch: false,
+      amnezia: false,
+    }
+  }
 }
 
 fn main() {
-  if let Err(err) = run() {
+  DEBUG.store(
+    std::env::args().nth(1).as_deref() == Some(DEBUG_FLAG),
+    Ordering::Relaxed,
+  );
+  let result = run();
+  record_last_operation(&result);
+  if let Err(err) = result {
     eprintln!("sillyvpn-helper error: {err}");
     std::process::exit(1);
   }
 }
 
+fn record_last_operation(result: &Result<(), String>) {
+  let Some(operation) = std::env::args().skip(1).find(|arg| arg != DEBUG_FLAG) else {
+    return;
+  };
+  if dry_run() || !RECORDED_OPERATIONS.contains(&operation.as_str()) {
+    return;
+  }
+  let Ok(dir) = prepare_state_dir() else {
+    return;
+  };
+  let failure = LAST_FAILURE
+    .lock()
+    .ok()
+    .and_then(|mut failure| failure.take())
+    .filter(|_| result.is_err());
+  let timestamp = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default();
+  let record = serde_json::json!({
+    "operation": operation,
+    "ok": result.is_ok(),
+    "error": result.as_ref().err(),
+    "failed_command": failure.as_ref().map(|failure| &failure.command),
+    "exit_code": failure.as_ref().and_then(|failure| failure.exit_code),
+    "stderr_tail": failure.as_ref().map(|failure| tail(&failure.stderr, LAST_OP_STDERR_MAX)),
+    "timestamp": timestamp,
+  });
+  let path = dir.join(LAST_OP_FILE);
+  if fs::write(&path, record.to_string()).is_ok() {
+    let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o600));
+  }
+}
+
+fn print_last_operation() -> Result<(), String> {
+  let recorded = state_dir_candidates()
+    .into_iter()
+    .filter(|dir| is_root_owned_dir(dir))
+    .map(|dir| dir.join(LAST_OP_FILE))
+    .find(|path| path.exists());
+  match recorded {
+    Some(path) => println!("{}", fs::read_to_string(path).map_err(|e| e.to_string())?.trim()),
+    None => println!("null"),
+  }
+  Ok(())
+}
+
+fn tail(text: &str, max: usize) -> &str {
+  let text = text.trim_end();
+  let mut start = text.len().saturating_sub(max);
+  while !text.is_char_boundary(start) {
+    start += 1;
+  }
+  &text[start..]
+}
+
 fn run() -> Result<(), String> {
-  let mut args = std::env::args().skip(1);
+  let mut args = std::env::args().skip(1).skip_while(|arg| arg == DEBUG_FLAG);
   let cmd = args.next().ok_or("missing command")?;
   match cmd.as_str() {
     "enable" => {
       let mut config = None;
+      let mut config_stdin = false;
       let mut ifname = None;
+      let mut bypass = Vec::new();
+      let mut dns = NamespaceDns::Tunnel;
+      let mut auto_mtu = false;
+      let mut external_routes = false;
+      let mut resolv_options = None;
+      let mut explicit_subnet = false;
       while let Some(arg) = args.next() {
         match arg.as_str() {
           "--config" => config = args.next(),
+          "--config-stdin" => config_stdin = true,
           "--ifname" => ifname = args.next(),
+          "--dry-run" => DRY_RUN.store(true, Ordering::Relaxed),
+          "--bypass" => {
+            let route = args.next().ok_or("--bypass missing value")?;
+            if !is_ipv4_cidr(&route) && !is_ipv6_cidr(&route) {
+              return Err(format!("invalid bypass subnet: {route}"));
+            }
+            bypass.push(route);
+          }
+          "--dns-mode" => match args.next().as_deref() {
+            Some("host") => dns = NamespaceDns::Host,
+            Some("tunnel") => dns = NamespaceDns::Tunnel,
+            Some("resolved") => dns = NamespaceDns::Resolved,
+            _ => return Err("--dns-mode expects host, tunnel or resolved".into()),
+          },
+          "--auto-mtu" => auto_mtu = true,
+          "--external-routes" => external_routes = true,
+          "--no-masquerade" => MASQUERADE.store(false, Ordering::Relaxed),
+          "--killswitch" => KILLSWITCH.store(true, Ordering::Relaxed),
+          "--randomize-subnet" => RANDOMIZE_SUBNET.store(true, Ordering::Relaxed),
+          "--subnet" => {
+            let subnet = parse_subnet_arg(&args.next().ok_or("--subnet missing value")?)?;
+            VETH_SUBNET.store(subnet, Ordering::Relaxed);
+            explicit_subnet = true;
+          }
+          "--table" => {
+            let table = parse_table_arg(&args.next().ok_or("--table missing value")?)?;
+            ROUTE_TABLE.store(table, Ordering::Relaxed);
+          }
+          "--fwmark" => {
+            let mark = parse_fwmark_arg(&args.next().ok_or("--fwmark missing value")?)?;
+            FWMARK.store(mark, Ordering::Relaxed);
+          }
+          "--handshake-timeout" => {
+            let secs = args
+              .next()
+              .and_then(|value| value.parse::<u64>().ok())
+              .filter(|secs| (1..=HANDSHAKE_TIMEOUT_MAX).contains(secs))
+              .ok_or(format!("--handshake-timeout expects 1-{HANDSHAKE_TIMEOUT_MAX} seconds"))?;
+            HANDSHAKE_TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+          }
+          "--rate-limit" => {
+            RATE_LIMIT_MBIT.store(parse_rate_limit(args.next())?, Ordering::Relaxed);
+          }
+          "--resolv-options" => {
+            let options = args.next().ok_or("--resolv-options missing value")?;
+            if !is_valid_resolv_options(&options) {
+              return Err(format!("invalid resolv.conf options: {options}"));
+            }
+            resolv_options = Some(options);
+          }
           _ => return Err(format!("unknown argument: {arg}")),
         }
       }
-      let config = config.ok_or("--config missing")?;
+      if external_routes && config_stdin {
+        return Err("--external-routes requires --config".into());
+      }
+      if external_routes && KILLSWITCH.load(Ordering::Relaxed) {
+        return Err("--killswitch is not supported with --external-routes".into());
+      }
+      if explicit_subnet && RANDOMIZE_SUBNET.load(Ordering::Relaxed) {
+        return Err("--subnet and --randomize-subnet are exclusive".into());
+      }
+      let source = match (config, config_stdin) {
+        (Some(_), true) => return Err("--config and --config-stdin are exclusive".into()),
+        (Some(path), false) => ConfigSource::File(PathBuf::from(path)),
+        (None, true) => ConfigSource::Stdin,
+        (None, false) => return Err("--config missing".into()),
+      };
       let ifname = ifname.ok_or("--ifname missing")?;
-      enable(Path::new(&config), &ifname)
+      enable(
+        &source,
+        &ifname,
+        &bypass,
+        dns,
+        resolv_options.as_deref(),
+        auto_mtu,
+        external_routes,
+      )
     }
     "disable" => disable(),
     "run" => {
-      let mut bins: Vec<String> = Vec::new();
-      let mut envs: Vec<(String, String)> = Vec::new();
+      let mut bins: Vec<(String, Option<String>)> = Vec::new();
+      let mut env_pairs: Vec<String> = Vec::new();
+      let mut extra_keys: Vec<String> = Vec::new();
+      let mut terminal: Option<String> = None;
+      let mut wait = false;
+      let mut capture = false;
       while let Some(arg) = args.next() {
         match arg.as_str() {
+          "--wait" => wait = true,
+          "--capture" => capture = true,
+          "--terminal" => terminal = Some(args.next().ok_or("--terminal missing value")?),
           "--bin" => {
             if let Some(value) = args.next() {
-              bins.push(value);
+              bins.push((value, None));
             } else {
               return Err("--bin missing value".into());
             }
           }
+          "--interp" => {
+            let value = args.next().ok_or("--interp missing value")?;
+            let last = bins.last_mut().ok_or("--interp must follow --bin")?;
+            last.1 = Some(value);
+          }
+          "--arg" => {
+            let value = args.next().ok_or("--arg missing value")?;
+            APP_ARGS.lock().map_err(|e| e.to_string())?.push(value);
+          }
+          "--cwd" => {
+            let dir = PathBuf::from(args.next().ok_or("--cwd missing value")?);
+            if !dir.is_absolute() || !dir.is_dir() {
+              return Err(format!("working directory does not exist: {}", dir.display()));
+            }
+            *APP_CWD.lock().map_err(|e| e.to_string())? = Some(dir);
+          }
+          "--logfile" => {
+            let path = PathBuf::from(args.next().ok_or("--logfile missing value")?);
+            if !path.is_absolute() {
+              return Err("--logfile must be an absolute path".into());
+            }
+            *APP_LOGFILE.lock().map_err(|e| e.to_string())? = Some(path);
+          }
           "--env" => {
-            let pair = args.next().ok_or("--env missing value")?;
-            if let Some((key, value)) = parse_env_pair(&pair)? {
-              envs.push((key, value));
+            env_pairs.push(args.next().ok_or("--env missing value")?);
+          }
+          "--allow-env" => {
+            let key = args.next().ok_or("--allow-env missing value")?;
+            if !is_safe_extra_env_key(&key) {
+              return Err(format!("env key not allowed: {key}"));
             }
+            extra_keys.push(key);
+          }
+          "--no-new-privs" => NO_NEW_PRIVS.store(true, Ordering::Relaxed),
+          "--rlimit" => {
+            let limit = parse_rlimit(&args.next().ok_or("--rlimit missing value")?)?;
+            RLIMITS.lock().map_err(|e| e.to_string())?.push(limit);
+          }
+          "--cgroup" => {
+            let name = args.next().ok_or("--cgroup missing value")?;
+            if !is_valid_cgroup_name(&name) {
+              return Err(format!("invalid cgroup name: {name}"));
+            }
+            *APP_CGROUP.lock().map_err(|e| e.to_string())? = Some(name);
           }
           _ => return Err(format!("unknown argument: {arg}")),
         }
       }
+      let mut envs: Vec<(String, String)> = Vec::new();
+      for pair in &env_pairs {
+        if let Some((key, value)) = parse_env_pair(pair, &extra_keys)? {
+          envs.push((key, value));
+        }
+      }
       if bins.is_empty() {
         return Err("--bin missing".into());
       }
-      for bin in bins {
-        run_in_namespace(Path::new(&bin), &envs)?;
+      if wait {
+        if bins.len() != 1 {
+          return Err("--wait accepts a single --bin".into());
+        }
+        let (bin, interp) = &bins[0];
+        let code = run_in_namespace_wait(
+          Path::new(bin),
+          interp.as_deref(),
+          terminal.as_deref(),
+          &envs,
+        )?;
+        std::process::exit(code);
+      }
+      if capture {
+        if bins.len() != 1 || terminal.is_some() {
+          return Err("--capture accepts a single --bin without --terminal".into());
+        }
+        let (bin, interp) = &bins[0];
+        return run_in_namespace_captured(Path::new(bin), interp.as_deref(), &envs);
+      }
+      for (bin, interp) in bins {
+        let pid = run_in_namespace(
+          Path::new(&bin),
+          interp.as_deref(),
+          terminal.as_deref(),
+          &envs,
+        )?;
+        println!("{pid}");
+      }
+      Ok(())
+    }
+    "shape" => {
+      let mut rate = None;
+      let mut clear = false;
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--rate" => rate = Some(parse_rate_limit(args.next())?),
+          "--clear" => clear = true,
+          "--dry-run" => DRY_RUN.store(true, Ordering::Relaxed),
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      if !dry_run() {
+        read_state(&state_dir()).map_err(|_| "VPN is not enabled".to_string())?;
+      }
+      match (rate, clear) {
+        (Some(rate), false) => apply_rate_limit(rate),
+        (None, true) => clear_rate_limit(),
+        _ => Err("shape expects --rate MBPS or --clear".into()),
+      }
+    }
+    "probe-mtu" => {
+      let mut ifname = None;
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--ifname" => ifname = args.next(),
+          "--dry-run" => DRY_RUN.store(true, Ordering::Relaxed),
+          "--timeout" => set_timeout(args.next())?,
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      let ifname = ifname.ok_or("--ifname missing")?;
+      if !is_valid_ifname(&ifname) {
+        return Err(format!("invalid interface name: {ifname}"));
+      }
+      let amnezia = read_state(&state_dir()).is_ok_and(|state| state.amnezia);
+      probe_mtu(&ifname, amnezia).map(|_| ())
+    }
+    "wipe-secrets" => wipe_secrets(),
+    "purge-namespaces" => {
+      for arg in args {
+        match arg.as_str() {
+          "--dry-run" => DRY_RUN.store(true, Ordering::Relaxed),
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      purge_namespaces()
+    }
+    "diag" => {
+      let mut tool = None;
+      let mut tool_args: Vec<String> = Vec::new();
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--tool" => tool = args.next(),
+          "--arg" => tool_args.push(args.next().ok_or("--arg missing value")?),
+          "--timeout" => set_timeout(args.next())?,
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      let tool = tool.ok_or("--tool missing")?;
+      run_diagnostic(&tool, &tool_args)
+    }
+    "conns" => {
+      parse_probe_args(args)?;
+      list_connections()
+    }
+    "peers" => {
+      parse_probe_args(args)?;
+      list_peers()
+    }
+    "status" => {
+      parse_probe_args(args)?;
+      print_tunnel_status()
+    }
+    "stats" => {
+      parse_probe_args(args)?;
+      print_transfer_stats()
+    }
+    "app-traffic" => {
+      parse_probe_args(args)?;
+      list_app_traffic()
+    }
+    "check-conflicts" => {
+      parse_probe_args(args)?;
+      check_conflicts()
+    }
+    "plan-network" => {
+      let mut subnet = None;
+      let mut table = None;
+      let mut fwmark = None;
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--subnet" => subnet = args.next(),
+          "--table" => table = args.next(),
+          "--fwmark" => fwmark = args.next(),
+          "--timeout" => set_timeout(args.next())?,
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      let network = match subnet {
+        Some(subnet) => parse_subnet_arg(&subnet)?,
+        None => DEFAULT_VETH_SUBNET,
+      };
+      let table_id = match table {
+        Some(table) => parse_table_arg(&table)?,
+        None => DEFAULT_TABLE_ID,
+      };
+      let mark = match fwmark {
+        Some(fwmark) => parse_fwmark_arg(&fwmark)?,
+        None => DEFAULT_FWMARK,
+      };
+      plan_network(network, 24, table_id, mark)
+    }
+    "check-nat" => {
+      let mut host = None;
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--host" => host = args.next(),
+          "--timeout" => set_timeout(args.next())?,
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      check_nat(&host.ok_or("--host missing")?)
+    }
+    "kernel-log" => {
+      let mut ifname = None;
+      let mut since = KERNEL_LOG_SINCE_SECS;
+      while let Some(arg) = args.next() {
+        match arg.as_str() {
+          "--ifname" => ifname = args.next(),
+          "--since" => {
+            since = args
+              .next()
+              .and_then(|value| value.parse::<u64>().ok())
+              .filter(|value| *value > 0)
+              .ok_or("--since expects a positive number of seconds")?;
+          }
+          "--timeout" => set_timeout(args.next())?,
+          _ => return Err(format!("unknown argument: {arg}")),
+        }
+      }
+      let ifname = ifname.ok_or("--ifname missing")?;
+      if !is_valid_ifname(&ifname) {
+        return Err(format!("invalid interface name: {ifname}"));
       }
+      print_kernel_log(&ifname, since)
+    }
+    "last-op" => {
+      parse_probe_args(args)?;
+      print_last_operation()
+    }
+    "version" => {
+      println!("{}", env!("CARGO_PKG_VERSION"));
       Ok(())
     }
     _ => Err(format!("unknown command: {cmd}")),
   }
 }
 
-fn enable(config_path: &Path, _ifname: &str) -> Result<(), String> {
-  if !config_path.exists() {
-    return Err("config does not exist".into());
+fn enable(
+  source: &ConfigSource,
+  ifname: &str,
+  bypass: &[String],
+  dns: NamespaceDns,
+  resolv_options: Option<&str>,
+  auto_mtu: bool,
+  external_routes: bool,
+) -> Result<(), String> {
+  if let ConfigSource::File(path) = source {
+    if !path.exists() {
+      return Err("config does not exist".into());
+    }
+  }
+  if !is_valid_ifname(ifname) {
+    return Err(format!("invalid interface name: {ifname}"));
+  }
+  let resolved_scope = dns == NamespaceDns::Resolved && !external_routes && resolved_available();
+  let host_dns = dns == NamespaceDns::Host || resolved_scope;
+
+  let state_dir = prepare_state_dir()?;
+  let content = read_config(source)?;
+  let (sanitized, dns_servers) = sanitize_config(&content, !external_routes)?;
+  let amnezia = uses_amnezia(&sanitized);
+  if let Err(err) = check_dependencies(host_dns, amnezia) {
+    if !dry_run() {
+      return Err(err);
+    }
+    eprintln!("{err}");
+  }
+  let backend = match wireguard_backend(amnezia) {
+    Ok(backend) => backend,
+    Err(err) if dry_run() => {
+      eprintln!("{err}");
+      WgBackend::Kernel
+    }
+    Err(err) => return Err(err),
+  };
+  if let WgBackend::Userspace(implementation) = &backend {
+    std::env::set_var("WG_QUICK_USERSPACE_IMPLEMENTATION", implementation);
+  }
+  let ipv6 = !external_routes && carries_ipv6(&sanitized);
+  if RANDOMIZE_SUBNET.load(Ordering::Relaxed) {
+    let subnet = pick_random_subnet(&sanitized, bypass)?;
+    VETH_SUBNET.store(subnet, Ordering::Relaxed);
   }
+  if !MASQUERADE.load(Ordering::Relaxed) && !subnet_routed_by_tunnel(&sanitized) {
+    eprintln!(
+      "sillyvpn-helper: MASQUERADE disabled but {} is not inside the tunnel Address, replies will only arrive if the peer routes it",
+      veth_subnet()
+    );
+  }
+  let (bypass_routes, skipped): (Vec<String>, Vec<String>) = bypass
+    .iter()
+    .cloned()
+    .partition(|route| ipv6 || !is_ipv6_cidr(route));
+  for route in &skipped {
+    eprintln!("sillyvpn-helper: skipping IPv6 bypass {route}, tunnel does not carry IPv6");
+  }
+  let bypass = bypass_routes.as_slice();
+  let (config_path, temp_config) = match source {
+    ConfigSource::File(path) => {
+      let temp_config = state_dir.join(format!("{ifname}.conf"));
+      write_private_file(&temp_config, &sanitized)?;
+      (path.to_string_lossy().to_string(), Some(temp_config))
+    }
+    ConfigSource::Stdin => ("stdin".to_string(), None),
+  };
+  bring_up(&BringUp {
+    state_dir: &state_dir,
+    ifname,
+    bypass,
+    sanitized: &sanitized,
+    dns_servers: &dns_servers,
+    host_dns,
+    resolv_options,
+    resolved_scope,
+    auto_mtu,
+    external_routes,
+    ipv6,
+    backend: &backend,
+    config_path: &config_path,
+    temp_config: temp_config.as_deref(),
+    amnezia,
+  })
+}
+
+struct BringUp<'a> {
+  state_dir: &'a Path,
+  ifname: &'a str,
+  bypass: &'a [String],
+  sanitized: &'a str,
+  dns_servers: &'a [String],
+  host_dns: bool,
+  resolv_options: Option<&'a str>,
+  resolved_scope: bool,
+  auto_mtu: bool,
+  external_routes: bool,
+  ipv6: bool,
+  backend: &'a WgBackend,
+  config_path: &'a str,
+  temp_config: Option<&'a Path>,
+  amnezia: bool,
+}
 
-  fs::create_dir_all(STATE_DIR).map_err(|e| e.to_string())?;
-  let temp_config = Path::new(STATE_DIR).join("wg-temp.conf");
-  let (temp_config, dns_servers) = sanitize_config(config_path, &temp_config)?;
-  let ifname = temp_config
-    .file_stem()
-    .and_then(|s| s.to_str())
-    .unwrap_or("wg-temp")
-    .to_string();
+fn bring_up(plan: &BringUp) -> Result<(), String> {
+  let BringUp {
+    state_dir,
+    ifname,
+    bypass,
+    sanitized,
+    dns_servers,
+    host_dns,
+    resolv_options,
+    resolved_scope,
+    auto_mtu,
+    external_routes,
+    ipv6,
+    backend,
+    config_path,
+    temp_config,
+    amnezia,
+  } = *plan;
 
-  let ip_forward_prev = read_ip_forward()?;
-  write_ip_forward("1")?;
+  let ip_forward_prev = read_ip_forward(IPV4_FORWARD)?;
+  write_ip_forward(IPV4_FORWARD, "1")?;
+  let ip6_forward_prev = if ipv6 {
+    let prev = read_ip_forward(IPV6_FORWARD)?;
+    if let Err(err) = write_ip_forward(IPV6_FORWARD, "1") {
+      let _ = write_ip_forward(IPV4_FORWARD, &ip_forward_prev);
+      return Err(err);
+    }
+    prev
+  } else {
+    String::new()
+  };
 
-  let _ = run_cmd("ip", &["link", "del", VETH_HOST]);
-  let _ = run_cmd("ip", &["netns", "del", NS_NAME]);
+  best_effort(|| {
+    let _ = run_cmd("ip", &["link", "del", veth_host()]);
+    let _ = run_cmd("ip", &["netns", "del", ns_name()]);
+  });
 
   let result = (|| -> Result<(), String> {
-    run_cmd("ip", &["netns", "add", NS_NAME])?;
-    setup_dns_for_namespace(&dns_servers)?;
+    run_cmd("ip", &["netns", "add", ns_name()])?;
+    let (namespace_dns, unreachable_dns): (Vec<String>, Vec<String>) = dns_servers
+      .iter()
+      .cloned()
+      .partition(|server| ipv6 || external_routes || !server.contains(':'));
+    for server in &unreachable_dns {
+      eprintln!("sillyvpn-helper: skipping IPv6 nameserver {server}, tunnel does not carry IPv6");
+    }
+    setup_dns_for_namespace(&namespace_dns, host_dns, resolv_options)?;
     run_cmd(
       "ip",
-      &["link", "add", VETH_HOST, "type", "veth", "peer", "name", VETH_NS],
+      &["link", "add", veth_host(), "type", "veth", "peer", "name", veth_ns()],
     )?;
-    run_cmd("ip", &["link", "set", VETH_NS, "netns", NS_NAME])?;
-    run_cmd("ip", &["addr", "add", VETH_HOST_IP, "dev", VETH_HOST])?;
-    run_cmd("ip", &["link", "set", VETH_HOST, "up"])?;
+    run_cmd("ip", &["link", "set", veth_ns(), "netns", ns_name()])?;
+    run_cmd("ip", &["addr", "add", &veth_cidr(1), "dev", veth_host()])?;
+    run_cmd("ip", &["link", "set", veth_host(), "up"])?;
+    if host_dns {
+      start_dns_forwarder(state_dir)?;
+    }
     run_cmd(
       "ip",
-      &["netns", "exec", NS_NAME, "ip", "addr", "add", VETH_NS_IP, "dev", VETH_NS],
+      &["netns", "exec", ns_name(), "ip", "addr", "add", &veth_cidr(2), "dev", veth_ns()],
     )?;
     run_cmd(
       "ip",
-      &["netns", "exec", NS_NAME, "ip", "link", "set", VETH_NS, "up"],
+      &["netns", "exec", ns_name(), "ip", "link", "set", veth_ns(), "up"],
     )?;
     run_cmd(
       "ip",
       &[
         "netns",
         "exec",
-        NS_NAME,
+        ns_name(),
         "ip",
         "route",
         "add",
         "default",
         "via",
-        "10.200.0.1",
+        &veth_addr(1).to_string(),
       ],
     )?;
+    if ipv6 {
+      setup_veth_v6()?;
+    }
 
-    run_cmd("wg-quick", &["up", temp_config.to_str().unwrap()])?;
+    if external_routes {
+      let temp_config = temp_config.ok_or("--external-routes requires --config")?;
+      run_cmd(
+        "ip",
+        &["netns", "exec", ns_name(), wg_quick_tool(amnezia), "up", temp_config.to_str().unwrap()],
+      )?;
+      for route in bypass {
+        run_cmd(
+          "ip",
+          &["netns", "exec", ns_name(), "ip", "route", "add", route, "via", &veth_addr(1).to_string()],
+        )?;
+      }
+      apply_external_forwarding(true)?;
+    } else {
+      match temp_config {
+        Some(temp_config) => {
+          run_cmd(wg_quick_tool(amnezia), &["up", temp_config.to_str().unwrap()])?
+        }
+        None => bring_up_interface(ifname, sanitized, backend, amnezia)?,
+      }
+      if auto_mtu {
+        if let Err(err) = probe_mtu(ifname, amnezia) {
+          eprintln!("sillyvpn-helper: MTU probe failed, keeping default: {err}");
+        }
+      }
+      setup_host_routing(ifname, bypass)?;
+      if ipv6 {
+        setup_host_routing_v6(ifname)?;
+      }
+      if KILLSWITCH.load(Ordering::Relaxed) {
+        apply_killswitch(ifname, bypass, ipv6, true)?;
+      }
+      if resolved_scope {
+        setup_resolved_scope(ifname, dns_servers)?;
+      }
+    }
 
-    run_cmd("ip", &["rule", "add", "fwmark", FWMARK, "table", TABLE_ID])?;
-    run_cmd(
-      "ip",
-      &[
-        "route",
-        "add",
-        "default",
-        "dev",
-        &ifname,
-        "table",
-        TABLE_ID,
-      ],
-    )?;
-    run_cmd(
-      "iptables",
-      &[
-        "-t",
-        "mangle",
-        "-A",
-        "PREROUTING",
-        "-i",
-        VETH_HOST,
-        "-j",
-        "MARK",
-        "--set-mark",
-        FWMARK,
-      ],
-    )?;
-    run_cmd(
-      "iptables",
-      &[
-        "-A",
-        "FORWARD",
-        "-i",
-        VETH_HOST,
-        "-o",
-        &ifname,
-        "-j",
-        "ACCEPT",
-      ],
-    )?;
-    run_cmd(
-      "iptables",
-      &[
-        "-A",
-        "FORWARD",
-        "-i",
-        &ifname,
-        "-o",
-        VETH_HOST,
-        "-j",
-        "ACCEPT",
-      ],
-    )?;
-    run_cmd(
-      "iptables",
-      &[
-        "-t",
-        "nat",
-        "-A",
-        "POSTROUTING",
-        "-s",
-        VETH_SUBNET,
-        "-o",
-        &ifname,
-        "-j",
-        "MASQUERADE",
-      ],
-    )?;
+    let handshake_timeout = HANDSHAKE_TIMEOUT_SECS.load(Ordering::Relaxed);
+    if handshake_timeout > 0 {
+      wait_for_handshake(ifname, sanitized, external_routes, handshake_timeout, amnezia)?;
+    }
 
     let state = HelperState {
       wg_ifname: ifname.to_string(),
-      config_path: config_path.to_string_lossy().to_string(),
-      temp_config: temp_config.to_string_lossy().to_string(),
+      config_path: config_path.to_string(),
+      temp_config: temp_config
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_default(),
       ip_forward_prev: ip_forward_prev.clone(),
+      ip6_forward_prev: ip6_forward_prev.clone(),
+      bypass: bypass.to_vec(),
+      masquerade: MASQUERADE.load(Ordering::Relaxed),
+      external_routes,
+      ipv6,
+      resolved_scope,
+      veth_subnet: veth_subnet(),
+      table: ROUTE_TABLE.load(Ordering::Relaxed),
+      fwmark: FWMARK.load(Ordering::Relaxed),
+      killswitch: KILLSWITCH.load(Ordering::Relaxed),
+      amnezia,
     };
-    write_state(&state)?;
+    write_state(state_dir, &state)?;
+    let rate = RATE_LIMIT_MBIT.load(Ordering::Relaxed);
+    if rate > 0 {
+      if let Err(err) = apply_rate_limit(rate) {
+        eprintln!("sillyvpn-helper: bandwidth limit not applied: {err}");
+      }
+    }
     Ok(())
   })();
 
   if let Err(err) = result {
-    cleanup_best_effort();
-    let _ = cleanup_dns_for_namespace();
-    let _ = write_ip_forward(&ip_forward_prev);
-    let _ = run_cmd(
-      "iptables",
-      &[
-        "-D",
-        "FORWARD",
-        "-i",
-        VETH_HOST,
-        "-o",
-        &ifname,
-        "-j",
-        "ACCEPT",
-      ],
-    );
-    let _ = run_cmd(
-      "iptables",
-      &[
-        "-D",
-        "FORWARD",
-        "-i",
-        &ifname,
-        "-o",
-        VETH_HOST,
-        "-j",
-        "ACCEPT",
-      ],
-    );
-    let _ = run_cmd(
-      "iptables",
-      &[
-        "-t",
-        "nat",
-        "-D",
-        "POSTROUTING",
-        "-s",
-        VETH_SUBNET,
-        "-o",
-        &ifname,
-        "-j",
-        "MASQUERADE",
-      ],
-    );
-    let _ = run_cmd("wg-quick", &["down", temp_config.to_str().unwrap()]);
+    best_effort(|| {
+      if external_routes {
+        let _ = apply_external_forwarding(false);
+      } else {
+        if resolved_scope {
+          let _ = run_cmd("resolvectl", &["revert", ifname]);
+        }
+        teardown_host_routing(ifname, bypass);
+        if ipv6 {
+          teardown_host_routing_v6(ifname);
+        }
+      }
+      cleanup_best_effort();
+      if KILLSWITCH.load(Ordering::Relaxed) {
+        let _ = apply_killswitch(ifname, bypass, ipv6, false);
+      }
+      let _ = cleanup_dns_for_namespace();
+      let _ = write_ip_forward(IPV4_FORWARD, &ip_forward_prev);
+      if !ip6_forward_prev.is_empty() {
+        let _ = write_ip_forward(IPV6_FORWARD, &ip6_forward_prev);
+      }
+      match &temp_config {
+        Some(temp_config) => {
+          let _ = run_cmd(wg_quick_tool(amnezia), &["down", temp_config.to_str().unwrap()]);
+          let _ = shred_file(temp_config);
+        }
+        None => {
+          let _ = run_cmd("ip", &["link", "del", ifname]);
+        }
+      }
+    });
     return Err(err);
   }
 
@@ -271,56 +880,449 @@ fn enable(config_path: &Path, _ifname: &str) -> Result<(), String> {
 }
 
 fn disable() -> Result<(), String> {
-  let state = match read_state() {
+  disable_in(&state_dir())
+}
+
+fn disable_in(state_dir: &Path) -> Result<(), String> {
+  let state = match read_state(state_dir) {
     Ok(state) => state,
     Err(_) => {
       cleanup_best_effort();
       return Ok(());
     }
   };
+  MASQUERADE.store(state.masquerade, Ordering::Relaxed);
+  ROUTE_TABLE.store(state.table, Ordering::Relaxed);
+  FWMARK.store(state.fwmark, Ordering::Relaxed);
+  set_veth_subnet(&state.veth_subnet);
 
-  let _ = run_cmd(
-    "iptables",
-    &[
-      "-t",
-      "mangle",
-      "-D",
-      "PREROUTING",
-      "-i",
-      VETH_HOST,
-      "-j",
-      "MARK",
-      "--set-mark",
-      FWMARK,
-    ],
-  );
-  let _ = run_cmd(
-    "iptables",
-    &[
-      "-D",
-      "FORWARD",
-      "-i",
-      VETH_HOST,
-      "-o",
-      &state.wg_ifname,
-      "-j",
-      "ACCEPT",
-    ],
-  );
-  let _ = run_cmd(
-    "iptables",
-    &[
-      "-D",
-      "FORWARD",
-      "-i",
-      &state.wg_ifname,
-      "-o",
-      VETH_HOST,
-      "-j",
-      "ACCEPT",
-    ],
-  );
-  let _ = run_cmd(
+  best_effort(|| {
+    if state.external_routes {
+      let _ = apply_external_forwarding(false);
+      let _ = run_cmd(
+        "ip",
+        &["netns", "exec", ns_name(), wg_quick_tool(state.amnezia), "down", &state.temp_config],
+      );
+      let _ = shred_file(Path::new(&state.temp_config));
+    } else {
+      if state.resolved_scope {
+        let _ = run_cmd("resolvectl", &["revert", &state.wg_ifname]);
+      }
+      teardown_host_routing(&state.wg_ifname, &state.bypass);
+      if state.ipv6 {
+        teardown_host_routing_v6(&state.wg_ifname);
+      }
+      if state.temp_config.is_empty() {
+        let _ = run_cmd("ip", &["link", "del", &state.wg_ifname]);
+      } else {
+        let _ = run_cmd(wg_quick_tool(state.amnezia), &["down", &state.temp_config]);
+        let _ = shred_file(Path::new(&state.temp_config));
+      }
+      force_remove_interface(&state.wg_ifname, &state.bypass, state.ipv6);
+    }
+
+    let _ = clear_rate_limit();
+    cleanup_best_effort();
+    if state.killswitch {
+      let _ = apply_killswitch(&state.wg_ifname, &state.bypass, state.ipv6, false);
+    }
+    remove_app_cgroups();
+  });
+  let mut errors = Vec::new();
+  if let Err(err) = write_ip_forward(IPV4_FORWARD, &state.ip_forward_prev) {
+    errors.push(format!("{IPV4_FORWARD}: {err}"));
+  }
+  if !state.ip6_forward_prev.is_empty() {
+    if let Err(err) = write_ip_forward(IPV6_FORWARD, &state.ip6_forward_prev) {
+      errors.push(format!("{IPV6_FORWARD}: {err}"));
+    }
+  }
+  if !errors.is_empty() {
+    return Err(errors.join("; "));
+  }
+  let _ = fs::remove_file(state_dir.join(STATE_FILE));
+  Ok(())
+}
+
+fn wait_for_handshake(
+  ifname: &str,
+  config: &str,
+  external_routes: bool,
+  timeout: u64,
+  amnezia: bool,
+) -> Result<(), String> {
+  if dry_run() {
+    println!("wait up to {timeout}s for a handshake on {ifname}");
+    return Ok(());
+  }
+  let target = handshake_trigger_addr(config);
+  let ping = find_in_path("ping");
+  let deadline = Instant::now() + Duration::from_secs(timeout);
+  loop {
+    let handshakes = if external_routes {
+      cmd_stdout(
+        "ip",
+        &["netns", "exec", ns_name(), wg_tool(amnezia), "show", ifname, "latest-handshakes"],
+      )
+    } else {
+      cmd_stdout(wg_tool(amnezia), &["show", ifname, "latest-handshakes"])
+    }
+    .unwrap_or_default();
+    let handshake_done = handshakes
+      .lines()
+      .any(|line| line.split_whitespace().nth(1).is_some_and(|ts| ts != "0"));
+    if handshake_done {
+      return Ok(());
+    }
+    if Instant::now() >= deadline {
+      return Err(format!("handshake timed out after {timeout}s"));
+    }
+    match &ping {
+      Some(ping) => {
+        let _ = Command::new("ip")
+          .args(["netns", "exec", ns_name()])
+          .arg(ping)
+          .args(["-n", "-q", "-c", "1", "-W", "1", &target])
+          .stdin(Stdio::null())
+          .stdout(Stdio::null())
+          .stderr(Stdio::null())
+          .status();
+      }
+      None => std::thread::sleep(Duration::from_secs(1)),
+    }
+  }
+}
+
+fn handshake_trigger_addr(config: &str) -> String {
+  config
+    .lines()
+    .filter_map(|line| line.split_once('='))
+    .filter(|(key, _)| key.trim().eq_ignore_ascii_case("allowedips"))
+    .flat_map(|(_, value)| value.split(','))
+    .find_map(|cidr| parse_ipv4_network(cidr.trim()))
+    .map(|(addr, prefix)| match prefix {
+      0 => HANDSHAKE_TRIGGER_FALLBACK.to_string(),
+      31 | 32 => std::net::Ipv4Addr::from(addr).to_string(),
+      _ => std::net::Ipv4Addr::from((addr & (u32::MAX << (32 - prefix))) + 1).to_string(),
+    })
+    .unwrap_or_else(|| HANDSHAKE_TRIGGER_FALLBACK.to_string())
+}
+
+fn parse_rate_limit(value: Option<String>) -> Result<u32, String> {
+  value
+    .and_then(|value| value.parse::<u32>().ok())
+    .filter(|rate| (1..=RATE_LIMIT_MAX_MBIT).contains(rate))
+    .ok_or(format!("rate limit expects 1-{RATE_LIMIT_MAX_MBIT} Mbit/s"))
+}
+
+fn apply_rate_limit(rate: u32) -> Result<(), String> {
+  if !dry_run() && find_in_path("tc").is_none() {
+    return Err("tc not found, install iproute2".into());
+  }
+  let rate_arg = format!("{rate}mbit");
+  let burst = (u64::from(rate) * 1250).max(TBF_MIN_BURST_BYTES).to_string();
+  let tbf = ["root", "tbf", "rate", &rate_arg, "burst", &burst, "latency", TBF_LATENCY];
+  run_cmd("tc", &[&["qdisc", "replace", "dev", veth_host()][..], &tbf].concat())?;
+  run_cmd(
+    "ip",
+    &[&["netns", "exec", ns_name(), "tc", "qdisc", "replace", "dev", veth_ns()][..], &tbf].concat(),
+  )
+}
+
+fn clear_rate_limit() -> Result<(), String> {
+  if !dry_run() && find_in_path("tc").is_none() {
+    return Err("tc not found, install iproute2".into());
+  }
+  let has_tbf = |output: Result<String, String>| output.is_ok_and(|qdiscs| qdiscs.contains("tbf"));
+  if dry_run() || has_tbf(cmd_stdout("tc", &["qdisc", "show", "dev", veth_host()])) {
+    run_cmd("tc", &["qdisc", "del", "dev", veth_host(), "root"])?;
+  }
+  if dry_run()
+    || has_tbf(cmd_stdout(
+      "ip",
+      &["netns", "exec", ns_name(), "tc", "qdisc", "show", "dev", veth_ns()],
+    ))
+  {
+    run_cmd(
+      "ip",
+      &["netns", "exec", ns_name(), "tc", "qdisc", "del", "dev", veth_ns(), "root"],
+    )?;
+  }
+  Ok(())
+}
+
+fn force_remove_interface(ifname: &str, bypass: &[String], ipv6: bool) -> bool {
+  if dry_run() || !runner().exists(&Path::new("/sys/class/net").join(ifname)) {
+    return false;
+  }
+  eprintln!("sillyvpn-helper: {ifname} is still present after teardown, deleting it");
+  let _ = run_cmd("ip", &["link", "del", ifname]);
+  teardown_host_routing(ifname, bypass);
+  if ipv6 {
+    teardown_host_routing_v6(ifname);
+  }
+  println!("forced-cleanup {ifname}");
+  true
+}
+
+fn sillyvpn_namespaces() -> Vec<String> {
+  let mut names: Vec<String> = fs::read_dir(NETNS_RUN_DIR)
+    .map(|entries| {
+      entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(ns_name()))
+        .collect()
+    })
+    .unwrap_or_default();
+  names.sort();
+  names
+}
+
+fn purge_namespaces() -> Result<(), String> {
+  let active = read_state(&state_dir()).is_ok();
+  let mut removed = Vec::new();
+  for name in sillyvpn_namespaces() {
+    if active && name == ns_name() {
+      continue;
+    }
+    if !dry_run() {
+      let pids = cmd_stdout("ip", &["netns", "pids", &name]).unwrap_or_default();
+      for pid in pids.lines().filter_map(|pid| pid.trim().parse::<i32>().ok()) {
+        unsafe {
+          libc::kill(pid, libc::SIGKILL);
+        }
+      }
+    }
+    if name == ns_name() {
+      cleanup_best_effort();
+    } else {
+      run_cmd("ip", &["netns", "del", &name])?;
+    }
+    removed.push(name);
+  }
+  println!("{}", serde_json::json!(removed));
+  Ok(())
+}
+
+fn shred_file(path: &Path) -> Result<(), String> {
+  if dry_run() {
+    println!("shred {}", path.display());
+    return Ok(());
+  }
+  let len = fs::metadata(path).map_err(|e| e.to_string())?.len();
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .open(path)
+    .map_err(|e| e.to_string())?;
+  file
+    .write_all(&vec![0u8; len as usize])
+    .and_then(|_| file.sync_all())
+    .map_err(|e| e.to_string())?;
+  fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+fn wipe_secrets() -> Result<(), String> {
+  let active = read_state(&state_dir()).ok().map(|state| PathBuf::from(state.temp_config));
+  let mut wiped = Vec::new();
+  for dir in state_dir_candidates() {
+    if !is_root_owned_dir(&dir) {
+      continue;
+    }
+    let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+    for path in entries.flatten().map(|entry| entry.path()) {
+      if path.extension().and_then(|ext| ext.to_str()) != Some("conf") {
+        continue;
+      }
+      if active.as_deref() == Some(path.as_path()) {
+        continue;
+      }
+      shred_file(&path)?;
+      wiped.push(path.to_string_lossy().to_string());
+    }
+  }
+  println!("{}", serde_json::json!(wiped));
+  Ok(())
+}
+
+fn probe_mtu(ifname: &str, amnezia: bool) -> Result<u32, String> {
+  if dry_run() {
+    println!("probe path MTU to the {ifname} endpoint and set mtu within {MTU_MIN}-{MTU_MAX}");
+    return Ok(MTU_MAX);
+  }
+  let endpoint = first_endpoint_host(ifname, amnezia)?;
+  let (ip_header, overhead) = if endpoint.contains(':') { (48, 80) } else { (28, 60) };
+  let fits = |path_mtu: u32| {
+    Command::new("ping")
+      .args(["-M", "do", "-c", "1", "-W", "1", "-s"])
+      .arg((path_mtu - ip_header).to_string())
+      .arg(&endpoint)
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .status()
+      .is_ok_and(|status| status.success())
+  };
+  let mut low = MTU_MIN + overhead;
+  let mut high = PATH_MTU_MAX;
+  if !fits(low) {
+    return Err(format!("{endpoint} does not answer DF pings of {low} bytes"));
+  }
+  while low < high {
+    let mid = low + (high - low).div_ceil(2);
+    if fits(mid) {
+      low = mid;
+    } else {
+      high = mid - 1;
+    }
+  }
+  let mtu = (low - overhead).clamp(MTU_MIN, MTU_MAX);
+  run_cmd("ip", &["link", "set", "mtu", &mtu.to_string(), "dev", ifname])?;
+  println!("mtu {mtu}");
+  Ok(mtu)
+}
+
+fn first_endpoint_host(ifname: &str, amnezia: bool) -> Result<String, String> {
+  let output = cmd_stdout(wg_tool(amnezia), &["show", ifname, "endpoints"])?;
+  output
+    .lines()
+    .filter_map(|line| line.split_whitespace().nth(1))
+    .filter_map(|endpoint| endpoint.rsplit_once(':').map(|(host, _)| host))
+    .map(|host| host.trim_start_matches('[').trim_end_matches(']').to_string())
+    .find(|host| !host.is_empty())
+    .ok_or_else(|| format!("{ifname} has no peer endpoint"))
+}
+
+fn setup_host_routing(ifname: &str, bypass: &[String]) -> Result<(), String> {
+  run_cmd(
+    "ip",
+    &[
+      "rule",
+      "add",
+      "fwmark",
+      &fwmark(),
+      "table",
+      &table_id(),
+      "priority",
+      FWMARK_RULE_PRIORITY,
+    ],
+  )?;
+  for route in bypass {
+    apply_bypass_route(route, true)?;
+  }
+  run_cmd(
+    "ip",
+    &[
+      "route",
+      "add",
+      "default",
+      "dev",
+      ifname,
+      "table",
+      &table_id(),
+    ],
+  )?;
+  run_cmd(
+    "iptables",
+    &[
+      "-t",
+      "mangle",
+      "-A",
+      "PREROUTING",
+      "-i",
+      veth_host(),
+      "-j",
+      "MARK",
+      "--set-mark",
+      &fwmark(),
+    ],
+  )?;
+  run_cmd(
+    "iptables",
+    &[
+      "-A",
+      "FORWARD",
+      "-i",
+      veth_host(),
+      "-o",
+      ifname,
+      "-j",
+      "ACCEPT",
+    ],
+  )?;
+  run_cmd(
+    "iptables",
+    &[
+      "-A",
+      "FORWARD",
+      "-i",
+      ifname,
+      "-o",
+      veth_host(),
+      "-j",
+      "ACCEPT",
+    ],
+  )?;
+  run_nat(
+    "iptables",
+    &[
+      "-t",
+      "nat",
+      "-A",
+      "POSTROUTING",
+      "-s",
+      &veth_subnet(),
+      "-o",
+      ifname,
+      "-j",
+      "MASQUERADE",
+    ],
+  )?;
+  Ok(())
+}
+
+fn teardown_host_routing(ifname: &str, bypass: &[String]) {
+  let _ = run_cmd(
+    "iptables",
+    &[
+      "-t",
+      "mangle",
+      "-D",
+      "PREROUTING",
+      "-i",
+      veth_host(),
+      "-j",
+      "MARK",
+      "--set-mark",
+      &fwmark(),
+    ],
+  );
+  let _ = run_cmd(
+    "iptables",
+    &[
+      "-D",
+      "FORWARD",
+      "-i",
+      veth_host(),
+      "-o",
+      ifname,
+      "-j",
+      "ACCEPT",
+    ],
+  );
+  let _ = run_cmd(
+    "iptables",
+    &[
+      "-D",
+      "FORWARD",
+      "-i",
+      ifname,
+      "-o",
+      veth_host(),
+      "-j",
+      "ACCEPT",
+    ],
+  );
+  let _ = run_nat(
     "iptables",
     &[
       "-t",
@@ -328,124 +1330,1771 @@ fn disable() -> Result<(), String> {
       "-D",
       "POSTROUTING",
       "-s",
-      VETH_SUBNET,
+      &veth_subnet(),
       "-o",
-      &state.wg_ifname,
+      ifname,
       "-j",
       "MASQUERADE",
     ],
   );
-  let _ = run_cmd("ip", &["rule", "del", "fwmark", FWMARK, "table", TABLE_ID]);
+  for route in bypass {
+    let _ = apply_bypass_route(route, false);
+  }
+  let _ = run_cmd("ip", &["rule", "del", "fwmark", &fwmark(), "table", &table_id()]);
   let _ = run_cmd(
     "ip",
-    &["route", "del", "default", "dev", &state.wg_ifname, "table", TABLE_ID],
+    &["route", "del", "default", "dev", ifname, "table", &table_id()],
+  );
+}
+
+fn setup_host_routing_v6(ifname: &str) -> Result<(), String> {
+  run_cmd(
+    "ip",
+    &["-6", "rule", "add", "fwmark", &fwmark(), "table", &table_id(), "priority", FWMARK_RULE_PRIORITY],
+  )?;
+  run_cmd("ip", &["-6", "route", "add", "default", "dev", ifname, "table", &table_id()])?;
+  run_cmd(
+    "ip6tables",
+    &["-t", "mangle", "-A", "PREROUTING", "-i", veth_host(), "-j", "MARK", "--set-mark", &fwmark()],
+  )?;
+  run_cmd("ip6tables", &["-A", "FORWARD", "-i", veth_host(), "-o", ifname, "-j", "ACCEPT"])?;
+  run_cmd("ip6tables", &["-A", "FORWARD", "-i", ifname, "-o", veth_host(), "-j", "ACCEPT"])?;
+  run_nat(
+    "ip6tables",
+    &["-t", "nat", "-A", "POSTROUTING", "-m", "mark", "--mark", &fwmark(), "-o", ifname, "-j", "MASQUERADE"],
+  )?;
+  Ok(())
+}
+
+fn apply_killswitch(ifname: &str, bypass: &[String], ipv6: bool, add: bool) -> Result<(), String> {
+  let (route_op, ipt_op) = if add { ("add", "-I") } else { ("del", "-D") };
+  let table = table_id();
+  let subnet = veth_subnet();
+  let run = |cmd: &str, args: &[&str]| {
+    if add {
+      run_cmd(cmd, args)
+    } else {
+      let _ = run_cmd(cmd, args);
+      Ok(())
+    }
+  };
+  run(
+    "ip",
+    &["route", route_op, "blackhole", "default", "table", &table, "metric", KILLSWITCH_METRIC],
+  )?;
+  run(
+    "iptables",
+    &[ipt_op, "FORWARD", "-s", &subnet, "!", "-o", ifname, "-j", "DROP"],
+  )?;
+  for route in bypass.iter().filter(|route| !is_ipv6_cidr(route)) {
+    run("iptables", &[ipt_op, "FORWARD", "-s", &subnet, "-d", route, "-j", "ACCEPT"])?;
+  }
+  if ipv6 {
+    run(
+      "ip",
+      &["-6", "route", route_op, "blackhole", "default", "table", &table, "metric", KILLSWITCH_METRIC],
+    )?;
+    run(
+      "ip6tables",
+      &[ipt_op, "FORWARD", "-i", veth_host(), "!", "-o", ifname, "-j", "DROP"],
+    )?;
+    for route in bypass.iter().filter(|route| is_ipv6_cidr(route)) {
+      run("ip6tables", &[ipt_op, "FORWARD", "-i", veth_host(), "-d", route, "-j", "ACCEPT"])?;
+    }
+  }
+  Ok(())
+}
+
+fn is_killswitch_route(line: &str) -> bool {
+  line.starts_with("blackhole default") && line.contains(&format!("metric {KILLSWITCH_METRIC}"))
+}
+
+fn veth6_addr(host: u32) -> String {
+  format!("{VETH6_PREFIX}{host}")
+}
+
+fn setup_veth_v6() -> Result<(), String> {
+  run_cmd(
+    "ip",
+    &["-6", "addr", "add", &format!("{}/64", veth6_addr(1)), "dev", veth_host(), "nodad"],
+  )?;
+  run_cmd(
+    "ip",
+    &[
+      "netns",
+      "exec",
+      ns_name(),
+      "ip",
+      "-6",
+      "addr",
+      "add",
+      &format!("{}/64", veth6_addr(2)),
+      "dev",
+      veth_ns(),
+      "nodad",
+    ],
+  )?;
+  run_cmd(
+    "ip",
+    &["netns", "exec", ns_name(), "ip", "-6", "route", "add", "default", "via", &veth6_addr(1)],
+  )?;
+  Ok(())
+}
+
+fn teardown_host_routing_v6(ifname: &str) {
+  let _ = run_cmd(
+    "ip6tables",
+    &["-t", "mangle", "-D", "PREROUTING", "-i", veth_host(), "-j", "MARK", "--set-mark", &fwmark()],
+  );
+  let _ = run_cmd("ip6tables", &["-D", "FORWARD", "-i", veth_host(), "-o", ifname, "-j", "ACCEPT"]);
+  let _ = run_cmd("ip6tables", &["-D", "FORWARD", "-i", ifname, "-o", veth_host(), "-j", "ACCEPT"]);
+  let _ = run_nat(
+    "ip6tables",
+    &["-t", "nat", "-D", "POSTROUTING", "-m", "mark", "--mark", &fwmark(), "-o", ifname, "-j", "MASQUERADE"],
+  );
+  let _ = run_cmd("ip", &["-6", "rule", "del", "fwmark", &fwmark(), "table", &table_id()]);
+  let _ = run_cmd("ip", &["-6", "route", "del", "default", "dev", ifname, "table", &table_id()]);
+}
+
+fn table_id() -> String {
+  ROUTE_TABLE.load(Ordering::Relaxed).to_string()
+}
+
+fn fwmark() -> String {
+  format!("0x{:x}", FWMARK.load(Ordering::Relaxed))
+}
+
+fn parse_subnet_arg(value: &str) -> Result<u32, String> {
+  parse_ipv4_network(value)
+    .filter(|(network, prefix)| *prefix == 24 && network & 0xff == 0)
+    .map(|(network, _)| network)
+    .ok_or(format!("invalid subnet: {value} (expected a.b.c.0/24)"))
+}
+
+fn parse_table_arg(value: &str) -> Result<u32, String> {
+  value
+    .parse::<u32>()
+    .ok()
+    .filter(|id| *id > 0 && !(253..=255).contains(id))
+    .ok_or(format!("invalid routing table: {value}"))
+}
+
+fn parse_fwmark_arg(value: &str) -> Result<u32, String> {
+  parse_fwmark(value)
+    .filter(|mark| *mark > 0)
+    .ok_or(format!("invalid fwmark: {value}"))
+}
+
+fn veth_addr(host: u32) -> std::net::Ipv4Addr {
+  std::net::Ipv4Addr::from(VETH_SUBNET.load(Ordering::Relaxed) | host)
+}
+
+fn veth_cidr(host: u32) -> String {
+  format!("{}/24", veth_addr(host))
+}
+
+fn veth_subnet() -> String {
+  veth_cidr(0)
+}
+
+fn set_veth_subnet(subnet: &str) {
+  let base = subnet
+    .strip_suffix("/24")
+    .and_then(|addr| addr.parse::<std::net::Ipv4Addr>().ok())
+    .map(u32::from)
+    .unwrap_or(DEFAULT_VETH_SUBNET);
+  VETH_SUBNET.store(base & 0xffff_ff00, Ordering::Relaxed);
+}
+
+fn pick_random_subnet(config: &str, bypass: &[String]) -> Result<u32, String> {
+  let mut random = [0u8; 2 * SUBNET_PICK_ATTEMPTS];
+  fs::File::open("/dev/urandom")
+    .and_then(|mut urandom| urandom.read_exact(&mut random))
+    .map_err(|e| format!("cannot read /dev/urandom: {e}"))?;
+  pick_free_subnet(config, bypass, &random)
+}
+
+fn pick_free_subnet(config: &str, bypass: &[String], random: &[u8]) -> Result<u32, String> {
+  let mut taken: Vec<(u32, u32)> = Vec::new();
+  for args in [
+    &["-4", "route", "show", "table", "all"][..],
+    &["-4", "-o", "addr", "show"][..],
+  ] {
+    for line in cmd_stdout("ip", args)?.lines() {
+      taken.extend(line.split_whitespace().filter_map(parse_ipv4_network));
+    }
+  }
+  taken.extend(
+    config
+      .lines()
+      .filter_map(|line| line.split_once('='))
+      .filter(|(key, _)| {
+        matches!(
+          key.trim().to_ascii_lowercase().as_str(),
+          "address" | "allowedips"
+        )
+      })
+      .flat_map(|(_, value)| value.split(',').map(str::trim).collect::<Vec<_>>())
+      .filter_map(parse_ipv4_network)
+      .filter(|(_, prefix)| *prefix > 0),
   );
-  let _ = run_cmd("wg-quick", &["down", &state.temp_config]);
+  taken.extend(bypass.iter().filter_map(|route| parse_ipv4_network(route)));
+  random
+    .chunks_exact(2)
+    .map(|pair| 0x0a00_0000 | (16 + pair[0] as u32 % 239) << 16 | (pair[1] as u32) << 8)
+    .find(|candidate| {
+      taken.iter().all(|(network, prefix)| {
+        let mask = u32::MAX.checked_shl(32 - (*prefix).min(24)).unwrap_or(0);
+        network & mask != candidate & mask
+      })
+    })
+    .ok_or_else(|| "no free private /24 found for the namespace".to_string())
+}
+
+fn parse_ipv4_network(value: &str) -> Option<(u32, u32)> {
+  let (addr, prefix) = value.split_once('/').unwrap_or((value, "32"));
+  let prefix = prefix.parse::<u32>().ok().filter(|prefix| *prefix <= 32)?;
+  Some((u32::from(addr.parse::<std::net::Ipv4Addr>().ok()?), prefix))
+}
+
+fn subnet_routed_by_tunnel(config: &str) -> bool {
+  let subnet = veth_addr(0);
+  let subnet_prefix = 24;
+  config
+    .lines()
+    .filter_map(|line| line.split_once('='))
+    .filter(|(key, _)| key.trim().eq_ignore_ascii_case("address"))
+    .flat_map(|(_, value)| value.split(',').map(str::trim).collect::<Vec<_>>())
+    .filter_map(|address| {
+      let (addr, prefix) = address.split_once('/').unwrap_or((address, "32"));
+      Some((addr.parse::<std::net::Ipv4Addr>().ok()?, prefix.parse::<u32>().ok()?))
+    })
+    .any(|(addr, prefix)| {
+      let mask = u32::MAX.checked_shl(32 - prefix.min(32)).unwrap_or(0);
+      prefix <= subnet_prefix && u32::from(addr) & mask == u32::from(subnet) & mask
+    })
+}
+
+fn carries_ipv6(config: &str) -> bool {
+  config.lines().any(|line| {
+    let Some((key, value)) = line.split_once('=') else {
+      return false;
+    };
+    key.trim().eq_ignore_ascii_case("address") && value.contains(':')
+  })
+}
+
+fn apply_external_forwarding(add: bool) -> Result<(), String> {
+  let op = if add { "-A" } else { "-D" };
+  run_cmd("iptables", &[op, "FORWARD", "-i", veth_host(), "-j", "ACCEPT"])?;
+  run_cmd("iptables", &[op, "FORWARD", "-o", veth_host(), "-j", "ACCEPT"])?;
+  run_nat(
+    "iptables",
+    &["-t", "nat", op, "POSTROUTING", "-s", &veth_subnet(), "!", "-o", veth_host(), "-j", "MASQUERADE"],
+  )?;
+  Ok(())
+}
+
+fn apply_bypass_route(route: &str, add: bool) -> Result<(), String> {
+  let (rule_op, ipt_op) = if add { ("add", "-A") } else { ("del", "-D") };
+  if is_ipv6_cidr(route) {
+    return apply_bypass_route_v6(route, rule_op, ipt_op);
+  }
+  run_cmd(
+    "ip",
+    &[
+      "rule",
+      rule_op,
+      "to",
+      route,
+      "fwmark",
+      &fwmark(),
+      "lookup",
+      "main",
+      "priority",
+      BYPASS_RULE_PRIORITY,
+    ],
+  )?;
+  run_nat(
+    "iptables",
+    &["-t", "nat", ipt_op, "POSTROUTING", "-s", &veth_subnet(), "-d", route, "-j", "MASQUERADE"],
+  )?;
+  run_cmd(
+    "iptables",
+    &[ipt_op, "FORWARD", "-i", veth_host(), "-d", route, "-j", "ACCEPT"],
+  )?;
+  run_cmd(
+    "iptables",
+    &[ipt_op, "FORWARD", "-o", veth_host(), "-s", route, "-j", "ACCEPT"],
+  )?;
+  Ok(())
+}
+
+fn apply_bypass_route_v6(route: &str, rule_op: &str, ipt_op: &str) -> Result<(), String> {
+  run_cmd(
+    "ip",
+    &[
+      "-6",
+      "rule",
+      rule_op,
+      "to",
+      route,
+      "fwmark",
+      &fwmark(),
+      "lookup",
+      "main",
+      "priority",
+      BYPASS_RULE_PRIORITY,
+    ],
+  )?;
+  run_nat(
+    "ip6tables",
+    &["-t", "nat", ipt_op, "POSTROUTING", "-m", "mark", "--mark", &fwmark(), "-d", route, "-j", "MASQUERADE"],
+  )?;
+  run_cmd(
+    "ip6tables",
+    &[ipt_op, "FORWARD", "-i", veth_host(), "-d", route, "-j", "ACCEPT"],
+  )?;
+  run_cmd(
+    "ip6tables",
+    &[ipt_op, "FORWARD", "-o", veth_host(), "-s", route, "-j", "ACCEPT"],
+  )?;
+  Ok(())
+}
+
+fn is_valid_ifname(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= 15
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn is_ipv4_cidr(value: &str) -> bool {
+  let (addr, prefix) = value.split_once('/').unwrap_or((value, "32"));
+  addr.parse::<std::net::Ipv4Addr>().is_ok() && prefix.parse::<u8>().is_ok_and(|p| p <= 32)
+}
+
+fn is_ipv6_cidr(value: &str) -> bool {
+  let (addr, prefix) = value.split_once('/').unwrap_or((value, "128"));
+  addr.parse::<std::net::Ipv6Addr>().is_ok() && prefix.parse::<u8>().is_ok_and(|p| p <= 128)
+}
+
+fn run_in_namespace(
+  bin: &Path,
+  interp: Option<&str>,
+  terminal: Option<&str>,
+  envs: &[(String, String)],
+) -> Result<u32, String> {
+  let mut cmd = namespace_command(bin, interp, terminal, envs, true)?;
+  cmd.stdin(Stdio::null());
+  let logfile = APP_LOGFILE.lock().map_err(|e| e.to_string())?.clone();
+  match logfile {
+    Some(path) => {
+      let file = open_app_log(&path)?;
+      cmd.stdout(file.try_clone().map_err(|e| e.to_string())?);
+      cmd.stderr(file);
+    }
+    None => {
+      cmd.stdout(Stdio::null());
+      cmd.stderr(Stdio::null());
+    }
+  }
+  let child = cmd.spawn().map_err(|e| e.to_string())?;
+  Ok(child.id())
+}
+
+fn open_app_log(path: &Path) -> Result<fs::File, String> {
+  let owner = match caller_identity() {
+    Some((uid, gid)) => (
+      uid.parse::<u32>().map_err(|_| "invalid PKEXEC_UID".to_string())?,
+      gid.parse::<u32>().map_err(|_| "invalid caller gid".to_string())?,
+    ),
+    None => (0, 0),
+  };
+  let parent = path.parent().ok_or("--logfile has no parent directory")?;
+  let parent_meta = fs::symlink_metadata(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+  if !parent_meta.is_dir() || parent_meta.uid() != owner.0 {
+    return Err(format!("{} is not a directory owned by the caller", parent.display()));
+  }
+  let open = |create_new: bool| {
+    let mut options = fs::OpenOptions::new();
+    options
+      .append(true)
+      .mode(0o600)
+      .custom_flags(libc::O_NOFOLLOW);
+    if create_new {
+      options.create_new(true);
+    }
+    options.open(path)
+  };
+  let file = match open(true) {
+    Ok(file) => {
+      std::os::unix::fs::fchown(&file, Some(owner.0), Some(owner.1))
+        .map_err(|e| format!("cannot chown {}: {e}", path.display()))?;
+      file
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+      let file = open(false).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+      let meta = file.metadata().map_err(|e| e.to_string())?;
+      if !meta.is_file() || meta.uid() != owner.0 {
+        return Err(format!("{} is not a regular file owned by the caller", path.display()));
+      }
+      file
+    }
+    Err(err) => return Err(format!("cannot create {}: {err}", path.display())),
+  };
+  Ok(file)
+}
+
+fn run_in_namespace_captured(
+  bin: &Path,
+  interp: Option<&str>,
+  envs: &[(String, String)],
+) -> Result<(), String> {
+  let mut cmd = namespace_command(bin, interp, None, envs, true)?;
+  cmd.stdin(Stdio::null());
+  cmd.stdout(Stdio::piped());
+  cmd.stderr(Stdio::piped());
+  let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+  println!("{}", child.id());
+  let _ = std::io::stdout().flush();
+  let stdout = child.stdout.take();
+  let stderr = child.stderr.take();
+  let stderr = std::thread::spawn(move || relay_lines(stderr));
+  relay_lines(stdout);
+  let _ = stderr.join();
+  let _ = child.wait();
+  Ok(())
+}
+
+fn relay_lines<R: Read>(pipe: Option<R>) {
+  let Some(pipe) = pipe else {
+    return;
+  };
+  let mut reader = std::io::BufReader::new(pipe);
+  let mut line = Vec::new();
+  while std::io::BufRead::read_until(&mut reader, b'\n', &mut line).is_ok_and(|read| read > 0) {
+    let mut out = std::io::stdout().lock();
+    let _ = out
+      .write_all(String::from_utf8_lossy(&line).trim_end_matches('\n').as_bytes())
+      .and_then(|_| out.write_all(b"\n"))
+      .and_then(|_| out.flush());
+    line.clear();
+  }
+}
+
+fn run_in_namespace_wait(
+  bin: &Path,
+  interp: Option<&str>,
+  terminal: Option<&str>,
+  envs: &[(String, String)],
+) -> Result<i32, String> {
+  let mut cmd = namespace_command(bin, interp, terminal, envs, false)?;
+  cmd.stdin(Stdio::null());
+  let status = cmd.status().map_err(|e| e.to_string())?;
+  Ok(status
+    .code()
+    .or_else(|| status.signal().map(|signal| 128 + signal))
+    .unwrap_or(1))
+}
+
+fn namespace_command(
+  bin: &Path,
+  interp: Option<&str>,
+  terminal: Option<&str>,
+  envs: &[(String, String)],
+  detach: bool,
+) -> Result<Command, String> {
+  if !bin.exists() {
+    return Err("binary does not exist".into());
+  }
+  let terminal_args = match terminal {
+    Some(terminal) => {
+      if !terminal.starts_with('/') || !Path::new(terminal).is_file() {
+        return Err(format!("terminal emulator does not exist: {terminal}"));
+      }
+      terminal_exec_args(terminal)?
+    }
+    None => Vec::new(),
+  };
+  let interp_parts: Vec<&str> = interp
+    .map(|line| match line.trim().split_once(char::is_whitespace) {
+      Some((program, arg)) => vec![program, arg.trim()],
+      None => vec![line.trim()],
+    })
+    .unwrap_or_default();
+  if let Some(program) = interp_parts.first() {
+    if !Path::new(program).exists() {
+      return Err(format!("interpreter does not exist: {program}"));
+    }
+  }
+  let (launcher, use_setsid) = find_setsid();
+  let mut cmd = if detach && use_setsid {
+    let mut cmd = Command::new(launcher);
+    cmd.arg("/usr/bin/ip");
+    cmd
+  } else {
+    Command::new("/usr/bin/ip")
+  };
+  cmd.args(["netns", "exec", ns_name()]);
+  let rlimits = RLIMITS.lock().map_err(|e| e.to_string())?.clone();
+  if !rlimits.is_empty() {
+    let prlimit = find_in_path("prlimit").ok_or("prlimit not found, install util-linux")?;
+    cmd.arg(prlimit);
+    for (name, value) in &rlimits {
+      cmd.arg(format!("--{name}={value}"));
+    }
+    cmd.arg("--");
+  }
+  let mut dropped = false;
+  if let Some((uid, gid)) = caller_identity() {
+    if let Some(setpriv) = find_setpriv() {
+      cmd.arg(setpriv);
+      cmd.args([
+        "--reuid",
+        &uid,
+        "--regid",
+        &gid,
+        "--init-groups",
+        "--inh-caps",
+        "-all",
+      ]);
+      if NO_NEW_PRIVS.load(Ordering::Relaxed) {
+        cmd.arg("--no-new-privs");
+      }
+      dropped = true;
+    }
+  }
+  if NO_NEW_PRIVS.load(Ordering::Relaxed) && !dropped {
+    return Err("--no-new-privs requires setpriv and a calling user".into());
+  }
+  if let Some(terminal) = terminal {
+    cmd.arg(terminal);
+    cmd.args(terminal_args);
+  }
+  cmd.args(&interp_parts);
+  cmd.arg(bin);
+  cmd.args(APP_ARGS.lock().map_err(|e| e.to_string())?.iter());
+  if let Some(dir) = APP_CWD.lock().map_err(|e| e.to_string())?.as_ref() {
+    cmd.current_dir(dir);
+  }
+  for (key, value) in envs {
+    cmd.env(key, value);
+  }
+  let cgroup = APP_CGROUP.lock().map_err(|e| e.to_string())?.clone();
+  if let Some(name) = cgroup {
+    match prepare_app_cgroup(&name) {
+      Ok(Some(procs)) => unsafe {
+        cmd.pre_exec(move || {
+          if libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) != 1 {
+            return Err(io::Error::last_os_error());
+          }
+          Ok(())
+        });
+      },
+      Ok(None) => {}
+      Err(err) => eprintln!("sillyvpn-helper: per-app traffic accounting disabled: {err}"),
+    }
+  }
+  Ok(cmd)
+}
+
+fn is_valid_cgroup_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= APP_CGROUP_NAME_MAX
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn app_connmark(name: &str, taken: &[(String, u32)]) -> Option<u32> {
+  if let Some((_, mark)) = taken.iter().find(|(owner, _)| owner == name) {
+    return Some(*mark);
+  }
+  let hash = name
+    .bytes()
+    .fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+  (0..=0xffff)
+    .map(|probe| APP_CONNMARK_BASE | (hash.wrapping_add(probe) & 0xffff))
+    .find(|mark| taken.iter().all(|(_, used)| used != mark))
+}
+
+fn parse_app_connmarks(rules: &str) -> Vec<(String, u32)> {
+  rules
+    .lines()
+    .filter_map(|line| {
+      let mut tokens = line.split_whitespace();
+      let mut name = None;
+      let mut mark = None;
+      while let Some(token) = tokens.next() {
+        match token {
+          "--comment" => name = tokens.next()?.trim_matches('"').strip_prefix("sillyvpn-tx:"),
+          "--set-xmark" | "--set-mark" => {
+            let value = tokens.next()?.split('/').next()?;
+            mark = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+          }
+          _ => {}
+        }
+      }
+      Some((name?.to_string(), mark?))
+    })
+    .collect()
+}
+
+fn app_traffic_rules(name: &str, mark: u32) -> [(String, Vec<String>); 2] {
+  let mark = format!("0x{mark:x}");
+  let cgroup_path = format!("{CGROUP_PARENT}/{name}");
+  [
+    (
+      "OUTPUT".to_string(),
+      [
+        "-m", "cgroup", "--path", &cgroup_path, "-m", "comment", "--comment",
+        &format!("sillyvpn-tx:{name}"), "-j", "CONNMARK", "--set-mark", &mark,
+      ]
+      .map(String::from)
+      .to_vec(),
+    ),
+    (
+      "INPUT".to_string(),
+      [
+        "-m", "connmark", "--mark", &mark, "-m", "comment", "--comment",
+        &format!("sillyvpn-rx:{name}"),
+      ]
+      .map(String::from)
+      .to_vec(),
+    ),
+  ]
+}
+
+fn prepare_app_cgroup(name: &str) -> Result<Option<fs::File>, String> {
+  let dir = Path::new(CGROUP_ROOT).join(CGROUP_PARENT).join(name);
+  if !dry_run() {
+    if !Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+      return Err("cgroup v2 is not mounted".into());
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+  }
+  let taken = if dry_run() {
+    Vec::new()
+  } else {
+    parse_app_connmarks(&cmd_stdout("ip", &["netns", "exec", ns_name(), "iptables", "-S", "OUTPUT"])?)
+  };
+  let mark = app_connmark(name, &taken).ok_or("no free connmark left for per-app accounting")?;
+  for (chain, rule) in app_traffic_rules(name, mark) {
+    let mut check = vec!["netns", "exec", ns_name(), "iptables", "-C", &chain];
+    check.extend(rule.iter().map(String::as_str));
+    if !dry_run() && cmd_stdout("ip", &check).is_ok() {
+      continue;
+    }
+    check[4] = "-A";
+    run_cmd("ip", &check)?;
+  }
+  if dry_run() {
+    println!("cgroup {}", dir.display());
+    return Ok(None);
+  }
+  fs::OpenOptions::new()
+    .write(true)
+    .open(dir.join("cgroup.procs"))
+    .map(Some)
+    .map_err(|e| format!("cannot open {}/cgroup.procs: {e}", dir.display()))
+}
+
+fn remove_app_cgroups() {
+  let parent = Path::new(CGROUP_ROOT).join(CGROUP_PARENT);
+  if dry_run() {
+    println!("rmdir empty cgroups under {}", parent.display());
+    return;
+  }
+  if let Ok(entries) = fs::read_dir(&parent) {
+    for entry in entries.flatten() {
+      if entry.path().is_dir() {
+        let _ = fs::remove_dir(entry.path());
+      }
+    }
+  }
+  let _ = fs::remove_dir(&parent);
+}
+
+fn read_rule_bytes(chain: &str, prefix: &str) -> Vec<(String, u64)> {
+  let Ok(listing) = cmd_stdout(
+    "ip",
+    &["netns", "exec", ns_name(), "iptables", "-L", chain, "-v", "-n", "-x"],
+  ) else {
+    return Vec::new();
+  };
+  listing
+    .lines()
+    .filter_map(|line| {
+      let (_, comment) = line.split_once(&format!("/* {prefix}:"))?;
+      let name = comment.split_whitespace().next()?;
+      let bytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+      Some((name.to_string(), bytes))
+    })
+    .collect()
+}
+
+fn list_app_traffic() -> Result<(), String> {
+  if read_state(&state_dir()).is_err() {
+    println!("[]");
+    return Ok(());
+  }
+  let rx = read_rule_bytes("INPUT", "sillyvpn-rx");
+  let apps: Vec<serde_json::Value> = read_rule_bytes("OUTPUT", "sillyvpn-tx")
+    .into_iter()
+    .map(|(name, tx_bytes)| {
+      let rx_bytes = rx
+        .iter()
+        .find(|(rx_name, _)| *rx_name == name)
+        .map(|(_, bytes)| *bytes)
+        .unwrap_or(0);
+      serde_json::json!({
+        "app_id": name,
+        "rx_bytes": rx_bytes,
+        "tx_bytes": tx_bytes,
+      })
+    })
+    .collect();
+  println!("{}", serde_json::Value::Array(apps));
+  Ok(())
+}
+
+fn run_diagnostic(tool: &str, tool_args: &[String]) -> Result<(), String> {
+  if !DIAGNOSTIC_TOOLS.contains(&tool) {
+    return Err(format!("tool not allowed: {tool}"));
+  }
+  if tool_args.len() > DIAGNOSTIC_MAX_ARGS {
+    return Err(format!("too many arguments (max {DIAGNOSTIC_MAX_ARGS})"));
+  }
+  if tool == "curl" {
+    let write_flag = tool_args.iter().find(|arg| {
+      CURL_WRITE_FLAGS
+        .iter()
+        .any(|flag| arg.as_str() == *flag || arg.starts_with(&format!("{flag}=")))
+    });
+    if let Some(flag) = write_flag {
+      return Err(format!("curl argument not allowed: {flag}"));
+    }
+  }
+  if !Path::new(&format!("/var/run/netns/{}", ns_name())).exists() {
+    return Err("namespace is not active, enable VPN first".into());
+  }
+  let bin = find_in_path(tool).ok_or(format!("{tool} not found"))?;
+  let (uid, gid) = caller_identity().ok_or("cannot determine calling user (PKEXEC_UID)")?;
+  let setpriv = find_setpriv().ok_or("setpriv not found, install util-linux")?;
+  if TIMEOUT_SECS.load(Ordering::Relaxed) == 0 {
+    TIMEOUT_SECS.store(DIAGNOSTIC_TIMEOUT_SECS, Ordering::Relaxed);
+  }
+  let mut cmd = Command::new("/usr/bin/ip");
+  cmd.args(["netns", "exec", ns_name(), setpriv]);
+  cmd.args([
+    "--reuid",
+    &uid,
+    "--regid",
+    &gid,
+    "--init-groups",
+    "--inh-caps",
+    "-all",
+  ]);
+  cmd.arg(bin);
+  cmd.args(tool_args);
+  cmd.env_clear();
+  cmd.env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin");
+  let output = output_with_timeout(cmd)?;
+  let result = serde_json::json!({
+    "exit_code": output.status.code().unwrap_or(-1),
+    "stdout": String::from_utf8_lossy(&output.stdout),
+    "stderr": String::from_utf8_lossy(&output.stderr),
+  });
+  println!("{result}");
+  Ok(())
+}
+
+fn is_valid_probe_host(host: &str) -> bool {
+  host.parse::<std::net::IpAddr>().is_ok()
+    || (!host.is_empty()
+      && !host.starts_with('-')
+      && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+}
+
+fn check_nat(host: &str) -> Result<(), String> {
+  if !is_valid_probe_host(host) {
+    return Err(format!("invalid host: {host}"));
+  }
+  let state = read_state(&state_dir()).map_err(|_| "VPN is not enabled".to_string())?;
+  set_veth_subnet(&state.veth_subnet);
+  if !state.masquerade {
+    println!(
+      "{}",
+      serde_json::json!({ "status": "masquerade_disabled", "masqueraded_packets": 0, "reply": false })
+    );
+    return Ok(());
+  }
+  let ping = find_in_path("ping").ok_or("ping not found, install iputils")?;
+  let before = masqueraded_packets()?;
+  let reply = Command::new("ip")
+    .args(["netns", "exec", ns_name()])
+    .arg(ping)
+    .args(["-n", "-q", "-c", "3", "-W", "2", host])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .map(|status| status.success())
+    .unwrap_or(false);
+  let masqueraded = masqueraded_packets()?.saturating_sub(before);
+  let status = if reply {
+    "ok"
+  } else if masqueraded > 0 {
+    "no_reply"
+  } else {
+    "nat_broken"
+  };
+  let firewall = (status == "nat_broken").then(detect_host_firewall).flatten();
+  println!(
+    "{}",
+    serde_json::json!({
+      "status": status,
+      "masqueraded_packets": masqueraded,
+      "reply": reply,
+      "firewall": firewall,
+    })
+  );
+  Ok(())
+}
+
+fn masqueraded_packets() -> Result<u64, String> {
+  let subnet = veth_subnet();
+  let listing = cmd_stdout("iptables", &["-t", "nat", "-L", "POSTROUTING", "-v", "-n", "-x"])?;
+  Ok(
+    listing
+      .lines()
+      .map(|line| line.split_whitespace().collect::<Vec<_>>())
+      .filter(|fields| fields.get(2) == Some(&"MASQUERADE") && fields.contains(&subnet.as_str()))
+      .filter_map(|fields| fields[0].parse::<u64>().ok())
+      .sum(),
+  )
+}
+
+fn detect_host_firewall() -> Option<String> {
+  if Path::new("/run/firewalld").exists() || Path::new("/var/run/firewalld.pid").exists() {
+    return Some("firewalld".into());
+  }
+  let ufw_enabled = fs::read_to_string("/etc/ufw/ufw.conf")
+    .map(|conf| conf.lines().any(|line| line.trim() == "ENABLED=yes"))
+    .unwrap_or(false);
+  if ufw_enabled {
+    return Some("ufw".into());
+  }
+  let forward_drop = cmd_stdout("iptables", &["-S", "FORWARD"])
+    .map(|rules| rules.lines().any(|line| line.trim() == "-P FORWARD DROP"))
+    .unwrap_or(false);
+  if forward_drop {
+    return Some("iptables FORWARD policy DROP".into());
+  }
+  let nft_drop = cmd_stdout("nft", &["list", "ruleset"])
+    .map(|ruleset| {
+      ruleset
+        .lines()
+        .any(|line| line.contains("hook forward") && line.contains("policy drop"))
+    })
+    .unwrap_or(false);
+  nft_drop.then(|| "nftables forward chain with policy drop".into())
+}
+
+fn print_kernel_log(ifname: &str, since: u64) -> Result<(), String> {
+  let journal = (Path::new(JOURNAL_DIR).exists() && find_in_path("journalctl").is_some())
+    .then(|| {
+      cmd_stdout(
+        "journalctl",
+        &["-k", "--no-pager", "-o", "short-iso", "--since", &format!("-{since}s")],
+      )
+      .ok()
+    })
+    .flatten();
+  let (source, log) = match journal {
+    Some(log) => ("journal", log),
+    None => ("dmesg", cmd_stdout("dmesg", &["--ctime"]).or_else(|_| cmd_stdout("dmesg", &[]))?),
+  };
+  let mut lines: Vec<&str> = log
+    .lines()
+    .filter(|line| {
+      let lower = line.to_ascii_lowercase();
+      lower.contains("wireguard") || lower.contains("amneziawg") || line.contains(ifname)
+    })
+    .collect();
+  if lines.len() > KERNEL_LOG_MAX_LINES {
+    lines.drain(..lines.len() - KERNEL_LOG_MAX_LINES);
+  }
+  println!("{}", serde_json::json!({ "source": source, "lines": lines }));
+  Ok(())
+}
+
+fn terminal_exec_args(terminal: &str) -> Result<Vec<&'static str>, String> {
+  let resolved = fs::canonicalize(terminal).unwrap_or_else(|_| PathBuf::from(terminal));
+  let name = resolved
+    .file_name()
+    .and_then(|name| name.to_str())
+    .unwrap_or_default();
+  match name {
+    "gnome-terminal" | "gnome-terminal.wrapper" | "kgx" => Err(format!(
+      "{name} starts commands through a session service outside the namespace"
+    )),
+    "xfce4-terminal" => Ok(vec!["--disable-server", "-x"]),
+    "terminator" | "mate-terminal" => Ok(vec!["-x"]),
+    "kitty" => Ok(Vec::new()),
+    _ => Ok(vec!["-e"]),
+  }
+}
+
+fn list_connections() -> Result<(), String> {
+  if !Path::new(&format!("/var/run/netns/{}", ns_name())).exists() {
+    println!("[]");
+    return Ok(());
+  }
+  let ss = find_ss().ok_or("ss not found, install iproute2")?;
+  let mut cmd = Command::new("ip");
+  cmd.args(["netns", "exec", ns_name(), ss, "-tunp"]);
+  let output = output_with_timeout(cmd)?;
+  if !output.status.success() {
+    return Err(format!(
+      "ss error: {}",
+      String::from_utf8_lossy(&output.stderr)
+    ));
+  }
+  let entries: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .filter_map(parse_ss_line)
+    .collect();
+  println!("{}", serde_json::Value::Array(entries));
+  Ok(())
+}
+
+fn list_peers() -> Result<(), String> {
+  let Ok(state) = read_state(&state_dir()) else {
+    println!("[]");
+    return Ok(());
+  };
+  let dump = wg_dump(&state)?;
+  let peers: Vec<serde_json::Value> = dump
+    .lines()
+    .skip(1)
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split('\t').collect();
+      if fields.len() < 7 {
+        return None;
+      }
+      let endpoint = Some(fields[2]).filter(|endpoint| *endpoint != "(none)");
+      let allowed_ips: Vec<&str> = fields[3]
+        .split(',')
+        .filter(|ip| !ip.is_empty() && *ip != "(none)")
+        .collect();
+      Some(serde_json::json!({
+        "public_key": fields[0],
+        "endpoint": endpoint,
+        "allowed_ips": allowed_ips,
+        "latest_handshake": fields[4].parse::<u64>().unwrap_or(0),
+        "rx": fields[5].parse::<u64>().unwrap_or(0),
+        "tx": fields[6].parse::<u64>().unwrap_or(0),
+      }))
+    })
+    .collect();
+  println!("{}", serde_json::Value::Array(peers));
+  Ok(())
+}
+
+fn wg_dump(state: &HelperState) -> Result<String, String> {
+  let wg = wg_tool(state.amnezia);
+  if state.external_routes {
+    cmd_stdout("ip", &["netns", "exec", ns_name(), wg, "show", &state.wg_ifname, "dump"])
+  } else {
+    cmd_stdout(wg, &["show", &state.wg_ifname, "dump"])
+  }
+}
+
+fn print_transfer_stats() -> Result<(), String> {
+  let Ok(state) = read_state(&state_dir()) else {
+    println!("[]");
+    return Ok(());
+  };
+  let peers: Vec<serde_json::Value> = wg_dump(&state)?
+    .lines()
+    .skip(1)
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split('\t').collect();
+      if fields.len() < 7 {
+        return None;
+      }
+      Some(serde_json::json!({
+        "public_key": fields[0],
+        "rx_bytes": fields[5].parse::<u64>().unwrap_or(0),
+        "tx_bytes": fields[6].parse::<u64>().unwrap_or(0),
+      }))
+    })
+    .collect();
+  println!("{}", serde_json::Value::Array(peers));
+  Ok(())
+}
+
+fn print_tunnel_status() -> Result<(), String> {
+  let mut interface_up = false;
+  let mut last_handshake = 0u64;
+  let mut rx_bytes = 0u64;
+  let mut tx_bytes = 0u64;
+  if let Ok(state) = read_state(&state_dir()) {
+    let mut link_args = vec!["link", "show", "dev", state.wg_ifname.as_str()];
+    if state.external_routes {
+      link_args.splice(0..0, ["netns", "exec", ns_name(), "ip"]);
+    }
+    interface_up = cmd_stdout("ip", &link_args).is_ok_and(|link| {
+      link
+        .split(['<', '>'])
+        .nth(1)
+        .is_some_and(|flags| flags.split(',').any(|flag| flag == "UP"))
+    });
+    if let Ok(dump) = wg_dump(&state) {
+      for fields in dump.lines().skip(1).map(|line| line.split('\t').collect::<Vec<_>>()) {
+        if fields.len() < 7 {
+          continue;
+        }
+        last_handshake = last_handshake.max(fields[4].parse().unwrap_or(0));
+        rx_bytes += fields[5].parse::<u64>().unwrap_or(0);
+        tx_bytes += fields[6].parse::<u64>().unwrap_or(0);
+      }
+    }
+  }
+  let now = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default();
+  let last_handshake_secs = (last_handshake > 0).then(|| now.saturating_sub(last_handshake));
+  println!(
+    "{}",
+    serde_json::json!({
+      "interface_up": interface_up,
+      "last_handshake_secs": last_handshake_secs,
+      "rx_bytes": rx_bytes,
+      "tx_bytes": tx_bytes,
+    })
+  );
+  Ok(())
+}
+
+fn check_conflicts() -> Result<(), String> {
+  let state = read_state(&state_dir()).ok();
+  if let Some(state) = &state {
+    ROUTE_TABLE.store(state.table, Ordering::Relaxed);
+    FWMARK.store(state.fwmark, Ordering::Relaxed);
+  }
+  let own_ifname = state.map(|state| state.wg_ifname);
+  let mut conflicts = Vec::new();
+
+  let rules = cmd_stdout("ip", &["rule", "show"])?;
+  for line in rules.lines() {
+    let line = line.trim();
+    let ours = line.starts_with(&format!("{FWMARK_RULE_PRIORITY}:"))
+      || line.starts_with(&format!("{BYPASS_RULE_PRIORITY}:"));
+    if ours {
+      continue;
+    }
+    let mark = format!("fwmark {}", fwmark());
+    if line.contains(&mark) || line.split_whitespace().any(|w| w == table_id()) {
+      conflicts.push(serde_json::json!({ "source": "rule", "entry": line }));
+    }
+  }
+
+  let routes = cmd_stdout("ip", &["route", "show", "table", &table_id()]).unwrap_or_default();
+  for line in routes.lines() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    if let Some(ifname) = &own_ifname {
+      if line.split_whitespace().any(|w| w == ifname) || is_killswitch_route(line) {
+        continue;
+      }
+    }
+    conflicts.push(serde_json::json!({ "source": "route", "entry": line }));
+  }
+
+  println!("{}", serde_json::Value::Array(conflicts));
+  Ok(())
+}
+
+fn parse_fwmark(value: &str) -> Option<u32> {
+  let value = value.split('/').next()?;
+  match value.strip_prefix("0x") {
+    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+    None => value.parse().ok(),
+  }
+}
+
+fn plan_network(network: u32, prefix: u32, table: u32, mark: u32) -> Result<(), String> {
+  VETH_SUBNET.store(network, Ordering::Relaxed);
+  let subnet = veth_subnet();
+  let host = veth_addr(1).to_string();
+  let table_id = table;
+  let table = table.to_string();
+  let mark_hex = format!("0x{mark:x}");
+  let (ns, veth_host, veth_ns) = (ns_name(), veth_host(), veth_ns());
+  let steps = [
+    format!("ip link add {veth_host} type veth peer name {veth_ns}"),
+    format!("ip addr add {} dev {veth_host}", veth_cidr(1)),
+    format!("ip netns exec {ns} ip addr add {} dev {veth_ns}", veth_cidr(2)),
+    format!("ip netns exec {ns} ip route add default via {host}"),
+    format!("ip rule add fwmark {mark_hex} table {table} priority {FWMARK_RULE_PRIORITY}"),
+    format!("ip route add default dev <wg> table {table}"),
+    format!("iptables -t mangle -A PREROUTING -i {veth_host} -j MARK --set-mark {mark_hex}"),
+    format!("iptables -A FORWARD -i {veth_host} -o <wg> -j ACCEPT"),
+    format!("iptables -A FORWARD -i <wg> -o {veth_host} -j ACCEPT"),
+    format!("iptables -t nat -A POSTROUTING -s {subnet} -o <wg> -j MASQUERADE"),
+  ];
+  let own_ifname = read_state(&state_dir()).ok().map(|state| state.wg_ifname);
+  let is_ours = |line: &str| {
+    is_killswitch_route(line)
+      || line.split_whitespace().any(|word| {
+        word == veth_host || own_ifname.as_deref() == Some(word)
+      })
+  };
+  let mut conflicts = Vec::new();
+
+  for args in [
+    &["-4", "route", "show", "table", "all"][..],
+    &["-4", "-o", "addr", "show"][..],
+  ] {
+    for line in cmd_stdout("ip", args)?.lines() {
+      let line = line.split('\\').next().unwrap_or_default().trim();
+      if is_ours(line) || line.starts_with("default") {
+        continue;
+      }
+      let overlaps = line
+        .split_whitespace()
+        .find_map(parse_ipv4_network)
+        .is_some_and(|(other_network, other_prefix)| {
+          let mask = u32::MAX.checked_shl(32 - other_prefix.min(prefix)).unwrap_or(0);
+          other_network & mask == network & mask
+        });
+      if overlaps {
+        conflicts.push(serde_json::json!({ "source": "subnet", "entry": line }));
+      }
+    }
+  }
+
+  for line in cmd_stdout("ip", &["rule", "show"])?.lines() {
+    let line = line.trim();
+    if line.starts_with(&format!("{FWMARK_RULE_PRIORITY}:"))
+      || line.starts_with(&format!("{BYPASS_RULE_PRIORITY}:"))
+    {
+      continue;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let same_mark = words
+      .windows(2)
+      .any(|pair| pair[0] == "fwmark" && parse_fwmark(pair[1]) == Some(mark));
+    let same_table = words
+      .windows(2)
+      .any(|pair| matches!(pair[0], "lookup" | "table") && pair[1] == table);
+    if same_mark || same_table {
+      conflicts.push(serde_json::json!({ "source": "rule", "entry": line }));
+    }
+  }
+
+  let routes = cmd_stdout("ip", &["route", "show", "table", &table]).unwrap_or_default();
+  for line in routes.lines().map(str::trim).filter(|line| !line.is_empty()) {
+    if !is_ours(line) {
+      conflicts.push(serde_json::json!({ "source": "route", "entry": line }));
+    }
+  }
+
+  let mangle = cmd_stdout("iptables", &["-t", "mangle", "-S"]).unwrap_or_default();
+  for line in mangle.lines().map(str::trim) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let sets_mark = words.windows(2).any(|pair| {
+      matches!(pair[0], "--set-mark" | "--set-xmark") && parse_fwmark(pair[1]) == Some(mark)
+    });
+    if sets_mark && !is_ours(line) {
+      conflicts.push(serde_json::json!({ "source": "iptables", "entry": line }));
+    }
+  }
+
+  println!(
+    "{}",
+    serde_json::json!({
+      "subnet": subnet,
+      "table": table_id,
+      "fwmark": mark_hex,
+      "steps": steps,
+      "conflicts": conflicts,
+    })
+  );
+  Ok(())
+}
+
+fn parse_probe_args(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--timeout" => set_timeout(args.next())?,
+      _ => return Err(format!("unknown argument: {arg}")),
+    }
+  }
+  Ok(())
+}
+
+fn set_timeout(value: Option<String>) -> Result<(), String> {
+  let secs = value
+    .and_then(|value| value.parse::<u64>().ok())
+    .filter(|secs| *secs > 0)
+    .ok_or("--timeout expects a positive number of seconds")?;
+  TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+  // The app cannot signal a root helper, so an abandoned probe has to end
+  // itself. Children die with us through PR_SET_PDEATHSIG.
+  std::thread::spawn(move || {
+    std::thread::sleep(Duration::from_secs(secs + PROBE_EXIT_GRACE_SECS));
+    eprintln!("sillyvpn-helper error: probe timed out after {secs}s");
+    std::process::exit(1);
+  });
+  Ok(())
+}
+
+fn output_with_timeout(mut cmd: Command) -> Result<std::process::Output, String> {
+  let name = cmd.get_program().to_string_lossy().to_string();
+  let timeout = TIMEOUT_SECS.load(Ordering::Relaxed);
+  if timeout == 0 {
+    return cmd.output().map_err(|e| format!("{name} failed to start: {e}"));
+  }
+  unsafe {
+    cmd.pre_exec(|| {
+      libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+      Ok(())
+    });
+  }
+  let mut child = cmd
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| format!("{name} failed to start: {e}"))?;
+  let stdout = child.stdout.take();
+  let stderr = child.stderr.take();
+  let stdout = std::thread::spawn(move || read_pipe(stdout));
+  let stderr = std::thread::spawn(move || read_pipe(stderr));
+  let deadline = Instant::now() + Duration::from_secs(timeout);
+  let status = loop {
+    if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+      break status;
+    }
+    if Instant::now() >= deadline {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(format!("{name} timed out after {timeout}s"));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+  };
+  Ok(std::process::Output {
+    status,
+    stdout: stdout.join().unwrap_or_default(),
+    stderr: stderr.join().unwrap_or_default(),
+  })
+}
+
+fn read_pipe<R: Read>(pipe: Option<R>) -> Vec<u8> {
+  let mut buf = Vec::new();
+  if let Some(mut pipe) = pipe {
+    let _ = pipe.read_to_end(&mut buf);
+  }
+  buf
+}
+
+fn cmd_stdout(cmd: &str, args: &[&str]) -> Result<String, String> {
+  let output = runner().output(cmd, args, None)?;
+  if output.status.success() {
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+  } else {
+    Err(format!(
+      "{cmd} error: {}",
+      String::from_utf8_lossy(&output.stderr)
+    ))
+  }
+}
+
+fn parse_ss_line(line: &str) -> Option<serde_json::Value> {
+  let fields: Vec<&str> = line.split_whitespace().collect();
+  if fields.len() < 6 || fields[0] == "Netid" {
+    return None;
+  }
+  let process = fields[6..].join(" ");
+  let name = process
+    .split("((\"")
+    .nth(1)
+    .and_then(|rest| rest.split('"').next())
+    .map(|name| name.to_string());
+  let pids: Vec<u32> = process
+    .split("pid=")
+    .skip(1)
+    .filter_map(|rest| {
+      rest
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|pid| pid.parse().ok())
+    })
+    .collect();
+  Some(serde_json::json!({
+    "proto": fields[0],
+    "state": fields[1],
+    "local": fields[4],
+    "peer": fields[5],
+    "process": name,
+    "pids": pids,
+  }))
+}
+
+fn find_ss() -> Option<&'static str> {
+  ["/usr/bin/ss", "/bin/ss", "/usr/sbin/ss", "/sbin/ss"]
+    .into_iter()
+    .find(|path| Path::new(path).exists())
+}
+
+fn read_config(source: &ConfigSource) -> Result<String, String> {
+  let mut content = String::new();
+  match source {
+    ConfigSource::File(path) => fs::File::open(path)
+      .map_err(|e| e.to_string())?
+      .read_to_string(&mut content)
+      .map_err(|e| e.to_string())?,
+    ConfigSource::Stdin => std::io::stdin()
+      .read_to_string(&mut content)
+      .map_err(|e| e.to_string())?,
+  };
+  Ok(content)
+}
+
+fn sanitize_config(content: &str, inject_table: bool) -> Result<(String, Vec<String>), String> {
+  let content = &content
+    .trim_start_matches('\u{feff}')
+    .replace("\r\n", "\n")
+    .replace('\r', "\n");
+  let interfaces = content
+    .lines()
+    .filter(|line| line.trim().eq_ignore_ascii_case("[interface]"))
+    .count();
+  match interfaces {
+    0 => return Err("config has no [Interface] section".into()),
+    1 => {}
+    _ => return Err("config has more than one [Interface] section".into()),
+  }
+  let dns_servers = extract_dns_servers(content);
+
+  let mut output = String::new();
+  let mut in_interface = false;
+  for line in content.lines() {
+    let trimmed = line.trim();
+    let lower = trimmed.to_ascii_lowercase();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      in_interface = lower == "[interface]";
+    }
+    if inject_table && in_interface && lower.replace(' ', "").starts_with("table=") {
+      if lower.replace(' ', "") != "table=off" {
+        eprintln!("sillyvpn-helper: replaced {trimmed} with Table = off in temporary config");
+      }
+      continue;
+    }
+    if let Some(value) = lower.strip_prefix("listenport") {
+      let value = value.trim_start().trim_start_matches('=').trim();
+      if value.parse::<u16>().is_err() {
+        return Err(format!("invalid ListenPort: {value}"));
+      }
+    }
+    if lower.starts_with("dns=") || lower.starts_with("dns =") {
+      continue;
+    }
+    if lower.starts_with("saveconfig") {
+      eprintln!("sillyvpn-helper: dropped SaveConfig from temporary config");
+      continue;
+    }
+    output.push_str(line.trim_end());
+    output.push('\n');
+    if inject_table && lower == "[interface]" {
+      output.push_str("Table = off\n");
+    }
+  }
+
+  Ok((output, dns_servers))
+}
+
+fn write_private_file(dest: &Path, content: &str) -> Result<(), String> {
+  if dry_run() {
+    println!("write {} (0600)", dest.display());
+    return Ok(());
+  }
+  fs::write(dest, content).map_err(|e| e.to_string())?;
+  let mut perms = fs::metadata(dest).map_err(|e| e.to_string())?.permissions();
+  perms.set_mode(0o600);
+  fs::set_permissions(dest, perms).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+fn bring_up_interface(
+  ifname: &str,
+  sanitized: &str,
+  backend: &WgBackend,
+  amnezia: bool,
+) -> Result<(), String> {
+  let mut addresses = Vec::new();
+  let mut mtu = "1420".to_string();
+  let mut wg_conf = String::new();
+  for line in sanitized.lines() {
+    let trimmed = line.trim();
+    let (key, value) = match trimmed.split_once('=') {
+      Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+      None => {
+        wg_conf.push_str(line);
+        wg_conf.push('\n');
+        continue;
+      }
+    };
+    match key.as_str() {
+      "address" => addresses.extend(
+        value
+          .split(',')
+          .map(|addr| addr.trim().to_string())
+          .filter(|addr| !addr.is_empty()),
+      ),
+      "mtu" => mtu = value.to_string(),
+      "dns" | "table" | "preup" | "postup" | "predown" | "postdown" | "saveconfig" => {}
+      _ => {
+        wg_conf.push_str(line);
+        wg_conf.push('\n');
+      }
+    }
+  }
+
+  match backend {
+    WgBackend::Kernel => {
+      run_cmd("ip", &["link", "add", ifname, "type", kernel_module(amnezia)])?
+    }
+    WgBackend::Userspace(implementation) => {
+      run_cmd(&implementation.to_string_lossy(), &[ifname])?
+    }
+  }
+  run_cmd_with_input(wg_tool(amnezia), &["setconf", ifname, "/dev/stdin"], &wg_conf)?;
+  for address in &addresses {
+    run_cmd("ip", &["address", "add", address, "dev", ifname])?;
+  }
+  run_cmd("ip", &["link", "set", "mtu", &mtu, "up", "dev", ifname])?;
+  Ok(())
+}
+
+fn dry_run() -> bool {
+  DRY_RUN.load(Ordering::Relaxed)
+}
+
+struct NetNames {
+  ns: String,
+  veth_host: String,
+  veth_ns: String,
+  netns_etc: String,
+  state_dir: String,
+  fallback_state_dir: String,
+}
+
+fn net_names() -> &'static NetNames {
+  static NAMES: OnceLock<NetNames> = OnceLock::new();
+  NAMES.get_or_init(|| match test_netns() {
+    Some(ns) => NetNames {
+      veth_host: format!("{ns}h"),
+      veth_ns: format!("{ns}n"),
+      netns_etc: format!("/etc/netns/{ns}"),
+      state_dir: format!("/run/{ns}"),
+      fallback_state_dir: format!("/dev/shm/{ns}"),
+      ns,
+    },
+    None => NetNames {
+      ns: NS_NAME.into(),
+      veth_host: VETH_HOST.into(),
+      veth_ns: VETH_NS.into(),
+      netns_etc: NETNS_ETC_DIR.into(),
+      state_dir: STATE_DIR.into(),
+      fallback_state_dir: FALLBACK_STATE_DIR.into(),
+    },
+  })
+}
+
+#[cfg(feature = "netns-tests")]
+fn test_netns() -> Option<String> {
+  std::env::var("SILLYVPN_TEST_NETNS").ok().filter(|name| {
+    (1..=12).contains(&name.len())
+      && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+  })
+}
+
+#[cfg(not(feature = "netns-tests"))]
+fn test_netns() -> Option<String> {
+  None
+}
+
+fn ns_name() -> &'static str {
+  &net_names().ns
+}
+
+fn veth_host() -> &'static str {
+  &net_names().veth_host
+}
 
-  cleanup_best_effort();
-  write_ip_forward(&state.ip_forward_prev)?;
-  let _ = fs::remove_file(STATE_FILE);
-  Ok(())
+fn veth_ns() -> &'static str {
+  &net_names().veth_ns
 }
 
-fn run_in_namespace(bin: &Path, envs: &[(String, String)]) -> Result<(), String> {
-  if !bin.exists() {
-    return Err("binary does not exist".into());
-  }
-  let (launcher, use_setsid) = find_setsid();
-  let mut cmd = if use_setsid {
-    let mut cmd = Command::new(launcher);
-    cmd.arg("/usr/bin/ip");
-    cmd
+fn netns_etc_dir() -> &'static str {
+  &net_names().netns_etc
+}
+
+enum WgBackend {
+  Kernel,
+  Userspace(PathBuf),
+}
+
+fn wg_tool(amnezia: bool) -> &'static str {
+  if amnezia {
+    "awg"
   } else {
-    Command::new("/usr/bin/ip")
-  };
-  cmd.args(["netns", "exec", NS_NAME]);
-  if let Some((uid, gid)) = caller_identity() {
-    if let Some(setpriv) = find_setpriv() {
-      cmd.arg(setpriv);
-      cmd.args([
-        "--reuid",
-        &uid,
-        "--regid",
-        &gid,
-        "--init-groups",
-        "--inh-caps",
-        "-all",
-      ]);
-    }
+    "wg"
   }
-  cmd.arg(bin);
-  for (key, value) in envs {
-    cmd.env(key, value);
+}
+
+fn wg_quick_tool(amnezia: bool) -> &'static str {
+  if amnezia {
+    "awg-quick"
+  } else {
+    "wg-quick"
   }
-  cmd.stdin(Stdio::null());
-  cmd.stdout(Stdio::null());
-  cmd.stderr(Stdio::null());
-  cmd.spawn().map_err(|e| e.to_string())?;
-  Ok(())
 }
 
-fn sanitize_config(original: &Path, dest: &Path) -> Result<(PathBuf, Vec<String>), String> {
-  let mut content = String::new();
-  fs::File::open(original)
-    .map_err(|e| e.to_string())?
-    .read_to_string(&mut content)
-    .map_err(|e| e.to_string())?;
-  let dns_servers = extract_dns_servers(&content);
-  let has_table = content.lines().any(|line| {
-    let normalized = line.trim().replace(' ', "").to_ascii_lowercase();
-    normalized == "table=off"
-  });
+fn kernel_module(amnezia: bool) -> &'static str {
+  if amnezia {
+    "amneziawg"
+  } else {
+    "wireguard"
+  }
+}
 
-  let mut output = String::new();
-  let mut inserted = false;
-  for line in content.lines() {
+fn uses_amnezia(config: &str) -> bool {
+  let mut in_interface = false;
+  for line in config.lines() {
     let trimmed = line.trim();
-    let lower = trimmed.to_ascii_lowercase();
-    if lower.starts_with("dns=") || lower.starts_with("dns =") {
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      in_interface = trimmed.eq_ignore_ascii_case("[interface]");
       continue;
     }
-    output.push_str(line);
-    output.push('\n');
-    if !inserted && trimmed == "[Interface]" {
-      if !has_table {
-        output.push_str("Table = off\n");
+    if let Some((key, _)) = split_key_value(trimmed) {
+      if in_interface && AMNEZIA_KEYS.contains(&key.as_str()) {
+        return true;
       }
-      inserted = true;
     }
   }
+  false
+}
 
-  fs::write(dest, output).map_err(|e| e.to_string())?;
-  let mut perms = fs::metadata(dest).map_err(|e| e.to_string())?.permissions();
-  perms.set_mode(0o600);
-  fs::set_permissions(dest, perms).map_err(|e| e.to_string())?;
-  Ok((dest.to_path_buf(), dns_servers))
+fn split_key_value(line: &str) -> Option<(String, &str)> {
+  if line.starts_with('#') || line.starts_with(';') {
+    return None;
+  }
+  let (key, value) = line.split_once('=')?;
+  Some((key.trim().to_ascii_lowercase(), value.trim()))
+}
+
+fn wireguard_backend(amnezia: bool) -> Result<WgBackend, String> {
+  let module = Path::new("/sys/module").join(kernel_module(amnezia));
+  if module.exists() {
+    return Ok(WgBackend::Kernel);
+  }
+  if !dry_run() && run_cmd("modprobe", &[kernel_module(amnezia)]).is_ok() && module.exists() {
+    return Ok(WgBackend::Kernel);
+  }
+  if amnezia {
+    return find_in_path("amneziawg-go").map(WgBackend::Userspace).ok_or_else(|| {
+      "config uses AmneziaWG parameters (Jc/Jmin/Jmax/S1/S2/H1-H4) but neither the amneziawg kernel module nor amneziawg-go is available".to_string()
+    });
+  }
+  ["wireguard-go", "boringtun-cli", "boringtun"]
+    .into_iter()
+    .find_map(find_in_path)
+    .map(WgBackend::Userspace)
+    .ok_or_else(|| {
+      "wireguard kernel module is not available and no userspace implementation (wireguard-go, boringtun) was found".to_string()
+    })
+}
+
+fn check_dependencies(host_dns: bool, amnezia: bool) -> Result<(), String> {
+  let mut required = vec!["ip", "iptables", wg_tool(amnezia), wg_quick_tool(amnezia)];
+  if host_dns {
+    required.push("dnsmasq");
+  }
+  let missing: Vec<&str> = required
+    .into_iter()
+    .filter(|name| find_in_path(name).is_none())
+    .collect();
+  if missing.is_empty() {
+    Ok(())
+  } else if amnezia {
+    Err(format!(
+      "config uses AmneziaWG parameters (Jc/Jmin/Jmax/S1/S2/H1-H4), missing dependencies: {}",
+      missing.join(", ")
+    ))
+  } else {
+    Err(format!("missing dependencies: {}", missing.join(", ")))
+  }
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+  let path = std::env::var("PATH").unwrap_or_default();
+  let found = path
+    .split(':')
+    .chain(["/usr/sbin", "/sbin", "/usr/bin", "/bin"])
+    .map(|dir| Path::new(dir).join(name))
+    .find(|candidate| candidate.is_file());
+  found
+}
+
+trait CommandRunner {
+  fn output(&self, cmd: &str, args: &[&str], input: Option<&str>) -> Result<Output, String>;
+  fn read_file(&self, path: &Path) -> io::Result<String>;
+  fn write_file(&self, path: &Path, contents: &str) -> io::Result<()>;
+  fn remove(&self, path: &Path) -> io::Result<()>;
+  fn exists(&self, path: &Path) -> bool;
+}
+
+struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+  fn output(&self, cmd: &str, args: &[&str], input: Option<&str>) -> Result<Output, String> {
+    let mut command = Command::new(cmd);
+    command.args(args);
+    let Some(input) = input else {
+      return output_with_timeout(command);
+    };
+    let mut child = command
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| format!("{cmd} failed to start: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin
+        .write_all(input.as_bytes())
+        .map_err(|e| format!("{cmd} stdin: {e}"))?;
+    }
+    child
+      .wait_with_output()
+      .map_err(|e| format!("{cmd} failed: {e}"))
+  }
+
+  fn read_file(&self, path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+  }
+
+  fn write_file(&self, path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)
+  }
+
+  fn remove(&self, path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+      fs::remove_dir(path)
+    } else {
+      fs::remove_file(path)
+    }
+  }
+
+  fn exists(&self, path: &Path) -> bool {
+    path.exists()
+  }
+}
+
+thread_local! {
+  static RUNNER: RefCell<Rc<dyn CommandRunner>> = RefCell::new(Rc::new(SystemRunner));
+}
+
+fn runner() -> Rc<dyn CommandRunner> {
+  RUNNER.with(|runner| runner.borrow().clone())
+}
+
+#[cfg(test)]
+fn set_runner(next: Rc<dyn CommandRunner>) {
+  RUNNER.with(|runner| *runner.borrow_mut() = next);
+}
+
+fn run_cmd_with_input(cmd: &str, args: &[&str], input: &str) -> Result<(), String> {
+  if dry_run() {
+    println!("{cmd} {} (config on stdin)", args.join(" "));
+    return Ok(());
+  }
+  trace_cmd(cmd, args);
+  let output = runner().output(cmd, args, Some(input))?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(record_failure(cmd, args, &output))
+  }
+}
+
+fn record_failure(cmd: &str, args: &[&str], output: &std::process::Output) -> String {
+  let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+  let message = format!("{cmd} error: {stderr}");
+  if let Ok(mut failure) = LAST_FAILURE.lock() {
+    failure.get_or_insert(FailedCommand {
+      command: format!("{cmd} {}", args.join(" ")),
+      exit_code: output.status.code(),
+      stderr,
+    });
+  }
+  message
+}
+
+fn best_effort<T>(f: impl FnOnce() -> T) -> T {
+  let recorded = LAST_FAILURE.lock().ok().and_then(|mut failure| failure.take());
+  let value = f();
+  if let Ok(mut failure) = LAST_FAILURE.lock() {
+    *failure = recorded;
+  }
+  value
+}
+
+fn run_nat(cmd: &str, args: &[&str]) -> Result<(), String> {
+  if !MASQUERADE.load(Ordering::Relaxed) {
+    return Ok(());
+  }
+  run_cmd(cmd, args)
+}
+
+fn trace_cmd(cmd: &str, args: &[&str]) {
+  if DEBUG.load(Ordering::Relaxed) {
+    eprintln!("+ {cmd} {}", args.join(" "));
+  }
 }
 
 fn run_cmd(cmd: &str, args: &[&str]) -> Result<(), String> {
-  let output = Command::new(cmd)
-    .args(args)
-    .output()
-    .map_err(|e| format!("{cmd} failed to start: {e}"))?;
+  if dry_run() {
+    println!("{cmd} {}", args.join(" "));
+    return Ok(());
+  }
+  trace_cmd(cmd, args);
+  let output = runner().output(cmd, args, None)?;
   if output.status.success() {
     Ok(())
   } else {
-    Err(format!(
-      "{cmd} error: {}",
-      String::from_utf8_lossy(&output.stderr)
-    ))
+    Err(record_failure(cmd, args, &output))
   }
 }
 
-fn parse_env_pair(pair: &str) -> Result<Option<(String, String)>, String> {
+fn parse_env_pair(pair: &str, extra_keys: &[String]) -> Result<Option<(String, String)>, String> {
   let mut parts = pair.splitn(2, '=');
   let key = parts.next().unwrap_or("").trim();
   let value = parts.next().unwrap_or("").to_string();
   if key.is_empty() {
     return Err("empty env key".into());
   }
-  if !allowed_env_key(key) {
+  if !allowed_env_key(key) && !extra_keys.iter().any(|extra| extra == key) {
     return Ok(None);
   }
   Ok(Some((key.to_string(), value)))
@@ -466,6 +3115,16 @@ fn allowed_env_key(key: &str) -> bool {
   )
 }
 
+fn is_safe_extra_env_key(key: &str) -> bool {
+  let mut chars = key.chars();
+  let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+  valid
+    && !key.starts_with("LD_")
+    && !key.starts_with("MALLOC_")
+    && !matches!(key, "GCONV_PATH" | "GLIBC_TUNABLES" | "LOCPATH" | "NLSPATH")
+}
+
 fn caller_identity() -> Option<(String, String)> {
   let uid = std::env::var("PKEXEC_UID").ok()?;
   let gid = gid_for_uid(&uid).unwrap_or_else(|| uid.clone());
@@ -496,6 +3155,21 @@ fn find_setpriv() -> Option<&'static str> {
   None
 }
 
+fn parse_rlimit(value: &str) -> Result<(String, u64), String> {
+  let (name, limit) = value
+    .split_once('=')
+    .ok_or_else(|| format!("invalid rlimit: {value}"))?;
+  if !RLIMIT_NAMES.contains(&name) {
+    return Err(format!("rlimit not supported: {name}"));
+  }
+  let limit = limit
+    .parse::<u64>()
+    .ok()
+    .filter(|limit| *limit > 0)
+    .ok_or_else(|| format!("invalid rlimit value: {value}"))?;
+  Ok((name.to_string(), limit))
+}
+
 fn find_setsid() -> (&'static str, bool) {
   if Path::new("/usr/bin/setsid").exists() {
     return ("/usr/bin/setsid", true);
@@ -506,55 +3180,133 @@ fn find_setsid() -> (&'static str, bool) {
   ("/usr/bin/ip", false)
 }
 
-fn read_ip_forward() -> Result<String, String> {
-  let mut content = String::new();
-  fs::File::open("/proc/sys/net/ipv4/ip_forward")
-    .map_err(|e| e.to_string())?
-    .read_to_string(&mut content)
-    .map_err(|e| e.to_string())?;
+fn read_ip_forward(path: &str) -> Result<String, String> {
+  let content = runner().read_file(Path::new(path)).map_err(|e| e.to_string())?;
   Ok(content.trim().to_string())
 }
 
-fn write_ip_forward(value: &str) -> Result<(), String> {
-  fs::File::create("/proc/sys/net/ipv4/ip_forward")
-    .and_then(|mut file| file.write_all(value.as_bytes()))
+fn write_ip_forward(path: &str, value: &str) -> Result<(), String> {
+  if dry_run() {
+    println!("write {path} = {value}");
+    return Ok(());
+  }
+  runner()
+    .write_file(Path::new(path), value)
     .map_err(|e| e.to_string())
 }
 
-fn write_state(state: &HelperState) -> Result<(), String> {
-  let json = format!(
-    "{{\"wg_ifname\":\"{}\",\"config_path\":\"{}\",\"temp_config\":\"{}\",\"ip_forward_prev\":\"{}\"}}",
-    state.wg_ifname, state.config_path, state.temp_config, state.ip_forward_prev
-  );
-  fs::write(STATE_FILE, json).map_err(|e| e.to_string())?;
+fn write_state(state_dir: &Path, state: &HelperState) -> Result<(), String> {
+  let json = serde_json::to_string(state).map_err(|e| e.to_string())?;
+  let path = state_dir.join(STATE_FILE);
+  if dry_run() {
+    println!("write {}", path.display());
+    return Ok(());
+  }
+  fs::write(&path, json).map_err(|e| format!("cannot write {}: {e}", path.display()))?;
   Ok(())
 }
 
-fn read_state() -> Result<HelperState, String> {
+fn read_state(state_dir: &Path) -> Result<HelperState, String> {
   let mut content = String::new();
-  fs::File::open(STATE_FILE)
+  fs::File::open(state_dir.join(STATE_FILE))
     .map_err(|e| e.to_string())?
     .read_to_string(&mut content)
     .map_err(|e| e.to_string())?;
-  let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
-  Ok(HelperState {
-    wg_ifname: value["wg_ifname"].as_str().unwrap_or("wg0").to_string(),
-    config_path: value["config_path"].as_str().unwrap_or("").to_string(),
-    temp_config: value["temp_config"].as_str().unwrap_or("").to_string(),
-    ip_forward_prev: value["ip_forward_prev"].as_str().unwrap_or("0").to_string(),
-  })
+  serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+fn deserialize_bypass<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  let routes = match serde_json::Value::deserialize(deserializer)? {
+    serde_json::Value::String(joined) => joined
+      .split(',')
+      .filter(|route| !route.is_empty())
+      .map(|route| route.to_string())
+      .collect(),
+    serde_json::Value::Array(items) => items
+      .into_iter()
+      .filter_map(|item| item.as_str().map(|route| route.to_string()))
+      .collect(),
+    _ => Vec::new(),
+  };
+  Ok(routes)
+}
+
+fn is_root_owned_dir(dir: &Path) -> bool {
+  fs::symlink_metadata(dir).is_ok_and(|meta| meta.is_dir() && meta.uid() == 0)
+}
+
+/// `/run/sillyvpn`, then a root-created `/dev/shm` fallback for systems where
+/// `/run` is read-only or missing; the one holding the state file comes first.
+fn state_dir_candidates() -> Vec<PathBuf> {
+  let names = net_names();
+  let mut dirs = vec![
+    PathBuf::from(&names.state_dir),
+    PathBuf::from(&names.fallback_state_dir),
+  ];
+  dirs.sort_by_key(|dir| !(is_root_owned_dir(dir) && dir.join(STATE_FILE).exists()));
+  dirs
+}
+
+fn state_dir() -> PathBuf {
+  let candidates = state_dir_candidates();
+  candidates
+    .iter()
+    .find(|dir| is_root_owned_dir(dir))
+    .unwrap_or(&candidates[0])
+    .clone()
+}
+
+fn prepare_state_dir() -> Result<PathBuf, String> {
+  let mut errors = Vec::new();
+  for dir in state_dir_candidates() {
+    if dry_run() {
+      println!("mkdir -p {}", dir.display());
+      return Ok(dir);
+    }
+    match ensure_writable_dir(&dir) {
+      Ok(()) => return Ok(dir),
+      Err(err) => errors.push(format!("{}: {err}", dir.display())),
+    }
+  }
+  Err(format!(
+    "no usable root-owned runtime directory ({}); check that /run or /dev/shm is a writable tmpfs",
+    errors.join("; ")
+  ))
+}
+
+fn ensure_writable_dir(dir: &Path) -> Result<(), String> {
+  fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  if !is_root_owned_dir(dir) {
+    return Err("not a directory owned by root".into());
+  }
+  fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+  let probe = dir.join(".write-test");
+  fs::write(&probe, b"").map_err(|e| format!("not writable: {e}"))?;
+  let _ = fs::remove_file(&probe);
+  Ok(())
 }
 
 fn cleanup_best_effort() {
-  let _ = run_cmd("ip", &["link", "del", VETH_HOST]);
-  let _ = run_cmd("ip", &["netns", "del", NS_NAME]);
-  let _ = cleanup_dns_for_namespace();
+  best_effort(|| {
+    stop_dns_forwarder();
+    let _ = run_cmd("ip", &["link", "del", veth_host()]);
+    let _ = run_cmd("ip", &["netns", "del", ns_name()]);
+    let _ = cleanup_dns_for_namespace();
+  });
 }
 
-fn setup_dns_for_namespace(dns_servers: &[String]) -> Result<(), String> {
-  fs::create_dir_all(NETNS_ETC_DIR).map_err(|e| e.to_string())?;
+fn setup_dns_for_namespace(
+  dns_servers: &[String],
+  host_dns: bool,
+  resolv_options: Option<&str>,
+) -> Result<(), String> {
   let mut lines = String::new();
-  if dns_servers.is_empty() {
+  if host_dns {
+    lines.push_str(&format!("nameserver {}\n", veth_addr(1)));
+  } else if dns_servers.is_empty() {
     lines.push_str("nameserver 1.1.1.1\n");
     lines.push_str("nameserver 8.8.8.8\n");
   } else {
@@ -562,8 +3314,89 @@ fn setup_dns_for_namespace(dns_servers: &[String]) -> Result<(), String> {
       lines.push_str(&format!("nameserver {server}\n"));
     }
   }
-  fs::write(format!("{NETNS_ETC_DIR}/resolv.conf"), lines).map_err(|e| e.to_string())?;
-  Ok(())
+  if let Some(options) = resolv_options {
+    lines.push_str(&format!("options {options}\n"));
+  }
+  if dry_run() {
+    println!("write {}/resolv.conf: {}", netns_etc_dir(), lines.trim().replace('\n', ", "));
+    return Ok(());
+  }
+  let resolv_conf = Path::new(netns_etc_dir()).join("resolv.conf");
+  runner()
+    .write_file(&resolv_conf, &lines)
+    .map_err(|e| format!("cannot write {}: {e}", resolv_conf.display()))
+}
+
+fn resolved_available() -> bool {
+  Path::new(RESOLVED_SOCKET).exists() && find_in_path("resolvectl").is_some()
+}
+
+fn setup_resolved_scope(ifname: &str, dns_servers: &[String]) -> Result<(), String> {
+  let defaults = ["1.1.1.1".to_string(), "8.8.8.8".to_string()];
+  let servers = if dns_servers.is_empty() {
+    &defaults[..]
+  } else {
+    dns_servers
+  };
+  let mut args = vec!["dns", ifname];
+  args.extend(servers.iter().map(String::as_str));
+  run_cmd("resolvectl", &args)?;
+  run_cmd("resolvectl", &["domain", ifname, "~."])
+}
+
+fn is_valid_resolv_options(options: &str) -> bool {
+  !options.trim().is_empty()
+    && !options.contains(['\n', '\r'])
+    && options.split_whitespace().all(|option| {
+      option
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'))
+    })
+}
+
+fn start_dns_forwarder(state_dir: &Path) -> Result<(), String> {
+  let dnsmasq = ["/usr/sbin/dnsmasq", "/usr/bin/dnsmasq", "/sbin/dnsmasq"]
+    .into_iter()
+    .find(|path| Path::new(path).exists())
+    .ok_or("dnsmasq not found, it is required for host DNS mode")?;
+  for proto in ["udp", "tcp"] {
+    run_cmd(
+      "iptables",
+      &["-A", "INPUT", "-i", veth_host(), "-p", proto, "--dport", "53", "-j", "ACCEPT"],
+    )?;
+  }
+  run_cmd(
+    dnsmasq,
+    &[
+      "--conf-file=/dev/null",
+      &format!("--listen-address={}", veth_addr(1)),
+      "--bind-interfaces",
+      "--except-interface=lo",
+      &format!("--no-dhcp-interface={}", veth_host()),
+      &format!("--pid-file={}", state_dir.join(DNSMASQ_PID_FILE).display()),
+    ],
+  )
+}
+
+fn stop_dns_forwarder() {
+  if dry_run() {
+    return;
+  }
+  let pid_file = state_dir().join(DNSMASQ_PID_FILE);
+  if let Ok(pid) = runner().read_file(&pid_file) {
+    if let Ok(pid) = pid.trim().parse::<i32>() {
+      unsafe {
+        libc::kill(pid, libc::SIGTERM);
+      }
+    }
+    let _ = runner().remove(&pid_file);
+  }
+  for proto in ["udp", "tcp"] {
+    let _ = run_cmd(
+      "iptables",
+      &["-D", "INPUT", "-i", veth_host(), "-p", proto, "--dport", "53", "-j", "ACCEPT"],
+    );
+  }
 }
 
 fn extract_dns_servers(content: &str) -> Vec<String> {
@@ -589,7 +3422,361 @@ fn extract_dns_servers(content: &str) -> Vec<String> {
 }
 
 fn cleanup_dns_for_namespace() -> Result<(), String> {
-  let _ = fs::remove_file(format!("{NETNS_ETC_DIR}/resolv.conf"));
-  let _ = fs::remove_dir(NETNS_ETC_DIR);
+  if dry_run() {
+    return Ok(());
+  }
+  let runner = runner();
+  let _ = runner.remove(&Path::new(netns_etc_dir()).join("resolv.conf"));
+  let _ = runner.remove(Path::new(netns_etc_dir()));
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  #[derive(Default)]
+  struct MockRunner {
+    failing: Vec<String>,
+    calls: RefCell<Vec<String>>,
+    files: RefCell<HashMap<PathBuf, String>>,
+    stdout: RefCell<HashMap<String, String>>,
+  }
+
+  impl MockRunner {
+    fn install(failing: &[&str]) -> Rc<MockRunner> {
+      let runner = Rc::new(MockRunner {
+        failing: failing.iter().map(|cmd| cmd.to_string()).collect(),
+        ..MockRunner::default()
+      });
+      set_runner(runner.clone());
+      runner
+    }
+
+    fn calls(&self) -> Vec<String> {
+      self.calls.borrow().clone()
+    }
+
+    fn file(&self, path: &str) -> Option<String> {
+      self.files.borrow().get(Path::new(path)).cloned()
+    }
+
+    fn set_file(&self, path: &str, contents: &str) {
+      self.files.borrow_mut().insert(PathBuf::from(path), contents.to_string());
+    }
+
+    fn set_stdout(&self, command: &str, stdout: &str) {
+      self.stdout.borrow_mut().insert(command.to_string(), stdout.to_string());
+    }
+  }
+
+  impl CommandRunner for MockRunner {
+    fn output(&self, cmd: &str, args: &[&str], _input: Option<&str>) -> Result<Output, String> {
+      let line = format!("{cmd} {}", args.join(" "));
+      let failed = self.failing.iter().any(|prefix| line.starts_with(prefix.as_str()));
+      let stdout = self.stdout.borrow().get(&line).cloned().unwrap_or_default();
+      self.calls.borrow_mut().push(line);
+      Ok(Output {
+        status: std::process::ExitStatus::from_raw(if failed { 1 << 8 } else { 0 }),
+        stdout: stdout.into_bytes(),
+        stderr: if failed { b"mock failure".to_vec() } else { Vec::new() },
+      })
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+      self.files.borrow().get(path).cloned().ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    fn write_file(&self, path: &Path, contents: &str) -> io::Result<()> {
+      self.files.borrow_mut().insert(path.to_path_buf(), contents.to_string());
+      Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+      self.files.borrow_mut().remove(path).map(|_| ()).ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+      self.files.borrow().contains_key(path)
+    }
+  }
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sillyvpn-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn state_round_trips_paths_with_quotes_and_unicode() {
+    let dir = temp_dir("state");
+    let state = HelperState {
+      wg_ifname: "wg-test".to_string(),
+      config_path: "/home/user/конфиги \"vpn\"/\\tunnel.conf".to_string(),
+      temp_config: dir.join("wg-test.conf").to_string_lossy().to_string(),
+      bypass: vec!["192.168.0.0/16".to_string(), "fd00::/8".to_string()],
+      ipv6: true,
+      table: 100,
+      fwmark: 0x77,
+      ..HelperState::default()
+    };
+    write_state(&dir, &state).unwrap();
+    let restored = read_state(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(restored.config_path, state.config_path);
+    assert_eq!(restored.temp_config, state.temp_config);
+    assert_eq!(restored.bypass, state.bypass);
+    assert_eq!(restored.wg_ifname, "wg-test");
+    assert!(restored.ipv6);
+    assert_eq!((restored.table, restored.fwmark), (100, 0x77));
+  }
+
+  const TUNNEL_V4: &str = "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\nAddress = 10.99.0.2/32\n\n[Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\nAllowedIPs = 0.0.0.0/0\nEndpoint = 192.0.2.1:51820\n";
+  const TUNNEL_DUAL: &str = "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\nAddress = 10.99.0.2/32, fd00:99::2/128\n\n[Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\nAllowedIPs = 0.0.0.0/0, ::/0\nEndpoint = 192.0.2.1:51820\n";
+
+  fn bring_up_mock(
+    name: &str,
+    config: &str,
+    bypass: &[String],
+    failing: &[&str],
+  ) -> (Rc<MockRunner>, Result<(), String>) {
+    let runner = MockRunner::install(failing);
+    runner.set_file(IPV4_FORWARD, "0\n");
+    runner.set_file(IPV6_FORWARD, "0\n");
+    let dir = temp_dir(name);
+    let result = bring_up(&BringUp {
+      state_dir: &dir,
+      ifname: "wg-test",
+      bypass,
+      sanitized: config,
+      dns_servers: &[],
+      host_dns: false,
+      resolv_options: None,
+      resolved_scope: false,
+      auto_mtu: false,
+      external_routes: false,
+      ipv6: carries_ipv6(config),
+      backend: &WgBackend::Kernel,
+      config_path: "stdin",
+      temp_config: None,
+      amnezia: false,
+    });
+    fs::remove_dir_all(&dir).unwrap();
+    (runner, result)
+  }
+
+  #[test]
+  fn dual_stack_bring_up_installs_ipv6_routing() {
+    let (runner, result) = bring_up_mock("dual-stack", TUNNEL_DUAL, &[], &[]);
+    result.unwrap();
+    let calls = runner.calls();
+    let mark = fwmark();
+    let table = table_id();
+    let rule = format!("ip -6 rule add fwmark {mark} table {table} priority {FWMARK_RULE_PRIORITY}");
+    assert!(calls.contains(&rule), "{calls:#?}");
+    assert!(calls.contains(&format!("ip -6 route add default dev wg-test table {table}")));
+    assert!(calls.contains(&format!(
+      "ip6tables -t mangle -A PREROUTING -i {} -j MARK --set-mark {mark}",
+      veth_host()
+    )));
+    assert!(calls.contains(&format!("ip6tables -A FORWARD -i {} -o wg-test -j ACCEPT", veth_host())));
+    assert!(calls.contains(&format!("ip6tables -A FORWARD -i wg-test -o {} -j ACCEPT", veth_host())));
+    assert_eq!(runner.file(IPV6_FORWARD).as_deref(), Some("1"));
+  }
+
+  #[test]
+  fn ipv4_only_bring_up_leaves_ipv6_alone() {
+    let (runner, result) = bring_up_mock("v4-only", TUNNEL_V4, &[], &[]);
+    result.unwrap();
+    let calls = runner.calls();
+    assert!(calls.iter().any(|call| call.starts_with("ip rule add fwmark")), "{calls:#?}");
+    assert!(!calls.iter().any(|call| call.starts_with("ip -6") || call.starts_with("ip6tables")), "{calls:#?}");
+    assert_eq!(runner.file(IPV6_FORWARD).as_deref(), Some("0\n"));
+  }
+
+  #[test]
+  fn bypass_rules_outrank_the_full_tunnel_default() {
+    let bypass = ["192.168.0.0/16".to_string(), "fd00::/8".to_string()];
+    let (runner, result) = bring_up_mock("bypass", TUNNEL_DUAL, &bypass, &[]);
+    result.unwrap();
+    let calls = runner.calls();
+    let (mark, table) = (fwmark(), table_id());
+    let position = |call: String| {
+      calls
+        .iter()
+        .position(|made| *made == call)
+        .unwrap_or_else(|| panic!("{call} not run: {calls:#?}"))
+    };
+    let bypass_v4 = position(format!(
+      "ip rule add to 192.168.0.0/16 fwmark {mark} lookup main priority {BYPASS_RULE_PRIORITY}"
+    ));
+    let bypass_v6 = position(format!(
+      "ip -6 rule add to fd00::/8 fwmark {mark} lookup main priority {BYPASS_RULE_PRIORITY}"
+    ));
+    let default_v4 = position(format!("ip route add default dev wg-test table {table}"));
+    let default_v6 = position(format!("ip -6 route add default dev wg-test table {table}"));
+    assert!(bypass_v4 < default_v4 && bypass_v6 < default_v6);
+    assert!(
+      BYPASS_RULE_PRIORITY.parse::<u32>().unwrap() < FWMARK_RULE_PRIORITY.parse::<u32>().unwrap()
+    );
+  }
+
+  #[test]
+  fn disable_restores_previous_ipv6_forwarding() {
+    let runner = MockRunner::install(&[]);
+    runner.set_file(IPV4_FORWARD, "1");
+    runner.set_file(IPV6_FORWARD, "1");
+    let dir = temp_dir("disable-v6");
+    let state = HelperState {
+      wg_ifname: "wg-test".to_string(),
+      ip_forward_prev: "0".to_string(),
+      ip6_forward_prev: "0".to_string(),
+      ipv6: true,
+      veth_subnet: veth_subnet(),
+      ..HelperState::default()
+    };
+    write_state(&dir, &state).unwrap();
+    disable_in(&dir).unwrap();
+    let state_left = dir.join(STATE_FILE).exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(!state_left);
+    assert_eq!(runner.file(IPV4_FORWARD).as_deref(), Some("0"));
+    assert_eq!(runner.file(IPV6_FORWARD).as_deref(), Some("0"));
+    let calls = runner.calls();
+    assert!(calls.contains(&"ip -6 route del default dev wg-test table 51820".to_string()), "{calls:#?}");
+  }
+
+  #[test]
+  fn random_subnet_skips_taken_networks() {
+    let runner = MockRunner::install(&[]);
+    runner.set_stdout(
+      "ip -4 route show table all",
+      "10.20.30.0/24 dev eth1 proto kernel scope link src 10.20.30.5\n",
+    );
+    runner.set_stdout("ip -4 -o addr show", "3: wlan0    inet 10.21.0.7/16 brd 10.21.255.255\n");
+    let config = "[Interface]\nAddress = 10.22.1.2/32\n";
+    let random = [4, 30, 5, 9, 6, 1, 7, 40];
+    assert_eq!(pick_free_subnet(config, &[], &random), Ok(0x0a17_2800));
+    assert!(pick_free_subnet(config, &[], &random[..6]).is_err());
+  }
+
+  #[test]
+  fn failed_bring_up_restores_forwarding() {
+    let (runner, result) = bring_up_mock("rollback", TUNNEL_DUAL, &[], &["ip netns add"]);
+    assert!(result.unwrap_err().contains("mock failure"));
+    assert_eq!(runner.file(IPV4_FORWARD).as_deref(), Some("0"));
+    assert_eq!(runner.file(IPV6_FORWARD).as_deref(), Some("0"));
+    let calls = runner.calls();
+    assert!(calls.contains(&"ip link del wg-test".to_string()), "{calls:#?}");
+    assert!(!calls.iter().any(|call| call.starts_with("ip link add wg-test")));
+  }
+
+  #[test]
+  fn leftover_interface_is_force_removed() {
+    let runner = MockRunner::install(&[]);
+    runner.set_file("/sys/class/net/wg-stale", "");
+    assert!(force_remove_interface("wg-stale", &[], true));
+    let calls = runner.calls();
+    assert_eq!(calls.first().map(String::as_str), Some("ip link del wg-stale"));
+    assert!(calls.contains(&format!("ip rule del fwmark {} table {}", fwmark(), table_id())), "{calls:#?}");
+    assert!(calls.iter().any(|call| call.starts_with("ip -6 rule del")), "{calls:#?}");
+
+    let runner = MockRunner::install(&[]);
+    assert!(!force_remove_interface("wg-gone", &[], false));
+    assert!(runner.calls().is_empty());
+  }
+
+  #[test]
+  fn amnezia_is_detected_from_interface_keys_only() {
+    assert!(uses_amnezia("[Interface]\nJc = 4\n"));
+    assert!(uses_amnezia("[interface]\nPrivateKey = k\nh1=1\n"));
+    assert!(!uses_amnezia("[Interface]\n# Jc = 4\n; Jmin = 1\n"));
+    assert!(!uses_amnezia("[Peer]\nJc = 4\n"));
+    assert!(!uses_amnezia("[Interface\nJc = 4\n"));
+  }
+
+  #[test]
+  fn sanitizer_rejects_out_of_range_listen_port() {
+    let config = |port: &str| format!("[Interface]\nListenPort = {port}\nAddress = 10.99.0.2/32\n");
+    assert!(sanitize_config(&config("51820"), true).is_ok());
+    assert!(sanitize_config(&config("0"), true).is_ok());
+    let err = sanitize_config(&config("70000"), true).unwrap_err();
+    assert!(err.contains("ListenPort"), "{err}");
+  }
+
+  #[test]
+  fn sanitizer_strips_save_config() {
+    for line in ["SaveConfig = true", "saveconfig=true", "SAVECONFIG = false"] {
+      let config = format!("[Interface]\nPrivateKey = key\n{line}\nAddress = 10.99.0.2/32\n\n[Peer]\nPublicKey = peer\n");
+      let (sanitized, _) = sanitize_config(&config, true).unwrap();
+      assert!(!sanitized.to_ascii_lowercase().contains("saveconfig"), "{sanitized}");
+      assert_eq!(
+        sanitized,
+        "[Interface]\nTable = off\nPrivateKey = key\nAddress = 10.99.0.2/32\n\n[Peer]\nPublicKey = peer\n"
+      );
+    }
+  }
+
+  #[test]
+  fn sanitizer_normalizes_crlf_and_bom() {
+    let config = "\u{feff}[Interface]\r\nPrivateKey = key\r\nDNS = 10.8.0.1\r\nAddress = 10.99.0.2/32\r\n\r\n[Peer]\rPublicKey = peer\r\n";
+    let (sanitized, dns) = sanitize_config(config, true).unwrap();
+    assert!(!sanitized.contains('\r') && !sanitized.contains('\u{feff}'), "{sanitized:?}");
+    assert_eq!(
+      sanitized,
+      "[Interface]\nTable = off\nPrivateKey = key\nAddress = 10.99.0.2/32\n\n[Peer]\nPublicKey = peer\n"
+    );
+    assert_eq!(dns, ["10.8.0.1"]);
+  }
+
+  #[test]
+  fn table_is_injected_wherever_interface_appears() {
+    let config = "# provider export\n\n[Peer]\nPublicKey = peer\nAllowedIPs = 0.0.0.0/0\n\n  [interface]\nTable = 1234\nAddress = 10.99.0.2/32\n";
+    let (sanitized, _) = sanitize_config(config, true).unwrap();
+    assert_eq!(
+      sanitized,
+      "# provider export\n\n[Peer]\nPublicKey = peer\nAllowedIPs = 0.0.0.0/0\n\n  [interface]\nTable = off\nAddress = 10.99.0.2/32\n"
+    );
+    let (untouched, _) = sanitize_config(config, false).unwrap();
+    assert!(untouched.contains("Table = 1234") && !untouched.contains("Table = off"), "{untouched}");
+  }
+
+  #[test]
+  fn sanitizer_requires_exactly_one_interface() {
+    let err = sanitize_config("# empty\n[Peer]\nPublicKey = peer\n", true).unwrap_err();
+    assert!(err.contains("no [Interface]"), "{err}");
+    let err = sanitize_config("[Interface]\nAddress = 10.99.0.2/32\n[INTERFACE]\nAddress = 10.99.0.3/32\n", true)
+      .unwrap_err();
+    assert!(err.contains("more than one"), "{err}");
+  }
+
+  #[test]
+  fn app_connmarks_skip_marks_taken_by_other_apps() {
+    let rules = "-P OUTPUT ACCEPT\n\
+      -A OUTPUT -m cgroup --path sillyvpn/firefox -m comment --comment sillyvpn-tx:firefox -j CONNMARK --set-xmark 0x1beef/0xffffffff\n\
+      -A OUTPUT -m cgroup --path sillyvpn/tg -m comment --comment \"sillyvpn-tx:tg\" -j CONNMARK --set-xmark 0x10001/0xffffffff\n";
+    let taken = parse_app_connmarks(rules);
+    assert_eq!(taken, [("firefox".to_string(), 0x1beef), ("tg".to_string(), 0x10001)]);
+    assert_eq!(app_connmark("firefox", &taken), Some(0x1beef));
+
+    let fresh = app_connmark("curl", &[]).unwrap();
+    let colliding = [("other".to_string(), fresh), ("next".to_string(), APP_CONNMARK_BASE | ((fresh + 1) & 0xffff))];
+    let mark = app_connmark("curl", &colliding).unwrap();
+    assert_eq!(mark, APP_CONNMARK_BASE | ((fresh + 2) & 0xffff));
+  }
+
+  #[test]
+  fn commands_and_sysctls_go_through_the_runner() {
+    let runner = MockRunner::install(&["ip link del"]);
+    runner.set_file(IPV4_FORWARD, "0\n");
+    run_cmd("ip", &["netns", "add", "test-ns"]).unwrap();
+    let err = run_cmd("ip", &["link", "del", "wg-test"]).unwrap_err();
+    assert!(err.contains("mock failure"), "{err}");
+    assert_eq!(read_ip_forward(IPV4_FORWARD).unwrap(), "0");
+    write_ip_forward(IPV4_FORWARD, "1").unwrap();
+    assert_eq!(runner.file(IPV4_FORWARD).as_deref(), Some("1"));
+    assert_eq!(runner.calls(), ["ip netns add test-ns", "ip link del wg-test"]);
+  }
+}
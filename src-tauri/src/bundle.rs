@@ -0,0 +1,85 @@
+use crate::models::StateBundle;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub const BUNDLE_FORMAT: &str = "sillyvpn-bundle";
+pub const BUNDLE_VERSION: u32 = 1;
+
+const OPENSSL_MAGIC: &[u8] = b"Salted__";
+const PASSPHRASE_ENV: &str = "SILLYVPN_BUNDLE_PASSPHRASE";
+
+pub fn write_bundle(dest: &Path, bundle: &StateBundle, passphrase: Option<&str>) -> Result<(), String> {
+  let json = serde_json::to_vec_pretty(bundle).map_err(|e| e.to_string())?;
+  let data = match passphrase {
+    Some(passphrase) => openssl_crypt(&json, passphrase, false)?,
+    None => json,
+  };
+  let mut file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(dest)
+    .map_err(|e| e.to_string())?;
+  file.write_all(&data).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+pub fn read_bundle(src: &Path, passphrase: Option<&str>) -> Result<StateBundle, String> {
+  let data = std::fs::read(src).map_err(|e| e.to_string())?;
+  let json = if data.starts_with(OPENSSL_MAGIC) {
+    let passphrase = passphrase.ok_or("bundle is encrypted, passphrase required")?;
+    openssl_crypt(&data, passphrase, true)?
+  } else {
+    data
+  };
+  let bundle: StateBundle =
+    serde_json::from_slice(&json).map_err(|e| format!("invalid bundle: {e}"))?;
+  if bundle.format != BUNDLE_FORMAT {
+    return Err("invalid bundle: unknown format".into());
+  }
+  if bundle.version > BUNDLE_VERSION {
+    return Err(format!(
+      "bundle version {} is newer than supported ({BUNDLE_VERSION})",
+      bundle.version
+    ));
+  }
+  for tunnel in &bundle.state.tunnels {
+    if !bundle.configs.contains_key(&tunnel.id) {
+      return Err(format!("invalid bundle: missing config for tunnel {}", tunnel.name));
+    }
+  }
+  Ok(bundle)
+}
+
+fn openssl_crypt(input: &[u8], passphrase: &str, decrypt: bool) -> Result<Vec<u8>, String> {
+  let mut cmd = Command::new("openssl");
+  cmd.args(["enc", "-aes-256-cbc", "-pbkdf2", "-iter", "200000", "-salt"]);
+  if decrypt {
+    cmd.arg("-d");
+  }
+  cmd.args(["-pass", &format!("env:{PASSPHRASE_ENV}")]);
+  let mut child = cmd
+    .env(PASSPHRASE_ENV, passphrase)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| format!("openssl failed to start: {e}"))?;
+  let mut stdin = child.stdin.take().ok_or("openssl stdin unavailable")?;
+  let input = input.to_vec();
+  let writer = std::thread::spawn(move || stdin.write_all(&input));
+  let output = child.wait_with_output().map_err(|e| e.to_string())?;
+  let _ = writer.join();
+  if !output.status.success() {
+    return Err(if decrypt {
+      "failed to decrypt bundle, check the passphrase".to_string()
+    } else {
+      format!("openssl error: {}", String::from_utf8_lossy(&output.stderr).trim())
+    });
+  }
+  Ok(output.stdout)
+}
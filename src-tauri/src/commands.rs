@@ -1,21 +1,53 @@
-use crate::helper_call::{run_helper_vec, HelperError};
-use crate::logging::append_log;
-use crate::models::AppStateFile;
+use crate::bundle::{read_bundle, write_bundle, BUNDLE_FORMAT, BUNDLE_VERSION};
+use crate::error::{CommandError, ErrorCode};
+use crate::helper_call::{
+  ensure_helper_installed, installed_helper_version, preview_helper, preview_install, run_helper_output, run_helper_probe,
+  run_helper_raw, run_helper_unprivileged, run_helper_vec, run_helper_with_input,
+  resolve_helper, set_debug_log, spawn_helper_stream, CommandPreview, HelperError,
+  HelperResolution, ProbeOutcome,
+};
+use crate::instance::{instance_status, InstanceStatus};
+use crate::logging::{
+  append_log, export_logs as write_log_export, list_log_generations as read_log_generations,
+  read_log_generation, remove_log_generation as delete_log_generation, LogGeneration,
+};
+use crate::models::{
+  AppItem, AppLimits, AppLiveOutput, AppStateFile, AppTraffic, BinaryKind, BundleImport, ConfigPermissionIssue, DiagnosticOutput,
+  DisableReport, DnsMode, EndpointReachability, KernelMessages, KillSignal, LastOperation, LivePeer,
+  NamespaceProcess, NamespaceProcesses, NatCheck, NetworkChangeAction, NetworkPlan, PeerStatus, PurgeReport,
+  RoutingConflict, StorageUsage, SuspendAction, TransferStats, Tunnel, TunnelConnection, TunnelCoverage, TunnelOp,
+  TunnelStatus,
+};
+use crate::monitor::{spawn_latency_monitor, spawn_ping};
 use crate::storage::{AppStateStore, StorageError};
+use crate::wg_config::{
+  aggregate_cidrs, cidr_contains, dns_servers, ConfigPeer, has_dns, has_save_config, infer_provider,
+  looks_like_wg_config, normalized_config, parse_peers, peer_endpoints, private_key_digest, resolve_includes,
+  sanitize_ifname, unique_ifname, uses_amnezia, validate_config,
+};
 use serde::Serialize;
-use std::path::PathBuf;
-use std::process::Command;
-use std::time::Duration;
-use tauri::State;
-use std::os::unix::fs::MetadataExt;
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+use uuid::Uuid;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
 
 #[tauri::command]
-pub fn get_state(store: State<'_, AppStateStore>) -> Result<AppStateFile, String> {
+pub fn get_state(store: State<'_, AppStateStore>) -> Result<AppStateFile, CommandError> {
   Ok(store.state_snapshot())
 }
 
 #[tauri::command]
-pub fn get_logs(store: State<'_, AppStateStore>) -> Result<Vec<String>, String> {
+pub fn get_logs(store: State<'_, AppStateStore>) -> Result<Vec<String>, CommandError> {
   let path = store.log_path();
   let content = std::fs::read_to_string(path).unwrap_or_default();
   let mut lines: Vec<String> = content.lines().map(|line| line.to_string()).collect();
@@ -26,154 +58,2711 @@ pub fn get_logs(store: State<'_, AppStateStore>) -> Result<Vec<String>, String>
   Ok(lines)
 }
 
+const APP_LOG_TAIL_BYTES: u64 = 64 * 1024;
+
+#[tauri::command]
+pub fn get_app_log(
+  app_id: String,
+  lines: Option<usize>,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<String>, CommandError> {
+  store
+    .find_app(&app_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::AppNotFound, "App not found"))?;
+  let mut file = match std::fs::File::open(store.app_log_path(&app_id)) {
+    Ok(file) => file,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(err.to_string().into()),
+  };
+  let len = file.metadata().map_err(|e| e.to_string())?.len();
+  let start = len.saturating_sub(APP_LOG_TAIL_BYTES);
+  file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+  let mut buf = Vec::new();
+  file.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+  let content = String::from_utf8_lossy(&buf);
+  let mut tail: Vec<String> = content
+    .lines()
+    .skip(usize::from(start > 0))
+    .map(|line| line.to_string())
+    .collect();
+  let max_lines = lines.unwrap_or(200);
+  for generation in store.app_log_generations(&app_id) {
+    if tail.len() >= max_lines {
+      break;
+    }
+    let content = read_log_generation(&generation).map_err(|e| e.to_string())?;
+    let mut older: Vec<String> = content.lines().map(|line| line.to_string()).collect();
+    older.append(&mut tail);
+    tail = older;
+  }
+  if tail.len() > max_lines {
+    tail = tail.split_off(tail.len() - max_lines);
+  }
+  Ok(tail)
+}
+
+#[tauri::command]
+pub fn clear_logs(store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  std::fs::write(store.log_path(), "").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn list_log_generations(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<LogGeneration>, CommandError> {
+  Ok(read_log_generations(&store.log_path()).map_err(|e| e.to_string())?)
+}
+
+#[tauri::command]
+pub fn remove_log_generation(
+  index: u32,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let removed = delete_log_generation(&store.log_path(), index).map_err(|e| e.to_string())?;
+  if !removed {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Log generation {index} not found"),
+    ));
+  }
+  Ok(())
+}
+
+#[tauri::command]
+pub fn export_logs(dest_path: String, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  let dest = PathBuf::from(dest_path);
+  let header = format!(
+    "sillyvpn {} | helper {}",
+    env!("CARGO_PKG_VERSION"),
+    installed_helper_version().unwrap_or_else(|| "not installed".to_string())
+  );
+  write_log_export(&store.log_path(), &dest, &header).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn verify_state_consistency(store: State<'_, AppStateStore>) -> Result<Vec<String>, CommandError> {
+  let differences = store.state_divergence().map_err(map_error)?;
+  if !differences.is_empty() {
+    append_log(
+      &store.log_path(),
+      &format!("State on disk diverged from memory: {}", differences.join("; ")),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  Ok(differences)
+}
+
+const STORAGE_LOGS_HINT_BYTES: u64 = 5 * 1024 * 1024;
+
+#[tauri::command]
+pub fn get_storage_usage(store: State<'_, AppStateStore>) -> Result<StorageUsage, CommandError> {
+  let mut usage = store.storage_usage().map_err(map_error)?;
+  let mut hints = Vec::new();
+  if usage.logs_bytes >= STORAGE_LOGS_HINT_BYTES {
+    hints.push(format!(
+      "логи занимают {:.1} МБ, старые поколения можно удалить",
+      usage.logs_bytes as f64 / (1024.0 * 1024.0)
+    ));
+  }
+  if usage.orphaned_config_bytes > 0 {
+    hints.push("в каталоге данных есть .conf без туннеля в списке".to_string());
+  }
+  if usage.other_bytes > 0 {
+    hints.push("посторонние файлы в каталоге данных можно удалить вручную".to_string());
+  }
+  if !hints.is_empty() {
+    usage.suggestion = Some(format!("Можно освободить место: {}.", hints.join("; ")));
+  }
+  Ok(usage)
+}
+
+#[tauri::command]
+pub fn check_config_permissions(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<ConfigPermissionIssue>, CommandError> {
+  store.config_permission_issues().map_err(map_error)
+}
+
+#[tauri::command]
+pub fn fix_config_permissions(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<ConfigPermissionIssue>, CommandError> {
+  let remaining = store.fix_config_permissions().map_err(map_error)?;
+  append_log(
+    &store.log_path(),
+    &format!("Fixed config permissions, {} issue(s) remain", remaining.len()),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(remaining)
+}
+
+pub fn warn_config_permissions(store: &AppStateStore) {
+  let Ok(issues) = store.config_permission_issues() else {
+    return;
+  };
+  for issue in issues {
+    let _ = append_log(
+      &store.log_path(),
+      &format!(
+        "Config {} has insecure permissions (mode {}, owner uid {})",
+        issue.path, issue.mode, issue.owner_uid
+      ),
+    );
+  }
+}
+
+const HELPER_STATE_DIRS: [&str; 2] = ["/run/sillyvpn", "/dev/shm/sillyvpn"];
+
+pub fn reconcile_vpn_state(store: &AppStateStore) {
+  if !store.state_snapshot().vpn_enabled || helper_state_present() {
+    return;
+  }
+  if store.set_vpn_enabled(false).is_ok() {
+    let _ = append_log(
+      &store.log_path(),
+      "VPN was marked as enabled, but neither the sillyvpn-ns namespace nor the helper state exists; marked it as disabled",
+    );
+  }
+}
+
+fn helper_state_present() -> bool {
+  if matches!(read_netns_inode("sillyvpn-ns"), Ok(Some(_)) | Err(_)) {
+    return true;
+  }
+  HELPER_STATE_DIRS.iter().any(|dir| {
+    let dir = Path::new(dir);
+    std::fs::symlink_metadata(dir).is_ok_and(|meta| meta.is_dir() && meta.uid() == 0)
+      && dir.join("state.json").exists()
+  })
+}
+
+#[tauri::command]
+pub fn import_conf(
+  path: String,
+  store: State<'_, AppStateStore>,
+) -> Result<Option<String>, CommandError> {
+  let source = PathBuf::from(path);
+  if !source.exists() {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Config file not found"));
+  }
+  let content = read_import_source(&source)?;
+  validate_config(&content)?;
+  let endpoints = peer_endpoints(&content)?;
+  let stem = source
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or_default();
+  let (provider, region) = infer_provider(stem, &endpoints);
+  let shared_key = tunnel_with_same_key(&store, &content);
+
+  let tunnel = store
+    .import_conf(&source, &content, endpoints, provider, region)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), &format!("Imported tunnel {}", tunnel.name))
+    .map_err(|e| e.to_string())?;
+  let warning = shared_key.map(|other| {
+    format!(
+      "Tunnel {} uses the same private key as {}; the server will not accept both at once",
+      tunnel.name, other
+    )
+  });
+  if let Some(warning) = &warning {
+    append_log(&store.log_path(), &format!("Warning: {warning}")).map_err(|e| e.to_string())?;
+  }
+  Ok(warning)
+}
+
+fn read_import_source(source: &Path) -> Result<String, String> {
+  let content = read_config_source(source)?;
+  if source.extension().and_then(|s| s.to_str()) != Some("conf") && !looks_like_wg_config(&content)
+  {
+    return Err("Only WireGuard .conf files are supported".into());
+  }
+  Ok(content)
+}
+
+fn read_config_source(source: &Path) -> Result<String, String> {
+  let content = std::fs::read_to_string(source).map_err(|e| e.to_string())?;
+  resolve_includes(&content, source.parent().unwrap_or(Path::new(".")))
+}
+
+fn tunnel_with_same_key(store: &AppStateStore, content: &str) -> Option<String> {
+  let digest = private_key_digest(content)?;
+  store
+    .state_snapshot()
+    .tunnels
+    .into_iter()
+    .find(|tunnel| {
+      std::fs::read_to_string(&tunnel.path)
+        .ok()
+        .and_then(|existing| private_key_digest(&existing))
+        == Some(digest)
+    })
+    .map(|tunnel| tunnel.name)
+}
+
+const FINGERPRINT_HEX_LEN: usize = 16;
+
+#[tauri::command]
+pub fn tunnel_fingerprint(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<String, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let content = std::fs::read_to_string(&tunnel.path).map_err(|e| e.to_string())?;
+  let digest = sha256_hex(normalized_config(&content).as_bytes());
+  let short: Vec<&str> = (0..FINGERPRINT_HEX_LEN)
+    .step_by(4)
+    .filter_map(|start| digest.get(start..start + 4))
+    .collect();
+  Ok(short.join("-"))
+}
+
+fn sha256_hex(input: &[u8]) -> String {
+  Sha256::digest(input)
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+#[tauri::command]
+pub fn replace_tunnel_config(
+  tunnel_id: String,
+  new_source: String,
+  refresh_name: Option<bool>,
+  store: State<'_, AppStateStore>,
+) -> Result<Tunnel, CommandError> {
+  let source = PathBuf::from(new_source);
+  if !source.exists() {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Config file not found"));
+  }
+  let content = read_import_source(&source)?;
+  validate_config(&content)?;
+  let endpoints = peer_endpoints(&content)?;
+  let stem = source
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or_default();
+  let inferred = infer_provider(stem, &endpoints);
+  let name = refresh_name
+    .unwrap_or(false)
+    .then(|| stem.to_string())
+    .filter(|name| !name.is_empty());
+  let tunnel = store
+    .replace_tunnel_config(&tunnel_id, &content, endpoints, inferred, name)
+    .map_err(map_error)?;
+  append_log(
+    &store.log_path(),
+    &format!("Replaced config of tunnel {}", tunnel.name),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(tunnel)
+}
+
+#[tauri::command]
+pub fn export_bundle(
+  dest_path: String,
+  passphrase: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let bundle = store
+    .export_bundle(BUNDLE_FORMAT, BUNDLE_VERSION)
+    .map_err(map_error)?;
+  let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+  write_bundle(Path::new(&dest_path), &bundle, passphrase.as_deref())?;
+  append_log(
+    &store.log_path(),
+    &format!(
+      "Exported bundle with {} tunnel(s), {} app(s){}",
+      bundle.state.tunnels.len(),
+      bundle.state.apps.len(),
+      if passphrase.is_some() { ", encrypted" } else { "" }
+    ),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn import_bundle(
+  src_path: String,
+  passphrase: Option<String>,
+  restore_settings: Option<bool>,
+  store: State<'_, AppStateStore>,
+) -> Result<BundleImport, CommandError> {
+  let restore_settings = restore_settings.unwrap_or(false);
+  let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+  let mut bundle = read_bundle(Path::new(&src_path), passphrase.as_deref())
+    .map_err(|e| CommandError::new(ErrorCode::InvalidInput, e))?;
+  for tunnel in &mut bundle.state.tunnels {
+    let content = &bundle.configs[&tunnel.id];
+    let endpoints = validate_config(content)
+      .and_then(|_| peer_endpoints(content))
+      .map_err(|e| {
+        CommandError::new(ErrorCode::InvalidInput, format!("{}: {e}", tunnel.name))
+      })?;
+    tunnel.endpoints = endpoints;
+  }
+  if restore_settings {
+    validate_imported_settings(&mut bundle.state)?;
+  }
+  let summary = store
+    .import_bundle(bundle, restore_settings)
+    .map_err(map_error)?;
+  let log_path = store.log_path();
+  append_log(
+    &log_path,
+    &format!(
+      "Imported bundle with {} tunnel(s), {} app(s){}",
+      summary.tunnels,
+      summary.apps,
+      if summary.settings_restored { ", settings restored" } else { "" }
+    ),
+  )
+  .map_err(|e| e.to_string())?;
+  for (name, cmd) in [
+    ("connect", &summary.skipped_on_connect_cmd),
+    ("disconnect", &summary.skipped_on_disconnect_cmd),
+  ] {
+    if let Some(cmd) = cmd {
+      append_log(&log_path, &format!("Bundle {name} hook not imported: {cmd}"))
+        .map_err(|e| e.to_string())?;
+    }
+  }
+  Ok(summary)
+}
+
+fn validate_imported_settings(settings: &mut AppStateFile) -> Result<(), CommandError> {
+  check_probe_settings(settings.probe_timeout_secs, settings.probe_retries)?;
+  check_app_output_limit(settings.app_output_limit_kib)?;
+  check_app_log_limit(settings.app_log_limit_kib)?;
+  check_bandwidth_limit(settings.bandwidth_limit_mbps)?;
+  settings.extra_env_keys = normalize_env_keys(std::mem::take(&mut settings.extra_env_keys))?;
+  settings.terminal = normalize_terminal(settings.terminal.take());
+  if settings.terminal.is_some() {
+    resolve_terminal(settings.terminal.as_deref())?;
+  }
+  Ok(())
+}
+
+#[tauri::command]
+pub fn add_app(
+  path: String,
+  label: String,
+  args: Option<Vec<String>>,
+  cwd: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<Option<String>, CommandError> {
+  let app_path = PathBuf::from(path);
+  if !app_path.exists() {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Binary not found"));
+  }
+  let args = args.unwrap_or_default();
+  if args.iter().any(|arg| arg.contains('\0')) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "Arguments must not contain NUL bytes",
+    ));
+  }
+  let cwd = cwd.filter(|cwd| !cwd.trim().is_empty());
+  if let Some(cwd) = &cwd {
+    if !cwd.starts_with('/') || !Path::new(cwd).is_dir() {
+      return Err(CommandError::new(
+        ErrorCode::InvalidInput,
+        format!("Working directory not found: {cwd}"),
+      ));
+    }
+  }
+  let info = inspect_binary_file(&app_path)?;
+  store
+    .add_app(&app_path, label, info.kind, info.interpreter, args, cwd)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Added VPN app").map_err(|e| e.to_string())?;
+  if let Some(warning) = &info.warning {
+    append_log(&store.log_path(), &format!("Warning: {warning}")).map_err(|e| e.to_string())?;
+  }
+  Ok(info.warning)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CandidateProcess {
+  pub pid: u32,
+  pub comm: String,
+  pub exe: String,
+}
+
+#[tauri::command]
+pub fn list_candidate_processes() -> Result<Vec<CandidateProcess>, CommandError> {
+  let own_exe = std::env::current_exe().ok();
+  let mut candidates: Vec<CandidateProcess> = Vec::new();
+  for entry in std::fs::read_dir("/proc").map_err(|e| e.to_string())?.flatten() {
+    let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+      continue;
+    };
+    let Some(exe) = owned_process_exe(pid) else {
+      continue;
+    };
+    if own_exe.as_deref() == Some(exe.as_path())
+      || candidates.iter().any(|candidate| Path::new(&candidate.exe) == exe)
+    {
+      continue;
+    }
+    let environ = std::fs::read(entry.path().join("environ")).unwrap_or_default();
+    let graphical = environ.split(|byte| *byte == 0).any(|var| {
+      var.starts_with(b"DISPLAY=") || var.starts_with(b"WAYLAND_DISPLAY=")
+    });
+    if !graphical {
+      continue;
+    }
+    let comm = std::fs::read_to_string(entry.path().join("comm"))
+      .map(|comm| comm.trim_end_matches('\n').to_string())
+      .unwrap_or_default();
+    candidates.push(CandidateProcess {
+      pid,
+      comm,
+      exe: exe.to_string_lossy().to_string(),
+    });
+  }
+  candidates.sort_by_key(|candidate| candidate.comm.to_lowercase());
+  Ok(candidates)
+}
+
+#[tauri::command]
+pub fn add_app_from_pid(
+  pid: u32,
+  label: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<Option<String>, CommandError> {
+  let exe = owned_process_exe(pid).ok_or_else(|| {
+    CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Process {pid} is not running or not owned by the current user"),
+    )
+  })?;
+  let exe = exe.to_str().map(PathBuf::from).ok_or_else(|| {
+    CommandError::new(ErrorCode::InvalidInput, "Binary path is not valid UTF-8")
+  })?;
+  let label = label
+    .map(|label| label.trim().to_string())
+    .filter(|label| !label.is_empty())
+    .or_else(|| {
+      std::fs::read_to_string(format!("/proc/{pid}/comm"))
+        .ok()
+        .map(|comm| comm.trim_end_matches('\n').to_string())
+        .filter(|comm| !comm.trim().is_empty())
+    })
+    .unwrap_or_else(|| exe.file_name().unwrap_or_default().to_string_lossy().to_string());
+  add_app(exe.to_string_lossy().to_string(), label, None, None, store)
+}
+
+fn owned_process_exe(pid: u32) -> Option<PathBuf> {
+  let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+  let uid = unsafe { libc::getuid() };
+  if std::fs::metadata(&proc_dir).ok()?.uid() != uid {
+    return None;
+  }
+  let exe = std::fs::read_link(proc_dir.join("exe")).ok()?;
+  let environ = std::fs::read(proc_dir.join("environ")).unwrap_or_default();
+  if let Some(appimage) = mounted_appimage(&environ, &exe).filter(|path| path.is_file()) {
+    return Some(appimage);
+  }
+  if !exe.is_file() {
+    return None;
+  }
+  Some(exe)
+}
+
+fn mounted_appimage(environ: &[u8], exe: &Path) -> Option<PathBuf> {
+  let var = |name: &[u8]| {
+    environ
+      .split(|byte| *byte == 0)
+      .find_map(|var| var.strip_prefix(name))
+      .filter(|value| !value.is_empty())
+      .map(|value| PathBuf::from(OsStr::from_bytes(value)))
+  };
+  let appdir = var(b"APPDIR=")?;
+  if !appdir.is_absolute() || !exe.starts_with(&appdir) {
+    return None;
+  }
+  var(b"APPIMAGE=")
+}
+
+#[derive(Debug, Serialize)]
+pub struct BinaryInfo {
+  pub kind: BinaryKind,
+  pub interpreter: Option<String>,
+  pub executable: bool,
+  pub warning: Option<String>,
+}
+
+#[tauri::command]
+pub fn inspect_binary(path: String) -> Result<BinaryInfo, CommandError> {
+  let path = PathBuf::from(path);
+  if !path.exists() {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Binary not found"));
+  }
+  Ok(inspect_binary_file(&path)?)
+}
+
+#[tauri::command]
+pub fn remove_app(app_id: String, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  store.remove_app(&app_id).map_err(map_error)?;
+  append_log(&store.log_path(), "Removed VPN app").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn remove_tunnel(tunnel_id: String, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  let tunnel = store.remove_tunnel(&tunnel_id).map_err(map_error)?;
+  append_log(&store.log_path(), &format!("Removed tunnel {}", tunnel.name))
+    .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn update_app(
+  app_id: String,
+  label: Option<String>,
+  note: Option<String>,
+  enabled: Option<bool>,
+  autostart: Option<bool>,
+  preferred_tunnel_id: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  if label.as_deref().is_some_and(|label| label.trim().is_empty()) {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Label must not be empty"));
+  }
+  if let Some(tunnel_id) = preferred_tunnel_id.as_deref().filter(|id| !id.is_empty()) {
+    store
+      .find_tunnel(tunnel_id)
+      .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  }
+  let app = store
+    .update_app(&app_id, label, note, enabled, autostart, preferred_tunnel_id)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), &format!("Updated VPN app {}", app.label))
+    .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn update_tunnel(
+  tunnel_id: String,
+  note: Option<String>,
+  enabled: Option<bool>,
+  provider: Option<String>,
+  region: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let tunnel = store
+    .update_tunnel(&tunnel_id, None, note, enabled, provider, region)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), &format!("Updated tunnel {}", tunnel.name))
+    .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn rename_tunnel(
+  tunnel_id: String,
+  name: String,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let name = name.trim().to_string();
+  if name.is_empty() {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Tunnel name must not be empty"));
+  }
+  let tunnel = store
+    .update_tunnel(&tunnel_id, Some(name), None, None, None, None)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), &format!("Renamed tunnel to {}", tunnel.name))
+    .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn batch_update_tunnels(
+  mut ops: Vec<TunnelOp>,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<Tunnel>, CommandError> {
+  for op in &mut ops {
+    if let TunnelOp::Rename { name, .. } = op {
+      *name = name.trim().to_string();
+      if name.is_empty() {
+        return Err(CommandError::new(
+          ErrorCode::InvalidInput,
+          "Tunnel name must not be empty",
+        ));
+      }
+    }
+  }
+  let tunnels = store.batch_update_tunnels(&ops).map_err(map_error)?;
+  append_log(
+    &store.log_path(),
+    &format!("Applied {} tunnel edit(s)", ops.len()),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(tunnels)
+}
+
+#[tauri::command]
+pub fn set_tunnel_bypass_routes(
+  tunnel_id: String,
+  routes: Vec<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let mut normalized = Vec::new();
+  for route in routes {
+    let route = route.trim();
+    if route.is_empty() {
+      continue;
+    }
+    if !is_ipv4_cidr(route) && !is_ipv6_cidr(route) {
+      return Err(CommandError::new(
+        ErrorCode::InvalidInput,
+        format!("Invalid bypass subnet: {route}"),
+      ));
+    }
+    normalized.push(route.to_string());
+  }
+  store
+    .set_tunnel_bypass_routes(&tunnel_id, normalized)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated bypass subnets").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+fn is_ipv4_cidr(value: &str) -> bool {
+  let (addr, prefix) = value.split_once('/').unwrap_or((value, "32"));
+  addr.parse::<std::net::Ipv4Addr>().is_ok() && prefix.parse::<u8>().is_ok_and(|p| p <= 32)
+}
+
+fn is_ipv6_cidr(value: &str) -> bool {
+  let (addr, prefix) = value.split_once('/').unwrap_or((value, "128"));
+  addr.parse::<std::net::Ipv6Addr>().is_ok() && prefix.parse::<u8>().is_ok_and(|p| p <= 128)
+}
+
+#[tauri::command]
+pub fn set_tunnel_dns_mode(
+  tunnel_id: String,
+  mode: DnsMode,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_tunnel_dns_mode(&tunnel_id, mode)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated tunnel DNS mode").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_tunnel_auto_mtu(
+  tunnel_id: String,
+  auto_mtu: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_tunnel_auto_mtu(&tunnel_id, auto_mtu)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated tunnel MTU mode").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+const CONNECT_TIMEOUT_MAX_SECS: u64 = 300;
+
+#[tauri::command]
+pub fn set_tunnel_connect_timeout(
+  tunnel_id: String,
+  timeout_secs: Option<u64>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  if timeout_secs.is_some_and(|secs| !(1..=CONNECT_TIMEOUT_MAX_SECS).contains(&secs)) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Connect timeout must be 1-{CONNECT_TIMEOUT_MAX_SECS} seconds"),
+    ));
+  }
+  store
+    .set_tunnel_connect_timeout(&tunnel_id, timeout_secs)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated tunnel connect timeout").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_tunnel_network(
+  tunnel_id: String,
+  subnet: Option<String>,
+  table: Option<u32>,
+  fwmark: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let (subnet, table, fwmark) = parse_network_overrides(subnet, table, fwmark)?;
+  store
+    .set_tunnel_network(&tunnel_id, subnet, table, fwmark)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated tunnel subnet/table/fwmark").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+fn parse_network_overrides(
+  subnet: Option<String>,
+  table: Option<u32>,
+  fwmark: Option<String>,
+) -> Result<(Option<String>, Option<u32>, Option<u32>), CommandError> {
+  let invalid = |message: String| CommandError::new(ErrorCode::InvalidInput, message);
+  let subnet = subnet.map(|subnet| subnet.trim().to_string()).filter(|subnet| !subnet.is_empty());
+  if let Some(subnet) = &subnet {
+    let valid = subnet
+      .strip_suffix("/24")
+      .and_then(|addr| addr.parse::<std::net::Ipv4Addr>().ok())
+      .is_some_and(|addr| addr.octets()[3] == 0);
+    if !valid {
+      return Err(invalid(format!("Подсеть должна иметь вид a.b.c.0/24: {subnet}")));
+    }
+  }
+  if table.is_some_and(|table| table == 0 || (253..=255).contains(&table)) {
+    return Err(invalid("Таблица маршрутизации 0 и 253–255 зарезервированы".to_string()));
+  }
+  let fwmark = match fwmark.as_deref().map(str::trim).filter(|mark| !mark.is_empty()) {
+    Some(mark) => {
+      let parsed = match mark.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => mark.parse::<u32>().ok(),
+      };
+      Some(
+        parsed
+          .filter(|mark| *mark > 0)
+          .ok_or_else(|| invalid(format!("Некорректный fwmark: {mark}")))?,
+      )
+    }
+    None => None,
+  };
+  Ok((subnet, table, fwmark))
+}
+
+#[tauri::command]
+pub fn set_tunnel_manage_routes_externally(
+  tunnel_id: String,
+  external: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_tunnel_manage_routes_externally(&tunnel_id, external)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated tunnel routing mode").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_tunnel_masquerade(
+  tunnel_id: String,
+  masquerade: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_tunnel_masquerade(&tunnel_id, masquerade)
+    .map_err(map_error)?;
+  append_log(
+    &store.log_path(),
+    if masquerade {
+      "Enabled MASQUERADE for tunnel"
+    } else {
+      "Disabled MASQUERADE for tunnel, namespace subnet is routed as is"
+    },
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_tunnel_resolv_options(
+  tunnel_id: String,
+  options: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<Option<String>, CommandError> {
+  let options = match options {
+    Some(options) => normalize_resolv_options(&options)?,
+    None => None,
+  };
+  store
+    .set_tunnel_resolv_options(&tunnel_id, options.clone())
+    .map_err(map_error)?;
+  Ok(options)
+}
+
+fn normalize_resolv_options(options: &str) -> Result<Option<String>, CommandError> {
+  if options.contains(['\n', '\r']) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "resolv.conf options must be a single line",
+    ));
+  }
+  let mut tokens: Vec<&str> = options.split_whitespace().collect();
+  if tokens.first() == Some(&"options") {
+    tokens.remove(0);
+  }
+  if tokens.is_empty() {
+    return Ok(None);
+  }
+  let invalid = tokens.iter().find(|token| {
+    !token
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'))
+  });
+  if let Some(token) = invalid {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Invalid resolv.conf option: {token}"),
+    ));
+  }
+  Ok(Some(tokens.join(" ")))
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnablePlan {
+  pub steps: Vec<String>,
+  pub errors: Vec<String>,
+}
+
+#[tauri::command(async)]
+pub fn enable_vpn(
+  tunnel_id: String,
+  simulate: Option<bool>,
+  strict: Option<bool>,
+  allow_risks: Option<Vec<String>>,
+  kill_switch: Option<bool>,
+  store: State<'_, AppStateStore>,
+  handle: AppHandle,
+) -> Result<Option<EnablePlan>, CommandError> {
+  let mut tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  if !tunnel.enabled {
+    return Err(CommandError::new(ErrorCode::TunnelDisabled, "Tunnel is disabled"));
+  }
+  if let Some(enabled) = kill_switch {
+    tunnel.kill_switch = enabled;
+  }
+  if simulate.unwrap_or(false) {
+    return simulate_enable(&store, &tunnel).map(Some);
+  }
+  if strict.unwrap_or(false) {
+    let allowed = allow_risks.unwrap_or_default();
+    let blocking: Vec<LeakRisk> = leak_risks(&tunnel)
+      .into_iter()
+      .filter(|risk| !allowed.contains(&risk.code))
+      .collect();
+    if !blocking.is_empty() {
+      let items: Vec<String> = blocking
+        .iter()
+        .map(|risk| format!("- [{}] {}", risk.code, risk.detail))
+        .collect();
+      return Err(CommandError::new(
+        ErrorCode::LeakRisk,
+        format!("Строгий режим: обнаружены риски утечки\n{}", items.join("\n")),
+      ));
+    }
+  }
+  if let Some(enabled) = kill_switch {
+    store
+      .set_tunnel_kill_switch(&tunnel_id, enabled)
+      .map_err(map_error)?;
+  }
+  start_vpn(&store, &tunnel, &handle)?;
+  Ok(None)
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeakRisk {
+  pub code: String,
+  pub detail: String,
+}
+
+#[tauri::command]
+pub fn check_leak_risks(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<LeakRisk>, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  Ok(leak_risks(&tunnel))
+}
+
+fn leak_risks(tunnel: &Tunnel) -> Vec<LeakRisk> {
+  let content = std::fs::read_to_string(&tunnel.path).unwrap_or_default();
+  let allowed_ips: Vec<String> = parse_peers(&content)
+    .into_iter()
+    .flat_map(|peer| peer.allowed_ips)
+    .collect();
+  let tunnels = |ip: IpAddr| allowed_ips.iter().any(|cidr| cidr_contains(cidr, ip));
+  let mut risks = Vec::new();
+
+  let ipv6_probe: IpAddr = "2001:4860:4860::8888".parse().expect("ipv6 literal");
+  if host_has_global_ipv6() && !tunnels(ipv6_probe) {
+    risks.push(LeakRisk {
+      code: "ipv6".to_string(),
+      detail: "На хосте есть глобальный IPv6, но AllowedIPs не включает ::/0".to_string(),
+    });
+  }
+
+  if tunnel.dns_mode == DnsMode::Host {
+    risks.push(LeakRisk {
+      code: "dns_host".to_string(),
+      detail: "DNS хоста: запросы уходят через резолвер хоста мимо туннеля".to_string(),
+    });
+  }
+  for server in dns_servers(&content) {
+    if !tunnels(server) {
+      risks.push(LeakRisk {
+        code: format!("dns_server:{server}"),
+        detail: format!("DNS-сервер {server} не входит в AllowedIPs"),
+      });
+    }
+  }
+
+  let fallback_open = !tunnel.kill_switch || tunnel.manage_routes_externally;
+  if fallback_open {
+    risks.push(LeakRisk {
+      code: "kill_switch".to_string(),
+      detail: "Kill switch не настроен: при обрыве туннеля трафик namespace уйдёт напрямую".to_string(),
+    });
+  }
+
+  let Some(uplink) = fallback_open
+    .then(|| route_device(&["route", "show", "default"]))
+    .flatten()
+  else {
+    return risks;
+  };
+  for endpoint in &tunnel.endpoints {
+    let Some(ip) = (endpoint.host.as_str(), endpoint.port)
+      .to_socket_addrs()
+      .ok()
+      .and_then(|mut addrs| addrs.next())
+      .map(|addr| addr.ip())
+    else {
+      continue;
+    };
+    let ip = ip.to_string();
+    if route_device(&["route", "get", &ip]).as_deref() == Some(uplink.as_str()) {
+      risks.push(LeakRisk {
+        code: format!("endpoint_route:{}", endpoint.host),
+        detail: format!(
+          "Endpoint {} доступен через {uplink}, и без kill switch namespace при обрыве туннеля уйдёт туда же",
+          endpoint.host
+        ),
+      });
+    }
+  }
+  risks
+}
+
+fn host_has_global_ipv6() -> bool {
+  std::fs::read_to_string("/proc/net/if_inet6")
+    .unwrap_or_default()
+    .lines()
+    .map(|line| line.split_whitespace().collect::<Vec<_>>())
+    .any(|fields| fields.len() >= 6 && fields[3] == "00" && fields[5] != "lo")
+}
+
+fn route_device(args: &[&str]) -> Option<String> {
+  let output = Command::new("ip").args(args).output().ok()?;
+  let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+  let mut words = stdout.split_whitespace();
+  words.find(|word| *word == "dev")?;
+  words.next().map(|dev| dev.to_string())
+}
+
+pub(crate) fn start_vpn(
+  store: &AppStateStore,
+  tunnel: &Tunnel,
+  handle: &AppHandle,
+) -> Result<(), CommandError> {
+  let content = std::fs::read_to_string(&tunnel.path).unwrap_or_default();
+  if has_save_config(&content) {
+    append_log(
+      &store.log_path(),
+      "SaveConfig is ignored, the temporary config is never written back",
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  let ifname = tunnel_ifname(store, tunnel);
+  if config_via_stdin(store, tunnel) {
+    let config = std::fs::read(&tunnel.path).map_err(|e| e.to_string())?;
+    run_helper_with_input(enable_helper_args(store, tunnel, &ifname, true), &config)
+      .map_err(map_helper_error)?;
+  } else {
+    run_helper_vec(enable_helper_args(store, tunnel, &ifname, false)).map_err(map_helper_error)?;
+  }
+  store.set_active_tunnel(&tunnel.id).map_err(map_error)?;
+  append_log(&store.log_path(), "VPN enabled").map_err(|e| e.to_string())?;
+  if tunnel.auto_mtu {
+    if let Ok(mtu) = std::fs::read_to_string(format!("/sys/class/net/{ifname}/mtu")) {
+      append_log(
+        &store.log_path(),
+        &format!("MTU for {} set to {}", tunnel.name, mtu.trim()),
+      )
+      .map_err(|e| e.to_string())?;
+    }
+  }
+  launch_apps(store, handle, |app| {
+    app.autostart || tunnel.launch_profile.contains(&app.id)
+  });
+  if let Some(cmd) = store.state_snapshot().on_connect_cmd {
+    run_user_hook(store, "connect", cmd, &tunnel.name);
+  }
+  Ok(())
+}
+
+fn simulate_enable(store: &AppStateStore, tunnel: &Tunnel) -> Result<EnablePlan, CommandError> {
+  let mut errors = Vec::new();
+  match std::fs::read_to_string(&tunnel.path) {
+    Ok(content) => {
+      if let Err(err) = validate_config(&content) {
+        errors.push(err);
+      }
+      if uses_amnezia(&content) && find_executable("awg-quick").is_none() {
+        errors.push("Config uses AmneziaWG parameters but awg-quick is not installed".to_string());
+      }
+    }
+    Err(err) => errors.push(format!("Cannot read config: {err}")),
+  }
+  let ifname = tunnel_ifname(store, tunnel);
+  let via_stdin = config_via_stdin(store, tunnel);
+  let mut args = enable_helper_args(store, tunnel, &ifname, via_stdin);
+  args.push("--dry-run".to_string());
+  let input = if via_stdin {
+    Some(std::fs::read(&tunnel.path).map_err(|e| e.to_string())?)
+  } else {
+    None
+  };
+  let output = run_helper_unprivileged(&args, input.as_deref()).map_err(map_helper_error)?;
+  let steps = String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .map(|line| line.to_string())
+    .collect();
+  errors.extend(
+    String::from_utf8_lossy(&output.stderr)
+      .lines()
+      .filter(|line| !line.trim().is_empty())
+      .map(|line| line.to_string()),
+  );
+  append_log(&store.log_path(), "Simulated VPN enable").map_err(|e| e.to_string())?;
+  Ok(EnablePlan { steps, errors })
+}
+
+#[tauri::command(async)]
+pub fn reconnect_vpn(
+  store: State<'_, AppStateStore>,
+  handle: AppHandle,
+) -> Result<(), CommandError> {
+  reconnect_active(&store, &handle)
+}
+
+pub(crate) fn reconnect_active(
+  store: &AppStateStore,
+  handle: &AppHandle,
+) -> Result<(), CommandError> {
+  let state = store.state_snapshot();
+  let tunnel = state
+    .active_tunnel_id
+    .as_deref()
+    .filter(|_| state.vpn_enabled)
+    .and_then(|tunnel_id| store.find_tunnel(tunnel_id))
+    .ok_or_else(|| CommandError::new(ErrorCode::VpnInactive, "VPN is not enabled"))?;
+  shutdown_vpn(store)?;
+  start_vpn(store, &tunnel, handle)?;
+  append_log(&store.log_path(), &format!("Reconnected tunnel {}", tunnel.name))
+    .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn disable_vpn(
+  kill_apps: Option<bool>,
+  store: State<'_, AppStateStore>,
+) -> Result<DisableReport, CommandError> {
+  let state = store.state_snapshot();
+  let mut killed = 0;
+  if kill_apps.unwrap_or(false) {
+    for app in &state.apps {
+      match kill_app_in_namespace(&store, app, "sillyvpn-ns") {
+        Ok(count) => killed += count,
+        Err(err) => append_log(
+          &store.log_path(),
+          &format!("Failed to stop {} before disabling VPN: {err}", app.label),
+        )
+        .map_err(|e| e.to_string())?,
+      }
+    }
+    append_log(
+      &store.log_path(),
+      &format!("Killed {killed} app processes before disabling VPN"),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  let remaining = match read_netns_inode("sillyvpn-ns")? {
+    Some(ns_inode) => namespace_pids(ns_inode)?.len() as u32,
+    None => 0,
+  };
+  shutdown_vpn(&store)?;
+  for app in &state.apps {
+    store.remove_app_log_generations(&app.id);
+  }
+  let warning = (remaining > 0).then(|| {
+    format!(
+      "В выключенном namespace остались процессы ({remaining}): сети у них больше нет. Закройте их вручную или выключайте VPN с завершением приложений (killApps)."
+    )
+  });
+  if remaining > 0 {
+    append_log(
+      &store.log_path(),
+      &format!("{remaining} processes left running in the torn-down namespace"),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  Ok(DisableReport {
+    killed,
+    remaining,
+    warning,
+  })
+}
+
+pub(crate) fn shutdown_vpn(store: &AppStateStore) -> Result<(), CommandError> {
+  store.stop_latency_monitor();
+  let state = store.state_snapshot();
+  let tunnel_name = state
+    .active_tunnel_id
+    .as_deref()
+    .and_then(|tunnel_id| store.find_tunnel(tunnel_id))
+    .map(|tunnel| tunnel.name)
+    .unwrap_or_default();
+  let output = run_helper_output(disable_helper_args()).map_err(map_helper_error)?;
+  store.set_vpn_enabled(false).map_err(map_error)?;
+  store.set_nat_check(None);
+  for ifname in output
+    .lines()
+    .filter_map(|line| line.strip_prefix("forced-cleanup "))
+  {
+    append_log(
+      &store.log_path(),
+      &format!("Interface {ifname} survived wg-quick down and was removed forcibly"),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  append_log(&store.log_path(), "VPN disabled").map_err(|e| e.to_string())?;
+  if let Some(cmd) = state.on_disconnect_cmd {
+    run_user_hook(store, "disconnect", cmd, &tunnel_name);
+  }
+  Ok(())
+}
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn run_user_hook(store: &AppStateStore, event: &'static str, cmd: String, tunnel_name: &str) {
+  let log_path = store.log_path();
+  let tunnel_name = tunnel_name.to_string();
+  std::thread::spawn(move || {
+    let spawned = Command::new("sh")
+      .arg("-c")
+      .arg(&cmd)
+      .env("SILLYVPN_EVENT", event)
+      .env("SILLYVPN_TUNNEL", &tunnel_name)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .process_group(0)
+      .spawn();
+    let mut child = match spawned {
+      Ok(child) => child,
+      Err(err) => {
+        let _ = append_log(&log_path, &format!("Hook on_{event} failed to start: {err}"));
+        return;
+      }
+    };
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let stdout = std::thread::spawn(move || read_hook_pipe(stdout));
+    let stderr = std::thread::spawn(move || read_hook_pipe(stderr));
+    let deadline = Instant::now() + HOOK_TIMEOUT;
+    let status = loop {
+      match child.try_wait() {
+        Ok(Some(status)) => break Some(status),
+        Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(100)),
+        _ => {
+          unsafe {
+            libc::killpg(child.id() as i32, libc::SIGKILL);
+          }
+          let _ = child.wait();
+          break None;
+        }
+      }
+    };
+    let output = format!(
+      "{}{}",
+      stdout.join().unwrap_or_default(),
+      stderr.join().unwrap_or_default()
+    );
+    let outcome = match status {
+      Some(status) if status.success() => "finished".to_string(),
+      Some(status) => format!("exited with {status}"),
+      None => format!("killed after {}s", HOOK_TIMEOUT.as_secs()),
+    };
+    let _ = append_log(
+      &log_path,
+      &format!("Hook on_{event} {outcome}: {}", output.trim()),
+    );
+  });
+}
+
+fn read_hook_pipe<R: Read>(pipe: Option<R>) -> String {
+  let mut buf = String::new();
+  if let Some(mut pipe) = pipe {
+    let _ = pipe.read_to_string(&mut buf);
+  }
+  buf
+}
+
+#[tauri::command]
+pub fn set_vpn_hooks(
+  on_connect_cmd: Option<String>,
+  on_disconnect_cmd: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let normalize = |cmd: Option<String>| {
+    cmd
+      .map(|cmd| cmd.trim().to_string())
+      .filter(|cmd| !cmd.is_empty())
+  };
+  store
+    .set_vpn_hooks(normalize(on_connect_cmd), normalize(on_disconnect_cmd))
+    .map_err(map_error)
+}
+
+#[tauri::command]
+pub fn run_app_via_vpn(
+  app_id: String,
+  switch_tunnel: Option<bool>,
+  store: State<'_, AppStateStore>,
+  handle: AppHandle,
+) -> Result<(), CommandError> {
+  let app = store
+    .find_app(&app_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::AppNotFound, "App not found"))?;
+  if !app.enabled {
+    return Err(CommandError::new(ErrorCode::AppDisabled, "App is disabled"));
+  }
+  ensure_app_not_running(&app.path)?;
+  store
+    .set_last_app_id(&app_id)
+    .map_err(map_error)?;
+  let preferred = app
+    .preferred_tunnel_id
+    .as_deref()
+    .and_then(|tunnel_id| store.find_tunnel(tunnel_id));
+  if let Some(tunnel) = preferred {
+    let state = store.state_snapshot();
+    if !state.vpn_enabled || state.active_tunnel_id.as_deref() != Some(tunnel.id.as_str()) {
+      if !switch_tunnel.unwrap_or(false) {
+        return Err(CommandError::new(
+          ErrorCode::AppPinned,
+          format!(
+            "{} is pinned to tunnel {}, switch to it first",
+            app.label, tunnel.name
+          ),
+        ));
+      }
+      if !tunnel.enabled {
+        return Err(CommandError::new(ErrorCode::TunnelDisabled, "Tunnel is disabled"));
+      }
+      if state.vpn_enabled {
+        shutdown_vpn(&store)?;
+      }
+      start_vpn(&store, &tunnel, &handle)?;
+      if app.autostart {
+        return Ok(());
+      }
+    }
+  }
+  launch_app(&store, &app, &handle).map(|_| ())
+}
+
+#[derive(Debug, Serialize)]
+pub struct AppExitStatus {
+  pub app_id: String,
+  pub code: Option<i32>,
+  pub success: bool,
+  pub stdout: String,
+  pub stderr: String,
+}
+
+#[tauri::command(async)]
+pub fn run_app_and_wait(
+  app_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<AppExitStatus, CommandError> {
+  let app = store
+    .find_app(&app_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::AppNotFound, "App not found"))?;
+  if !app.enabled {
+    return Err(CommandError::new(ErrorCode::AppDisabled, "App is disabled"));
+  }
+  if !store.state_snapshot().vpn_enabled {
+    return Err(CommandError::new(ErrorCode::VpnInactive, "VPN is not enabled"));
+  }
+  let args = run_helper_args(&app, &store.state_snapshot(), RunMode::Wait)?;
+  append_log(&store.log_path(), &format!("Running app via VPN and waiting: {}", app.label))
+    .map_err(|e| e.to_string())?;
+  let output = run_helper_raw(args).map_err(map_helper_error)?;
+  let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+  if let Some(line) = stderr.lines().find(|line| line.starts_with("sillyvpn-helper error:")) {
+    return Err(map_helper_error(HelperError::HelperFailed(line.to_string())));
+  }
+  let code = output.status.code();
+  if let Some(code @ (126 | 127)) = code {
+    return Err(map_helper_error(HelperError::HelperFailed(format!(
+      "pkexec exited with status {code}: {}",
+      stderr.trim()
+    ))));
+  }
+  append_log(
+    &store.log_path(),
+    &format!(
+      "App {} exited with code {}",
+      app.label,
+      code.map(|code| code.to_string()).unwrap_or_else(|| "unknown".to_string())
+    ),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(AppExitStatus {
+    app_id,
+    code,
+    success: output.status.success(),
+    stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+    stderr,
+  })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppLaunchResult {
+  pub app_id: String,
+  pub label: String,
+  pub status: String,
+  pub detail: Option<String>,
+  pub pid: Option<u32>,
+}
+
+const LAUNCH_VERIFY_DELAY: Duration = Duration::from_millis(1500);
+const PEER_ONLINE_WINDOW_SECS: u64 = 180;
+const DIAGNOSTIC_TOOLS: [&str; 7] = ["dig", "nslookup", "curl", "ping", "traceroute", "ip", "ss"];
+
+fn launch_captured(
+  handle: AppHandle,
+  app: AppItem,
+  args: Vec<String>,
+  sender: Sender<AppLaunchResult>,
+) {
+  let store = handle.state::<AppStateStore>();
+  let mut child = match spawn_helper_stream(args) {
+    Ok(child) => child,
+    Err(err) => {
+      report_launch(&handle, &sender, failed_launch(&store, &app, map_helper_error(err)));
+      return;
+    }
+  };
+  let mut lines = child
+    .stdout
+    .take()
+    .map(|stdout| BufReader::new(stdout).lines().map_while(Result::ok));
+  let Some(pid_line) = lines.as_mut().and_then(|lines| lines.next()) else {
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+      let _ = pipe.read_to_string(&mut stderr);
+    }
+    let _ = child.wait();
+    let err = map_helper_error(HelperError::HelperFailed(stderr));
+    report_launch(&handle, &sender, failed_launch(&store, &app, err));
+    return;
+  };
+  store.begin_app_output(&app.id);
+  let confirm_handle = handle.clone();
+  let confirm_app = app.clone();
+  std::thread::spawn(move || {
+    let store = confirm_handle.state::<AppStateStore>();
+    let result = confirm_launch(&store, &confirm_app, &pid_line);
+    report_launch(&confirm_handle, &sender, result);
+  });
+  for line in lines.into_iter().flatten() {
+    store.push_app_output(&app.id, line);
+  }
+  let _ = child.wait();
+  store.end_app_output(&app.id);
+}
+
+fn failed_launch(store: &AppStateStore, app: &AppItem, err: CommandError) -> AppLaunchResult {
+  let _ = append_log(
+    &store.log_path(),
+    &format!("Failed to start app via VPN: {} ({})", app.label, err),
+  );
+  AppLaunchResult {
+    app_id: app.id.clone(),
+    label: app.label.clone(),
+    status: "failed".to_string(),
+    detail: Some(err.to_string()),
+    pid: None,
+  }
+}
+
+#[tauri::command]
+pub fn get_app_live_output(
+  app_id: String,
+  since: Option<u64>,
+  store: State<'_, AppStateStore>,
+) -> Result<AppLiveOutput, CommandError> {
+  if store.find_app(&app_id).is_none() {
+    return Err(CommandError::new(ErrorCode::AppNotFound, "App not found"));
+  }
+  Ok(store.app_output_since(&app_id, since.unwrap_or(0)))
+}
+
+#[tauri::command]
+pub fn set_app_limits(
+  app_id: String,
+  limits: AppLimits,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  let checks = [
+    (limits.max_memory_mb, 64..=1_048_576, "Memory limit must be 64-1048576 MB"),
+    (limits.max_processes, 1..=100_000, "Process limit must be 1-100000"),
+    (limits.max_open_files, 16..=1_048_576, "Open files limit must be 16-1048576"),
+    (limits.max_cpu_secs, 1..=31_536_000, "CPU time limit must be 1-31536000 seconds"),
+  ];
+  for (value, range, message) in checks {
+    if value.is_some_and(|value| !range.contains(&value)) {
+      return Err(CommandError::new(ErrorCode::InvalidInput, message));
+    }
+  }
+  store.set_app_limits(&app_id, limits).map_err(map_error)?;
+  append_log(&store.log_path(), "Updated app resource limits").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_app_capture_output(
+  app_id: String,
+  capture: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_app_capture_output(&app_id, capture)
+    .map_err(map_error)
+}
+
+fn confirm_launch(store: &AppStateStore, app: &AppItem, helper_output: &str) -> AppLaunchResult {
+  let spawned = helper_output
+    .lines()
+    .rev()
+    .find_map(|line| line.trim().parse::<u32>().ok());
+  std::thread::sleep(LAUNCH_VERIFY_DELAY);
+  let alive = spawned.filter(|pid| Path::new(&format!("/proc/{pid}")).exists());
+  let pid = alive.or_else(|| {
+    app_pids_in_namespace(&app.path, "sillyvpn-ns")
+      .ok()
+      .and_then(|pids| pids.first().map(|pid| *pid as u32))
+  });
+  let (status, detail) = match pid {
+    Some(pid) => {
+      store.track_app_pid(&app.id, pid);
+      if alive.is_none() {
+        let _ = append_log(
+          &store.log_path(),
+          &format!("{} daemonized, tracking PID {}", app.label, pid),
+        );
+      }
+      ("confirmed", None)
+    }
+    None => {
+      let _ = append_log(
+        &store.log_path(),
+        &format!("{} was spawned but no running process was found", app.label),
+      );
+      ("unconfirmed", Some("spawned but not confirmed".to_string()))
+    }
+  };
+  AppLaunchResult {
+    app_id: app.id.clone(),
+    label: app.label.clone(),
+    status: status.to_string(),
+    detail,
+    pid,
+  }
+}
+
+#[tauri::command(async)]
+pub fn launch_autostart_apps(
+  store: State<'_, AppStateStore>,
+  handle: AppHandle,
+) -> Result<Vec<AppLaunchResult>, CommandError> {
+  if !store.state_snapshot().vpn_enabled {
+    return Err(CommandError::new(ErrorCode::VpnInactive, "VPN is not enabled"));
+  }
+  Ok(launch_apps(&store, &handle, |app| app.autostart))
+}
+
+#[tauri::command]
+pub fn set_launch_profile_member(
+  tunnel_id: String,
+  app_id: String,
+  member: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<String>, CommandError> {
+  store
+    .set_launch_profile_member(&tunnel_id, &app_id, member)
+    .map_err(map_error)
+}
+
+#[tauri::command(async)]
+pub fn launch_tunnel_profile(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+  handle: AppHandle,
+) -> Result<Vec<AppLaunchResult>, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let state = store.state_snapshot();
+  if !state.vpn_enabled || state.active_tunnel_id.as_deref() != Some(tunnel.id.as_str()) {
+    return Err(CommandError::new(ErrorCode::VpnInactive, "Tunnel is not active"));
+  }
+  Ok(launch_apps(&store, &handle, |app| {
+    tunnel.launch_profile.contains(&app.id)
+  }))
+}
+
+fn launch_apps(
+  store: &AppStateStore,
+  handle: &AppHandle,
+  selected: impl Fn(&AppItem) -> bool,
+) -> Vec<AppLaunchResult> {
+  let apps = store.state_snapshot().apps;
+  let mut pending = Vec::new();
+  for app in apps.into_iter().filter(|app| app.enabled && selected(app)) {
+    let launched = match is_app_running(&app.path) {
+      Ok(true) => Err(("skipped", "already running".to_string())),
+      Ok(false) => launch_app(store, &app, handle).map_err(|err| ("failed", err.to_string())),
+      Err(err) => Err(("failed", err)),
+    };
+    pending.push((app, launched));
+  }
+  pending
+    .into_iter()
+    .map(|(app, launched)| {
+      let (status, detail) = match launched {
+        Ok(confirmation) => match confirmation.recv() {
+          Ok(result) => return result,
+          Err(_) => ("failed", "launch thread exited without a result".to_string()),
+        },
+        Err(outcome) => outcome,
+      };
+      AppLaunchResult {
+        app_id: app.id,
+        label: app.label,
+        status: status.to_string(),
+        detail: Some(detail),
+        pid: None,
+      }
+    })
+    .collect()
+}
+
+fn launch_app(
+  store: &AppStateStore,
+  app: &AppItem,
+  handle: &AppHandle,
+) -> Result<Receiver<AppLaunchResult>, CommandError> {
+  append_log(
+    &store.log_path(),
+    &format!("Started app via VPN: {}", app.label),
+  )
+  .map_err(|e| e.to_string())?;
+  let mut args = run_helper_args(app, &store.state_snapshot(), RunMode::Detached)?;
+  let handle = handle.clone();
+  let app = app.clone();
+  let (sender, confirmation) = mpsc::channel();
+  if app.capture_output && !app.in_terminal {
+    args.push("--capture".to_string());
+    std::thread::spawn(move || launch_captured(handle, app, args, sender));
+    return Ok(confirmation);
+  }
+  let logfile = store.prepare_app_log(&app.id).map_err(map_error)?;
+  args.push("--logfile".to_string());
+  args.push(logfile.to_string_lossy().to_string());
+  std::thread::spawn(move || {
+    let store = handle.state::<AppStateStore>();
+    let result = match run_helper_output(args).map_err(map_helper_error) {
+      Ok(output) => confirm_launch(&store, &app, &output),
+      Err(err) => failed_launch(&store, &app, err),
+    };
+    report_launch(&handle, &sender, result);
+  });
+  Ok(confirmation)
+}
+
+fn report_launch(handle: &AppHandle, sender: &Sender<AppLaunchResult>, result: AppLaunchResult) {
+  let _ = handle.emit_all("app-launch", result.clone());
+  let _ = sender.send(result);
+}
+
+#[tauri::command]
+pub fn get_helper_resolution() -> HelperResolution {
+  resolve_helper()
+}
+
+#[tauri::command]
+pub fn preview_privileged_command(
+  operation: String,
+  tunnel_id: Option<String>,
+  app_id: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<CommandPreview, CommandError> {
+  let state = store.state_snapshot();
+  match operation.as_str() {
+    "enable" => {
+      let tunnel_id = tunnel_id
+        .or(state.last_tunnel_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::InvalidInput, "Select a tunnel first"))?;
+      let tunnel = store
+        .find_tunnel(&tunnel_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+      let ifname = tunnel_ifname(&store, &tunnel);
+      Ok(preview_helper(&enable_helper_args(
+        &store,
+        &tunnel,
+        &ifname,
+        config_via_stdin(&store, &tunnel),
+      )))
+    }
+    "disable" => Ok(preview_helper(&disable_helper_args())),
+    "run" => {
+      let app_id = app_id
+        .or_else(|| state.last_app_id.clone())
+        .ok_or_else(|| CommandError::new(ErrorCode::InvalidInput, "Select an app first"))?;
+      let app = store
+        .find_app(&app_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::AppNotFound, "App not found"))?;
+      Ok(preview_helper(&run_helper_args(&app, &state, RunMode::Detached)?))
+    }
+    "install" => Ok(preview_install()),
+    _ => Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Unknown operation: {operation}"),
+    )),
+  }
+}
+
+fn tunnel_ifname(store: &AppStateStore, tunnel: &Tunnel) -> String {
+  let taken: Vec<String> = store
+    .state_snapshot()
+    .tunnels
+    .iter()
+    .filter(|other| other.id != tunnel.id)
+    .map(|other| sanitize_ifname(&other.name))
+    .collect();
+  unique_ifname(&tunnel.name, &tunnel.id, &taken)
+}
+
+fn enable_helper_args(
+  store: &AppStateStore,
+  tunnel: &Tunnel,
+  ifname: &str,
+  via_stdin: bool,
+) -> Vec<String> {
+  let mut args = vec!["enable".to_string()];
+  if via_stdin {
+    args.push("--config-stdin".to_string());
+  } else {
+    args.push("--config".to_string());
+    args.push(tunnel.path.clone());
+  }
+  args.push("--ifname".to_string());
+  args.push(ifname.to_string());
+  for route in &tunnel.bypass_routes {
+    args.push("--bypass".to_string());
+    args.push(route.clone());
+  }
+  match tunnel.dns_mode {
+    DnsMode::Tunnel => {}
+    DnsMode::Host => args.extend(["--dns-mode".to_string(), "host".to_string()]),
+    DnsMode::Resolved => args.extend(["--dns-mode".to_string(), "resolved".to_string()]),
+  }
+  if tunnel.auto_mtu {
+    args.push("--auto-mtu".to_string());
+  }
+  if let Some(timeout) = tunnel.connect_timeout_secs {
+    args.push("--handshake-timeout".to_string());
+    args.push(timeout.to_string());
+  }
+  if tunnel.manage_routes_externally {
+    args.push("--external-routes".to_string());
+  } else if tunnel.kill_switch {
+    args.push("--killswitch".to_string());
+  }
+  let settings = store.state_snapshot();
+  match &tunnel.veth_subnet {
+    Some(subnet) => args.extend(["--subnet".to_string(), subnet.clone()]),
+    None if settings.randomize_subnet => args.push("--randomize-subnet".to_string()),
+    None => {}
+  }
+  if let Some(table) = tunnel.route_table {
+    args.push("--table".to_string());
+    args.push(table.to_string());
+  }
+  if let Some(fwmark) = tunnel.fwmark {
+    args.push("--fwmark".to_string());
+    args.push(format!("0x{fwmark:x}"));
+  }
+  if let Some(limit) = settings.bandwidth_limit_mbps {
+    args.push("--rate-limit".to_string());
+    args.push(limit.to_string());
+  }
+  if !tunnel.masquerade {
+    args.push("--no-masquerade".to_string());
+  }
+  if let Some(options) = &tunnel.resolv_options {
+    args.push("--resolv-options".to_string());
+    args.push(options.clone());
+  }
+  args
+}
+
+fn config_via_stdin(store: &AppStateStore, tunnel: &Tunnel) -> bool {
+  store.state_snapshot().config_via_stdin && !tunnel.manage_routes_externally
+}
+
+fn disable_helper_args() -> Vec<String> {
+  vec!["disable".to_string()]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+  Detached,
+  Wait,
+}
+
+fn run_helper_args(
+  app: &AppItem,
+  state: &AppStateFile,
+  mode: RunMode,
+) -> Result<Vec<String>, CommandError> {
+  let mut args = vec!["run".to_string(), "--bin".to_string(), app.path.clone()];
+  if mode == RunMode::Wait {
+    args.push("--wait".to_string());
+  }
+  if let Some(interpreter) = &app.interpreter {
+    args.push("--interp".to_string());
+    args.push(interpreter.clone());
+  }
+  for arg in &app.args {
+    args.push("--arg".to_string());
+    args.push(arg.clone());
+  }
+  if let Some(cwd) = &app.cwd {
+    args.push("--cwd".to_string());
+    args.push(cwd.clone());
+  }
+  if app.in_terminal {
+    args.push("--terminal".to_string());
+    args.push(resolve_terminal(state.terminal.as_deref())?);
+  }
+  if app.limits.no_new_privs {
+    args.push("--no-new-privs".to_string());
+  }
+  for (name, value) in [
+    ("as", app.limits.max_memory_mb.map(|mb| mb * 1024 * 1024)),
+    ("nproc", app.limits.max_processes),
+    ("nofile", app.limits.max_open_files),
+    ("cpu", app.limits.max_cpu_secs),
+  ] {
+    if let Some(value) = value {
+      args.push("--rlimit".to_string());
+      args.push(format!("{name}={value}"));
+    }
+  }
+  args.push("--cgroup".to_string());
+  args.push(app.id.clone());
+  for key in &state.extra_env_keys {
+    args.push("--allow-env".to_string());
+    args.push(key.clone());
+  }
+  for (key, value) in collect_ui_env(&state.extra_env_keys) {
+    args.push("--env".to_string());
+    args.push(format!("{}={}", key, value));
+  }
+  Ok(args)
+}
+
+const TERMINAL_CANDIDATES: [&str; 6] = [
+  "x-terminal-emulator",
+  "konsole",
+  "xfce4-terminal",
+  "alacritty",
+  "kitty",
+  "xterm",
+];
+
+fn resolve_terminal(configured: Option<&str>) -> Result<String, CommandError> {
+  let found = match configured {
+    Some(terminal) if terminal.starts_with('/') => {
+      Some(PathBuf::from(terminal)).filter(|path| path.is_file())
+    }
+    Some(terminal) => find_executable(terminal),
+    None => TERMINAL_CANDIDATES.into_iter().find_map(find_executable),
+  };
+  found
+    .map(|path| path.to_string_lossy().to_string())
+    .ok_or_else(|| {
+      CommandError::new(
+        ErrorCode::InvalidInput,
+        match configured {
+          Some(terminal) => format!("Terminal emulator not found: {terminal}"),
+          None => "No terminal emulator found, set one in settings".to_string(),
+        },
+      )
+    })
+}
+
+#[tauri::command]
+pub fn set_app_in_terminal(
+  app_id: String,
+  in_terminal: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_app_in_terminal(&app_id, in_terminal)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated app terminal mode").map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_terminal(
+  terminal: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<String, CommandError> {
+  let terminal = normalize_terminal(terminal);
+  let resolved = resolve_terminal(terminal.as_deref())?;
+  store.set_terminal(terminal).map_err(map_error)?;
+  Ok(resolved)
+}
+
+fn normalize_terminal(terminal: Option<String>) -> Option<String> {
+  terminal
+    .map(|terminal| terminal.trim().to_string())
+    .filter(|terminal| !terminal.is_empty())
+}
+
+#[tauri::command]
+pub fn kill_all_apps(store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  let apps = store.state_snapshot().apps;
+  let mut total = 0;
+  for app in apps {
+    total += kill_app_in_namespace(&store, &app, "sillyvpn-ns")?;
+  }
+  append_log(
+    &store.log_path(),
+    &format!("Killed {} processes for VPN apps", total),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
 #[tauri::command]
-pub fn clear_logs(store: State<'_, AppStateStore>) -> Result<(), String> {
-  std::fs::write(store.log_path(), "").map_err(|e| e.to_string())?;
+pub fn kill_app(app_id: String, store: State<'_, AppStateStore>) -> Result<u32, CommandError> {
+  let app = store
+    .find_app(&app_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::AppNotFound, "App not found"))?;
+  let killed = kill_app_in_namespace(&store, &app, "sillyvpn-ns")?;
+  append_log(
+    &store.log_path(),
+    &format!("Killed {killed} processes for {}", app.label),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(killed)
+}
+
+#[tauri::command]
+pub fn set_app_kill_signal(
+  app_id: String,
+  signal: KillSignal,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store
+    .set_app_kill_signal(&app_id, signal)
+    .map_err(map_error)?;
+  append_log(&store.log_path(), "Updated app stop signal").map_err(|e| e.to_string())?;
   Ok(())
 }
 
 #[tauri::command]
-pub fn import_conf(path: String, store: State<'_, AppStateStore>) -> Result<(), String> {
-  let source = PathBuf::from(path);
-  if !source.exists() {
-    return Err("Config file not found".into());
+pub fn list_all_namespace_processes() -> Result<Vec<NamespaceProcesses>, CommandError> {
+  let mut result = Vec::new();
+  for namespace in sillyvpn_namespaces()? {
+    let Some(ns_inode) = read_netns_inode(&namespace)? else {
+      continue;
+    };
+    let processes = namespace_pids(ns_inode)?
+      .into_iter()
+      .map(|pid| {
+        let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+        NamespaceProcess {
+          pid: pid as u32,
+          comm: std::fs::read_to_string(proc_dir.join("comm"))
+            .map(|comm| comm.trim_end_matches('\n').to_string())
+            .unwrap_or_default(),
+          exe: std::fs::read_link(proc_dir.join("exe"))
+            .ok()
+            .map(|exe| exe.to_string_lossy().to_string()),
+        }
+      })
+      .collect();
+    result.push(NamespaceProcesses {
+      namespace,
+      processes,
+    });
+  }
+  Ok(result)
+}
+
+#[tauri::command(async)]
+pub fn kill_all_everywhere(store: State<'_, AppStateStore>) -> Result<PurgeReport, CommandError> {
+  let mut pids = Vec::new();
+  for namespace in sillyvpn_namespaces()? {
+    if let Some(ns_inode) = read_netns_inode(&namespace)? {
+      pids.extend(namespace_pids(ns_inode)?);
+    }
+  }
+  for pid in &pids {
+    unsafe {
+      libc::kill(*pid, libc::SIGTERM);
+    }
+  }
+  std::thread::sleep(Duration::from_millis(300));
+  for pid in &pids {
+    if std::fs::metadata(format!("/proc/{pid}")).is_ok() {
+      unsafe {
+        libc::kill(*pid, libc::SIGKILL);
+      }
+    }
   }
-  if source.extension().and_then(|s| s.to_str()) != Some("conf") {
-    return Err("Only .conf files are supported".into());
+  if store.state_snapshot().vpn_enabled {
+    shutdown_vpn(&store)?;
   }
+  let output =
+    run_helper_output(vec!["purge-namespaces".to_string()]).map_err(map_helper_error)?;
+  let namespaces_removed: Vec<String> =
+    serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  store.clear_app_pids();
+  append_log(
+    &store.log_path(),
+    &format!(
+      "Killed {} processes and removed {} namespaces",
+      pids.len(),
+      namespaces_removed.len()
+    ),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(PurgeReport {
+    killed: pids.len() as u32,
+    namespaces_removed,
+  })
+}
 
-  let tunnel = store.import_conf(&source).map_err(map_error)?;
-  append_log(store.log_path(), &format!("Imported tunnel {}", tunnel.name))
-    .map_err(|e| e.to_string())?;
-  Ok(())
+fn sillyvpn_namespaces() -> Result<Vec<String>, String> {
+  let entries = match std::fs::read_dir(NETNS_RUN_DIR) {
+    Ok(entries) => entries,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(err.to_string()),
+  };
+  let mut names: Vec<String> = entries
+    .flatten()
+    .filter_map(|entry| entry.file_name().into_string().ok())
+    .filter(|name| name.starts_with("sillyvpn-ns"))
+    .collect();
+  names.sort();
+  Ok(names)
+}
+
+fn namespace_pids(ns_inode: u64) -> Result<Vec<i32>, String> {
+  let mut pids = Vec::new();
+  for entry in std::fs::read_dir("/proc").map_err(|e| e.to_string())?.flatten() {
+    let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+      continue;
+    };
+    if process_in_namespace(&entry.path(), ns_inode) {
+      pids.push(pid);
+    }
+  }
+  Ok(pids)
 }
 
 #[tauri::command]
-pub fn add_app(path: String, label: String, store: State<'_, AppStateStore>) -> Result<(), String> {
-  let app_path = PathBuf::from(path);
-  if !app_path.exists() {
-    return Err("Binary not found".into());
+pub fn get_running_apps(store: State<'_, AppStateStore>) -> Result<Vec<String>, CommandError> {
+  Ok(running_app_ids(&store)?)
+}
+
+fn running_app_ids(store: &AppStateStore) -> Result<Vec<String>, String> {
+  let ns_inode = read_netns_inode("sillyvpn-ns")?;
+  let apps = store.state_snapshot().apps;
+  let mut running = Vec::new();
+  for app in apps {
+    let tracked = ns_inode.and_then(|inode| tracked_app_pid(store, &app.id, inode));
+    if tracked.is_some() || is_app_running_in_namespace(&app.path, "sillyvpn-ns")? {
+      running.push(app.id);
+    }
   }
+  Ok(running)
+}
+
+fn tracked_app_pid(store: &AppStateStore, app_id: &str, ns_inode: u64) -> Option<i32> {
   store
-    .add_app(&app_path, label)
-    .map_err(map_error)?;
-  append_log(store.log_path(), "Added VPN app").map_err(|e| e.to_string())?;
-  Ok(())
+    .app_pid(app_id)
+    .filter(|pid| pid_in_namespace(*pid, ns_inode))
+    .map(|pid| pid as i32)
+}
+
+fn pid_in_namespace(pid: u32, ns_inode: u64) -> bool {
+  process_in_namespace(Path::new(&format!("/proc/{pid}")), ns_inode)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionUsage {
+  pub rx_bytes: u64,
+  pub tx_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+  pub tunnels: usize,
+  pub apps: usize,
+  pub running_apps: Option<usize>,
+  pub active_tunnel: Option<String>,
+  pub session: Option<SessionUsage>,
+  pub total_connects: u64,
+  pub helper_version: Option<String>,
+  pub helper_up_to_date: bool,
+  pub nat_check: Option<NatCheck>,
 }
 
 #[tauri::command]
-pub fn remove_app(app_id: String, store: State<'_, AppStateStore>) -> Result<(), String> {
-  store.remove_app(&app_id).map_err(map_error)?;
-  append_log(store.log_path(), "Removed VPN app").map_err(|e| e.to_string())?;
-  Ok(())
+pub fn get_summary(store: State<'_, AppStateStore>) -> Result<Summary, CommandError> {
+  let state = store.state_snapshot();
+  let active = state
+    .active_tunnel_id
+    .as_deref()
+    .filter(|_| state.vpn_enabled)
+    .and_then(|tunnel_id| state.tunnels.iter().find(|tunnel| tunnel.id == tunnel_id));
+  let session = active
+    .filter(|tunnel| !tunnel.manage_routes_externally)
+    .and_then(|tunnel| interface_usage(&tunnel_ifname(&store, tunnel)));
+  let helper_version = installed_helper_version();
+  Ok(Summary {
+    tunnels: state.tunnels.len(),
+    apps: state.apps.len(),
+    running_apps: running_app_ids(&store).ok().map(|running| running.len()),
+    active_tunnel: active.map(|tunnel| tunnel.name.clone()),
+    session,
+    total_connects: state.tunnels.iter().map(|tunnel| tunnel.connect_count).sum(),
+    helper_up_to_date: helper_version.as_deref() == Some(env!("CARGO_PKG_VERSION")),
+    helper_version,
+    nat_check: store.nat_check().filter(|_| active.is_some()),
+  })
+}
+
+fn interface_usage(ifname: &str) -> Option<SessionUsage> {
+  let read = |name: &str| {
+    std::fs::read_to_string(format!("/sys/class/net/{ifname}/statistics/{name}"))
+      .ok()?
+      .trim()
+      .parse::<u64>()
+      .ok()
+  };
+  Some(SessionUsage {
+    rx_bytes: read("rx_bytes")?,
+    tx_bytes: read("tx_bytes")?,
+  })
+}
+
+#[tauri::command]
+pub fn refresh_running_state(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<String>, CommandError> {
+  let ns_inode = read_netns_inode("sillyvpn-ns")?;
+  let stale =
+    store.retain_app_pids(|pid| ns_inode.is_some_and(|inode| pid_in_namespace(pid, inode)));
+  if stale > 0 {
+    append_log(
+      &store.log_path(),
+      &format!("Dropped {stale} stale app PID(s) from tracking"),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  Ok(running_app_ids(&store)?)
 }
 
 #[tauri::command]
-pub fn enable_vpn(tunnel_id: String, store: State<'_, AppStateStore>) -> Result<(), String> {
+pub fn clear_app_tracking(store: State<'_, AppStateStore>) -> Result<usize, CommandError> {
+  let cleared = store.clear_app_pids();
+  append_log(
+    &store.log_path(),
+    &format!("Cleared tracking for {cleared} app PID(s)"),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(cleared)
+}
+
+#[tauri::command(async)]
+pub fn get_tunnel_connections(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<TunnelConnection>, CommandError> {
+  let output = run_probe(&store, vec!["conns".to_string()])?;
+  Ok(serde_json::from_str(output.trim()).map_err(|e| e.to_string())?)
+}
+
+#[tauri::command(async)]
+pub fn get_peer_status(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<PeerStatus>, CommandError> {
   let tunnel = store
     .find_tunnel(&tunnel_id)
-    .ok_or_else(|| "Tunnel not found".to_string())?;
-  let ifname = "wg-temp".to_string();
-
-  let args = vec![
-    "enable".to_string(),
-    "--config".to_string(),
-    tunnel.path.clone(),
-    "--ifname".to_string(),
-    ifname,
-  ];
-  run_helper_vec(args).map_err(map_helper_error)?;
-  store.set_vpn_enabled(true).map_err(map_error)?;
-  append_log(store.log_path(), "VPN enabled").map_err(|e| e.to_string())?;
-  Ok(())
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let content = std::fs::read_to_string(&tunnel.path).map_err(|e| e.to_string())?;
+  let state = store.state_snapshot();
+  let live: Vec<LivePeer> =
+    if state.vpn_enabled && state.active_tunnel_id.as_deref() == Some(tunnel.id.as_str()) {
+      let output = run_probe(&store, vec!["peers".to_string()])?;
+      serde_json::from_str(output.trim()).map_err(|e| e.to_string())?
+    } else {
+      Vec::new()
+    };
+  let now = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default();
+  let clock_skew_suspected = !live.is_empty()
+    && live.iter().any(|peer| peer.latest_handshake == 0)
+    && clock_status(false).clock_skew_suspected;
+  Ok(merge_peer_status(&parse_peers(&content), &live, now, clock_skew_suspected))
+}
+
+fn merge_peer_status(
+  configured: &[ConfigPeer],
+  live: &[LivePeer],
+  now: u64,
+  clock_skew_suspected: bool,
+) -> Vec<PeerStatus> {
+  let unexpected = live.iter().filter(|live| {
+    !configured
+      .iter()
+      .any(|peer| peer.public_key == live.public_key)
+  });
+  configured
+    .iter()
+    .map(|peer| (Some(peer), live.iter().find(|live| live.public_key == peer.public_key)))
+    .chain(unexpected.map(|live| (None, Some(live))))
+    .map(|(peer, live)| {
+      let last_handshake = live
+        .map(|live| live.latest_handshake)
+        .filter(|handshake| *handshake > 0);
+      PeerStatus {
+        public_key: peer
+          .map(|peer| peer.public_key.clone())
+          .or_else(|| live.map(|live| live.public_key.clone()))
+          .unwrap_or_default(),
+        endpoint: peer.and_then(|peer| peer.endpoint.clone()),
+        live_endpoint: live.and_then(|live| live.endpoint.clone()),
+        allowed_ips: match peer {
+          Some(peer) => peer.allowed_ips.clone(),
+          None => live.map(|live| live.allowed_ips.clone()).unwrap_or_default(),
+        },
+        last_handshake,
+        rx: live.map(|live| live.rx).unwrap_or_default(),
+        tx: live.map(|live| live.tx).unwrap_or_default(),
+        online: last_handshake
+          .is_some_and(|handshake| now.saturating_sub(handshake) <= PEER_ONLINE_WINDOW_SECS),
+        clock_skew_suspected: clock_skew_suspected && last_handshake.is_none(),
+        in_config: peer.is_some(),
+        in_interface: live.is_some(),
+      }
+    })
+    .collect()
+}
+
+#[tauri::command(async)]
+pub fn check_nat(
+  host: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<NatCheck, CommandError> {
+  let state = store.state_snapshot();
+  let tunnel = state
+    .active_tunnel_id
+    .as_deref()
+    .filter(|_| state.vpn_enabled)
+    .and_then(|tunnel_id| store.find_tunnel(tunnel_id))
+    .ok_or_else(|| CommandError::new(ErrorCode::VpnInactive, "VPN is not enabled"))?;
+  let host = match host {
+    Some(host) => host,
+    None => {
+      let content = std::fs::read_to_string(&tunnel.path).map_err(|e| e.to_string())?;
+      dns_servers(&content)
+        .into_iter()
+        .find(|server| server.is_ipv4())
+        .map(|server| server.to_string())
+        .unwrap_or_else(|| "1.1.1.1".to_string())
+    }
+  };
+  let output = run_probe(
+    &store,
+    vec!["check-nat".to_string(), "--host".to_string(), host.clone()],
+  )?;
+  let mut check: NatCheck = serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  check.detail = match check.status.as_str() {
+    "ok" => format!("NAT работает: {host} отвечает из namespace"),
+    "no_reply" => format!(
+      "Пакеты из namespace проходят NAT ({}), но {host} не ответил через туннель",
+      check.masqueraded_packets
+    ),
+    "masquerade_disabled" => "MASQUERADE отключён для туннеля, проверка не выполнялась".to_string(),
+    _ => match &check.firewall {
+      Some(firewall) => format!(
+        "Правила NAT установлены, но пакеты из namespace до них не доходят. Вероятно, их блокирует {firewall}: разрешите форвардинг с интерфейса svpn0"
+      ),
+      None => "Правила NAT установлены, но пакеты из namespace до них не доходят. Проверьте цепочку FORWARD межсетевого экрана хоста".to_string(),
+    },
+  };
+  if check.status == "nat_broken" {
+    append_log(&store.log_path(), &format!("NAT check failed: {}", check.detail))
+      .map_err(|e| e.to_string())?;
+  }
+  store.set_nat_check(Some(check.clone()));
+  Ok(check)
+}
+
+const ENDPOINT_PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[tauri::command(async)]
+pub fn check_endpoint_reachable(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<EndpointReachability>, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  if tunnel.endpoints.is_empty() {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "Tunnel has no peer Endpoint to probe",
+    ));
+  }
+  let results: Vec<EndpointReachability> = tunnel
+    .endpoints
+    .iter()
+    .map(|endpoint| {
+      let target = format!("{}:{}", endpoint.host, endpoint.port);
+      let address = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next());
+      let (status, detail) = match address {
+        None => (
+          "unresolved",
+          format!("Не удалось разрешить {}: проверьте DNS хоста", endpoint.host),
+        ),
+        Some(addr) => match probe_udp_endpoint(addr) {
+          Ok(true) => ("ok", format!("{target} ответил на UDP-пакет")),
+          Ok(false) => (
+            "no_response",
+            format!(
+              "{target} не ответил и не отклонил пакет. WireGuard молча игнорирует неаутентифицированные пакеты, доступность подтвердит только handshake"
+            ),
+          ),
+          Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => (
+            "refused",
+            format!("{target} отвечает ICMP port unreachable: на этом порту нет WireGuard"),
+          ),
+          Err(err)
+            if matches!(err.raw_os_error(), Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH)) =>
+          {
+            ("no_route", format!("Нет маршрута до {target} в сети хоста: {err}"))
+          }
+          Err(err) => ("unreachable", format!("{target} недоступен: {err}")),
+        },
+      };
+      EndpointReachability {
+        host: endpoint.host.clone(),
+        port: endpoint.port,
+        address: address.map(|addr| addr.ip().to_string()),
+        status: status.to_string(),
+        detail,
+      }
+    })
+    .collect();
+  for result in results.iter().filter(|result| result.status != "ok") {
+    append_log(
+      &store.log_path(),
+      &format!("Endpoint check for {}: {}", tunnel.name, result.detail),
+    )
+    .map_err(|e| e.to_string())?;
+  }
+  Ok(results)
+}
+
+fn probe_udp_endpoint(addr: SocketAddr) -> std::io::Result<bool> {
+  let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+  let socket = UdpSocket::bind(bind)?;
+  socket.connect(addr)?;
+  socket.set_read_timeout(Some(ENDPOINT_PROBE_TIMEOUT))?;
+  socket.send(&handshake_initiation_probe())?;
+  let mut buf = [0u8; 64];
+  match socket.recv(&mut buf) {
+    Ok(_) => Ok(true),
+    Err(err)
+      if matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+      ) =>
+    {
+      Ok(false)
+    }
+    Err(err) => Err(err),
+  }
+}
+
+const WG_HANDSHAKE_INITIATION_LEN: usize = 148;
+
+fn handshake_initiation_probe() -> [u8; WG_HANDSHAKE_INITIATION_LEN] {
+  let mut message = [0u8; WG_HANDSHAKE_INITIATION_LEN];
+  message[0] = 1;
+  for chunk in message[4..].chunks_mut(16) {
+    chunk.copy_from_slice(&Uuid::new_v4().as_bytes()[..chunk.len()]);
+  }
+  message
 }
 
+const LATENCY_INTERVAL_MAX: u64 = 60;
+
 #[tauri::command]
-pub fn disable_vpn(store: State<'_, AppStateStore>) -> Result<(), String> {
-  let args = vec!["disable".to_string()];
-  run_helper_vec(args).map_err(map_helper_error)?;
-  store.set_vpn_enabled(false).map_err(map_error)?;
-  append_log(store.log_path(), "VPN disabled").map_err(|e| e.to_string())?;
+pub fn start_latency_monitor(
+  tunnel_id: String,
+  interval_secs: Option<u64>,
+  store: State<'_, AppStateStore>,
+  app: AppHandle,
+) -> Result<(), CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let state = store.state_snapshot();
+  if !state.vpn_enabled || state.active_tunnel_id.as_deref() != Some(tunnel.id.as_str()) {
+    return Err(CommandError::new(ErrorCode::VpnInactive, "Tunnel is not active"));
+  }
+  let content = std::fs::read_to_string(&tunnel.path).map_err(|e| e.to_string())?;
+  let endpoint = peer_endpoints(&content)?
+    .into_iter()
+    .next()
+    .ok_or_else(|| CommandError::new(ErrorCode::InvalidInput, "Tunnel has no peer endpoint"))?;
+  let interval = interval_secs.unwrap_or(1);
+  if !(1..=LATENCY_INTERVAL_MAX).contains(&interval) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Interval must be 1-{LATENCY_INTERVAL_MAX} seconds"),
+    ));
+  }
+  let ping = find_executable("ping").ok_or_else(|| {
+    CommandError::new(ErrorCode::HelperFailed, "ping not found, install iputils")
+  })?;
+  let child = spawn_ping(&ping, &endpoint.host, interval)
+    .map_err(|e| format!("Failed to start ping: {e}"))?;
+  let stop = store.begin_latency_monitor();
+  spawn_latency_monitor(app, tunnel.id, child, stop);
   Ok(())
 }
 
 #[tauri::command]
-pub fn run_app_via_vpn(app_id: String, store: State<'_, AppStateStore>) -> Result<(), String> {
-  let app = store
-    .find_app(&app_id)
-    .ok_or_else(|| "App not found".to_string())?;
-  ensure_app_not_running(&app.path)?;
-  store
-    .set_last_app_id(&app_id)
-    .map_err(map_error)?;
-  let mut args = vec!["run".to_string(), "--bin".to_string(), app.path.clone()];
-  for (key, value) in collect_ui_env() {
-    args.push("--env".to_string());
-    args.push(format!("{}={}", key, value));
+pub fn stop_latency_monitor(store: State<'_, AppStateStore>) -> Result<bool, CommandError> {
+  Ok(store.stop_latency_monitor())
+}
+
+#[tauri::command]
+pub fn get_tunnel_coverage(
+  tunnel_id: String,
+  store: State<'_, AppStateStore>,
+) -> Result<TunnelCoverage, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let content = std::fs::read_to_string(&tunnel.path).map_err(|e| e.to_string())?;
+  let allowed_ips: Vec<String> = parse_peers(&content)
+    .into_iter()
+    .flat_map(|peer| peer.allowed_ips)
+    .collect();
+  let (v4, v6) = aggregate_cidrs(&allowed_ips);
+  Ok(TunnelCoverage {
+    full_tunnel_v4: v4.iter().any(|cidr| cidr == "0.0.0.0/0"),
+    full_tunnel_v6: v6.iter().any(|cidr| cidr == "::/0"),
+    routed_cidrs: v4.into_iter().chain(v6).collect(),
+  })
+}
+
+#[tauri::command(async)]
+pub fn get_last_operation(
+  store: State<'_, AppStateStore>,
+) -> Result<Option<LastOperation>, CommandError> {
+  let output = run_probe(&store, vec!["last-op".to_string()])?;
+  Ok(serde_json::from_str(output.trim()).map_err(|e| e.to_string())?)
+}
+
+#[tauri::command(async)]
+pub fn get_wg_kernel_messages(
+  tunnel_id: String,
+  since_secs: Option<u64>,
+  store: State<'_, AppStateStore>,
+) -> Result<KernelMessages, CommandError> {
+  let tunnel = store
+    .find_tunnel(&tunnel_id)
+    .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+  let ifname = tunnel_ifname(&store, &tunnel);
+  let mut args = vec!["kernel-log".to_string(), "--ifname".to_string(), ifname.clone()];
+  if let Some(since) = since_secs {
+    args.push("--since".to_string());
+    args.push(since.max(1).to_string());
   }
-  let log_path = store.log_path().to_path_buf();
-  let app_label = app.label.clone();
-  std::thread::spawn(move || {
-    if let Err(err) = run_helper_vec(args).map_err(map_helper_error) {
-      let _ = append_log(
-        &log_path,
-        &format!("Failed to start app via VPN: {} ({})", app_label, err),
-      );
-    }
-  });
+  let output = run_probe(&store, args)?;
+  let mut messages: KernelMessages =
+    serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  messages.ifname = ifname;
+  Ok(messages)
+}
+
+#[tauri::command(async)]
+pub fn tunnel_status(store: State<'_, AppStateStore>) -> Result<TunnelStatus, CommandError> {
+  if !store.state_snapshot().vpn_enabled {
+    return Ok(TunnelStatus::default());
+  }
+  let output = run_probe(&store, vec!["status".to_string()])?;
+  Ok(serde_json::from_str(output.trim()).map_err(|e| e.to_string())?)
+}
+
+#[tauri::command(async)]
+pub fn get_transfer_stats(store: State<'_, AppStateStore>) -> Result<TransferStats, CommandError> {
+  if !store.state_snapshot().vpn_enabled {
+    return Ok(TransferStats::default());
+  }
+  let output = run_probe(&store, vec!["stats".to_string()])?;
+  let peers = serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  Ok(TransferStats { peers })
+}
+
+#[tauri::command(async)]
+pub fn dry_run_network_config(
+  subnet: Option<String>,
+  table: Option<u32>,
+  fwmark: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<NetworkPlan, CommandError> {
+  let (subnet, table, fwmark) = parse_network_overrides(subnet, table, fwmark)?;
+  let mut args = vec!["plan-network".to_string()];
+  if let Some(subnet) = subnet {
+    args.push("--subnet".to_string());
+    args.push(subnet);
+  }
+  if let Some(table) = table {
+    args.push("--table".to_string());
+    args.push(table.to_string());
+  }
+  if let Some(fwmark) = fwmark {
+    args.push("--fwmark".to_string());
+    args.push(format!("0x{fwmark:x}"));
+  }
+  let output = run_probe(&store, args)?;
+  let plan: NetworkPlan = serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
   append_log(
-    store.log_path(),
-    &format!("Started app via VPN: {}", app.label),
+    &store.log_path(),
+    &format!(
+      "Planned network config {} table {} fwmark {}: {} conflict(s)",
+      plan.subnet,
+      plan.table,
+      plan.fwmark,
+      plan.conflicts.len()
+    ),
   )
   .map_err(|e| e.to_string())?;
-  Ok(())
+  Ok(plan)
 }
 
-#[tauri::command]
-pub fn kill_all_apps(store: State<'_, AppStateStore>) -> Result<(), String> {
-  let apps = store.state_snapshot().apps;
-  let mut total = 0;
-  for app in apps {
-    total += kill_by_path_in_namespace(&app.path, "sillyvpn-ns")?;
+#[tauri::command(async)]
+pub fn get_per_app_traffic(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<AppTraffic>, CommandError> {
+  if !store.state_snapshot().vpn_enabled {
+    return Ok(Vec::new());
   }
+  let output = run_probe(&store, vec!["app-traffic".to_string()])?;
+  let mut traffic: Vec<AppTraffic> =
+    serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  for entry in &mut traffic {
+    entry.label = store
+      .find_app(&entry.app_id)
+      .map(|app| app.label)
+      .unwrap_or_else(|| entry.app_id.clone());
+  }
+  traffic.sort_by_key(|entry| std::cmp::Reverse(entry.rx_bytes + entry.tx_bytes));
+  Ok(traffic)
+}
+
+#[tauri::command(async)]
+pub fn check_conflicts(
+  store: State<'_, AppStateStore>,
+) -> Result<Vec<RoutingConflict>, CommandError> {
+  let output = run_probe(&store, vec!["check-conflicts".to_string()])?;
+  Ok(serde_json::from_str(output.trim()).map_err(|e| e.to_string())?)
+}
+
+#[tauri::command(async)]
+pub fn run_diagnostic(
+  tool: String,
+  args: Vec<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<DiagnosticOutput, CommandError> {
+  if !DIAGNOSTIC_TOOLS.contains(&tool.as_str()) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Инструмент {tool} не разрешён. Доступны: {}", DIAGNOSTIC_TOOLS.join(", ")),
+    ));
+  }
+  if !store.state_snapshot().vpn_enabled {
+    return Err(CommandError::new(ErrorCode::VpnInactive, "VPN is not enabled"));
+  }
+  let mut helper_args = vec!["diag".to_string(), "--tool".to_string(), tool.clone()];
+  for arg in &args {
+    helper_args.push("--arg".to_string());
+    helper_args.push(arg.clone());
+  }
+  let output = run_probe(&store, helper_args)?;
+  let result: DiagnosticOutput =
+    serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
   append_log(
-    store.log_path(),
-    &format!("Killed {} processes for VPN apps", total),
+    &store.log_path(),
+    &format!("Diagnostic {tool} {} exited with {}", args.join(" "), result.exit_code),
   )
   .map_err(|e| e.to_string())?;
+  Ok(result)
+}
+
+#[tauri::command]
+pub fn cancel_probe(store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  store.cancel_probes();
   Ok(())
 }
 
 #[tauri::command]
-pub fn get_running_apps(store: State<'_, AppStateStore>) -> Result<Vec<String>, String> {
-  let apps = store.state_snapshot().apps;
-  let mut running = Vec::new();
-  for app in apps {
-    if is_app_running_in_namespace(&app.path, "sillyvpn-ns")? {
-      running.push(app.id);
+pub fn set_probe_settings(
+  timeout_secs: u64,
+  retries: u32,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  check_probe_settings(timeout_secs, retries)?;
+  store
+    .set_probe_settings(timeout_secs, retries)
+    .map_err(map_error)?;
+  Ok(())
+}
+
+fn check_probe_settings(timeout_secs: u64, retries: u32) -> Result<(), CommandError> {
+  if !(1..=300).contains(&timeout_secs) || retries > 5 {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "Timeout must be 1-300 seconds and retries at most 5",
+    ));
+  }
+  Ok(())
+}
+
+fn run_probe(store: &AppStateStore, mut args: Vec<String>) -> Result<String, CommandError> {
+  let state = store.state_snapshot();
+  args.push("--timeout".to_string());
+  args.push(state.probe_timeout_secs.to_string());
+  let timeout = Duration::from_secs(state.probe_timeout_secs);
+  let cancel = store.begin_probe();
+  let result = retry_probe(store, &args, timeout, state.probe_retries, &cancel);
+  store.finish_probe(&cancel);
+  result
+}
+
+fn retry_probe(
+  store: &AppStateStore,
+  args: &[String],
+  timeout: Duration,
+  retries: u32,
+  cancel: &AtomicBool,
+) -> Result<String, CommandError> {
+  for attempt in 0..=retries {
+    let outcome = run_helper_probe(args.to_vec(), timeout, cancel).map_err(map_helper_error)?;
+    match outcome {
+      ProbeOutcome::Finished(output) => return Ok(output),
+      ProbeOutcome::Abandoned => {
+        let _ = append_log(
+          &store.log_path(),
+          &format!("Probe {} abandoned, the helper exits on its own timeout", args[0]),
+        );
+        return Err(CommandError::new(ErrorCode::Cancelled, "Probe abandoned"));
+      }
+      ProbeOutcome::TimedOut => {
+        let _ = append_log(
+          &store.log_path(),
+          &format!("Probe {} timed out (attempt {})", args[0], attempt + 1),
+        );
+      }
     }
   }
-  Ok(running)
+  Err(CommandError::new(
+    ErrorCode::TimedOut,
+    format!("Probe timed out after {}s", timeout.as_secs()),
+  ))
+}
+
+#[tauri::command]
+pub fn wipe_runtime_secrets(store: State<'_, AppStateStore>) -> Result<Vec<String>, CommandError> {
+  let output =
+    run_helper_output(vec!["wipe-secrets".to_string()]).map_err(map_helper_error)?;
+  let wiped: Vec<String> = serde_json::from_str(output.trim()).map_err(|e| e.to_string())?;
+  append_log(
+    &store.log_path(),
+    &format!("Wiped {} stray runtime config(s)", wiped.len()),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(wiped)
 }
 
-fn ensure_app_not_running(path: &str) -> Result<(), String> {
+fn ensure_app_not_running(path: &str) -> Result<(), CommandError> {
   if is_app_running(path)? {
-    return Err(
-      "Приложение уже запущено. Закройте его полностью и повторите запуск через VPN."
-        .to_string(),
-    );
+    return Err(CommandError::new(
+      ErrorCode::AppRunning,
+      "Приложение уже запущено. Закройте его полностью и повторите запуск через VPN.",
+    ));
   }
   Ok(())
 }
 
 fn is_app_running(path: &str) -> Result<bool, String> {
   let target = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
-  let target_base = target
-    .file_name()
-    .and_then(|s| s.to_str())
-    .unwrap_or("")
-    .to_string();
-  let target_base_lower = target_base.to_ascii_lowercase();
+  let target_base = target.file_name().unwrap_or_default().to_os_string();
+  let target_base_lower = target_base.to_string_lossy().to_ascii_lowercase();
   for entry in std::fs::read_dir("/proc").map_err(|e| e.to_string())? {
     let entry = match entry {
       Ok(entry) => entry,
@@ -206,12 +2795,8 @@ fn is_app_running_in_namespace(path: &str, ns_name: &str) -> Result<bool, String
     Err(err) => return Err(err),
   };
   let target = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
-  let target_base = target
-    .file_name()
-    .and_then(|s| s.to_str())
-    .unwrap_or("")
-    .to_string();
-  let target_base_lower = target_base.to_ascii_lowercase();
+  let target_base = target.file_name().unwrap_or_default().to_os_string();
+  let target_base_lower = target_base.to_string_lossy().to_ascii_lowercase();
   for entry in std::fs::read_dir("/proc").map_err(|e| e.to_string())? {
     let entry = match entry {
       Ok(entry) => entry,
@@ -242,19 +2827,54 @@ fn is_app_running_in_namespace(path: &str, ns_name: &str) -> Result<bool, String
   Ok(false)
 }
 
-fn kill_by_path_in_namespace(path: &str, ns_name: &str) -> Result<u32, String> {
+fn kill_app_in_namespace(
+  store: &AppStateStore,
+  app: &AppItem,
+  ns_name: &str,
+) -> Result<u32, String> {
+  let mut pids = app_pids_in_namespace(&app.path, ns_name)?;
+  let tracked = read_netns_inode(ns_name)?
+    .and_then(|inode| tracked_app_pid(store, &app.id, inode))
+    .filter(|pid| !pids.contains(pid));
+  pids.extend(tracked);
+  store.forget_app_pid(&app.id);
+  if pids.is_empty() {
+    return Ok(0);
+  }
+
+  let signal = app.kill_signal;
+  let first = match signal {
+    KillSignal::KillOnly => libc::SIGKILL,
+    KillSignal::TermThenKill | KillSignal::TermOnly => libc::SIGTERM,
+  };
+  for pid in &pids {
+    unsafe {
+      libc::kill(*pid, first);
+    }
+  }
+  if signal != KillSignal::TermThenKill {
+    return Ok(pids.len() as u32);
+  }
+  std::thread::sleep(Duration::from_millis(300));
+  for pid in &pids {
+    if std::fs::metadata(format!("/proc/{pid}")).is_ok() {
+      unsafe {
+        libc::kill(*pid, libc::SIGKILL);
+      }
+    }
+  }
+  Ok(pids.len() as u32)
+}
+
+fn app_pids_in_namespace(path: &str, ns_name: &str) -> Result<Vec<i32>, String> {
   let ns_inode = match read_netns_inode(ns_name) {
     Ok(Some(inode)) => inode,
-    Ok(None) => return Ok(0),
+    Ok(None) => return Ok(Vec::new()),
     Err(err) => return Err(err),
   };
   let target = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
-  let target_base = target
-    .file_name()
-    .and_then(|s| s.to_str())
-    .unwrap_or("")
-    .to_string();
-  let target_base_lower = target_base.to_ascii_lowercase();
+  let target_base = target.file_name().unwrap_or_default().to_os_string();
+  let target_base_lower = target_base.to_string_lossy().to_ascii_lowercase();
   let mut pids = Vec::new();
   for entry in std::fs::read_dir("/proc").map_err(|e| e.to_string())? {
     let entry = match entry {
@@ -283,84 +2903,71 @@ fn kill_by_path_in_namespace(path: &str, ns_name: &str) -> Result<u32, String> {
       continue;
     }
     if process_in_namespace(&proc_path, ns_inode) {
-      pids.push(pid);
-    }
-  }
-
-  if pids.is_empty() {
-    return Ok(0);
-  }
-
-  for pid in &pids {
-    unsafe {
-      libc::kill(*pid, libc::SIGTERM);
-    }
-  }
-  std::thread::sleep(Duration::from_millis(300));
-  for pid in &pids {
-    if std::fs::metadata(format!("/proc/{pid}")).is_ok() {
-      unsafe {
-        libc::kill(*pid, libc::SIGKILL);
-      }
+      pids.push(pid);
     }
   }
-  Ok(pids.len() as u32)
+  Ok(pids)
 }
 
 fn process_matches_path(
-  proc_dir: &PathBuf,
-  target: &PathBuf,
-  target_base: &str,
+  proc_dir: &Path,
+  target: &Path,
+  target_base: &OsStr,
   target_base_lower: &str,
 ) -> bool {
   let exe_path = proc_dir.join("exe");
   if let Ok(link) = std::fs::read_link(&exe_path) {
-    if &link == target {
+    if link == target {
       return true;
     }
   }
 
+  let base_matches = |base: &OsStr| {
+    !target_base.is_empty()
+      && (base == target_base
+        || base.to_string_lossy().to_ascii_lowercase() == target_base_lower)
+  };
+
   let cmdline_path = proc_dir.join("cmdline");
   if let Ok(raw) = std::fs::read(cmdline_path) {
-    let parts: Vec<String> = raw
-      .split(|b| *b == 0)
-      .filter_map(|slice| {
-        if slice.is_empty() {
-          None
-        } else {
-          Some(String::from_utf8_lossy(slice).to_string())
-        }
-      })
-      .collect();
-    for arg in parts {
-      if &arg == target.to_string_lossy().as_ref() {
+    let mut args = raw.split(|b| *b == 0).filter(|slice| !slice.is_empty());
+    if let Some(argv0) = args.next() {
+      if argv0
+        .strip_prefix(target.as_os_str().as_bytes())
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(b" "))
+      {
         return true;
       }
-      if !target_base.is_empty() {
-        if let Some(base) = std::path::Path::new(&arg).file_name().and_then(|s| s.to_str()) {
-          if base == target_base || base.to_ascii_lowercase() == target_base_lower {
-            return true;
-          }
-        }
+      let joined_cmdline =
+        argv0.contains(&b' ') && !Path::new(OsStr::from_bytes(argv0)).exists();
+      let program = if joined_cmdline {
+        argv0.split(|b| *b == b' ').next().unwrap_or(argv0)
+      } else {
+        argv0
+      };
+      if Path::new(OsStr::from_bytes(program)).file_name().is_some_and(base_matches) {
+        return true;
       }
     }
+    if args.any(|arg| Path::new(OsStr::from_bytes(arg)) == target) {
+      return true;
+    }
   }
 
-  if !target_base.is_empty() {
-    let comm_path = proc_dir.join("comm");
-    if let Ok(comm) = std::fs::read_to_string(comm_path) {
-      let comm = comm.trim();
-      if comm == target_base || comm.to_ascii_lowercase() == target_base_lower {
-        return true;
-      }
+  let comm_path = proc_dir.join("comm");
+  if let Ok(comm) = std::fs::read(comm_path) {
+    if base_matches(OsStr::from_bytes(comm.strip_suffix(b"\n").unwrap_or(&comm))) {
+      return true;
     }
   }
 
   false
 }
 
+const NETNS_RUN_DIR: &str = "/var/run/netns";
+
 fn read_netns_inode(ns_name: &str) -> Result<Option<u64>, String> {
-  let ns_path = format!("/var/run/netns/{ns_name}");
+  let ns_path = format!("{NETNS_RUN_DIR}/{ns_name}");
   match std::fs::metadata(ns_path) {
     Ok(meta) => Ok(Some(meta.ino())),
     Err(err) => {
@@ -373,7 +2980,7 @@ fn read_netns_inode(ns_name: &str) -> Result<Option<u64>, String> {
   }
 }
 
-fn process_in_namespace(proc_dir: &PathBuf, ns_inode: u64) -> bool {
+fn process_in_namespace(proc_dir: &Path, ns_inode: u64) -> bool {
   let ns_path = proc_dir.join("ns/net");
   match std::fs::metadata(ns_path) {
     Ok(meta) => meta.ino() == ns_inode,
@@ -381,7 +2988,72 @@ fn process_in_namespace(proc_dir: &PathBuf, ns_inode: u64) -> bool {
   }
 }
 
-fn collect_ui_env() -> Vec<(String, String)> {
+fn inspect_binary_file(path: &Path) -> Result<BinaryInfo, String> {
+  let mut header = [0u8; 256];
+  let read = std::fs::File::open(path)
+    .and_then(|mut file| file.read(&mut header))
+    .map_err(|e| e.to_string())?;
+  let header = &header[..read];
+  let executable = std::fs::metadata(path)
+    .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    .unwrap_or(false);
+
+  if header.starts_with(b"\x7fELF") {
+    let warning = if executable {
+      None
+    } else {
+      Some("Binary is not marked executable (chmod +x)".to_string())
+    };
+    return Ok(BinaryInfo {
+      kind: BinaryKind::Elf,
+      interpreter: None,
+      executable,
+      warning,
+    });
+  }
+
+  if let Some(rest) = header.strip_prefix(b"#!") {
+    let line = rest.split(|b| *b == b'\n').next().unwrap_or(&[]);
+    let shebang = String::from_utf8_lossy(line).trim().to_string();
+    let (interpreter, warning) = if shebang.is_empty() {
+      (
+        Some("/bin/sh".to_string()),
+        Some("Script has an empty shebang, it will be launched via /bin/sh".to_string()),
+      )
+    } else if executable {
+      (None, None)
+    } else {
+      (
+        Some(shebang.clone()),
+        Some(format!("Script is not marked executable, it will be launched via {shebang}")),
+      )
+    };
+    return Ok(BinaryInfo {
+      kind: BinaryKind::Script,
+      interpreter,
+      executable,
+      warning,
+    });
+  }
+
+  if !header.is_empty() && !header.contains(&0) {
+    return Ok(BinaryInfo {
+      kind: BinaryKind::Script,
+      interpreter: Some("/bin/sh".to_string()),
+      executable,
+      warning: Some("Script has no shebang, it will be launched via /bin/sh".to_string()),
+    });
+  }
+
+  Ok(BinaryInfo {
+    kind: BinaryKind::Unknown,
+    interpreter: None,
+    executable,
+    warning: Some("File is neither an ELF binary nor a script and may not launch".to_string()),
+  })
+}
+
+fn collect_ui_env(extra_keys: &[String]) -> Vec<(String, String)> {
   let keys = [
     "DISPLAY",
     "WAYLAND_DISPLAY",
@@ -394,7 +3066,7 @@ fn collect_ui_env() -> Vec<(String, String)> {
     "LOGNAME",
   ];
   let mut out = Vec::new();
-  for key in keys {
+  for key in keys.iter().copied().chain(extra_keys.iter().map(|key| key.as_str())) {
     if let Ok(value) = std::env::var(key) {
       if !value.trim().is_empty() {
         out.push((key.to_string(), value));
@@ -405,7 +3077,7 @@ fn collect_ui_env() -> Vec<(String, String)> {
 }
 
 #[tauri::command]
-pub fn set_last_tunnel(tunnel_id: String, store: State<'_, AppStateStore>) -> Result<(), String> {
+pub fn set_last_tunnel(tunnel_id: String, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
   store
     .set_last_tunnel_id(&tunnel_id)
     .map_err(map_error)?;
@@ -413,11 +3085,207 @@ pub fn set_last_tunnel(tunnel_id: String, store: State<'_, AppStateStore>) -> Re
 }
 
 #[tauri::command]
-pub fn set_last_app(app_id: String, store: State<'_, AppStateStore>) -> Result<(), String> {
+pub fn set_last_app(app_id: String, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
   store.set_last_app_id(&app_id).map_err(map_error)?;
   Ok(())
 }
 
+#[tauri::command]
+pub fn set_data_dir(path: String, store: State<'_, AppStateStore>) -> Result<String, CommandError> {
+  let target = PathBuf::from(path);
+  if !target.is_absolute() {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "Data directory must be an absolute path",
+    ));
+  }
+  let data_dir = store.set_data_dir(&target).map_err(map_error)?;
+  apply_privileged_debug(&store);
+  append_log(
+    &store.log_path(),
+    &format!("Moved data directory to {}", data_dir.display()),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(data_dir.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+pub fn set_privileged_debug(enabled: bool, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  store.set_privileged_debug(enabled).map_err(map_error)?;
+  apply_privileged_debug(&store);
+  append_log(
+    &store.log_path(),
+    if enabled {
+      "Privileged debug logging enabled"
+    } else {
+      "Privileged debug logging disabled"
+    },
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+pub fn apply_privileged_debug(store: &AppStateStore) {
+  set_debug_log(store.state_snapshot().privileged_debug.then(|| store.log_path()));
+}
+
+#[tauri::command]
+pub fn set_app_output_limit(limit_kib: u64, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  check_app_output_limit(limit_kib)?;
+  store.set_app_output_limit(limit_kib).map_err(map_error)?;
+  Ok(())
+}
+
+fn check_app_output_limit(limit_kib: u64) -> Result<(), CommandError> {
+  if !(16..=16384).contains(&limit_kib) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "Captured output limit must be 16-16384 KiB",
+    ));
+  }
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_app_log_rotation(
+  limit_kib: u64,
+  compress: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  check_app_log_limit(limit_kib)?;
+  store.set_app_log_rotation(limit_kib, compress).map_err(map_error)?;
+  Ok(())
+}
+
+fn check_app_log_limit(limit_kib: u64) -> Result<(), CommandError> {
+  if !(64..=65536).contains(&limit_kib) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      "App log limit must be 64-65536 KiB",
+    ));
+  }
+  Ok(())
+}
+
+const BANDWIDTH_LIMIT_MAX_MBPS: u32 = 10_000;
+
+#[tauri::command(async)]
+pub fn set_bandwidth_limit(
+  limit_mbps: Option<u32>,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  check_bandwidth_limit(limit_mbps)?;
+  if store.state_snapshot().vpn_enabled {
+    let args = match limit_mbps {
+      Some(limit) => vec!["shape".to_string(), "--rate".to_string(), limit.to_string()],
+      None => vec!["shape".to_string(), "--clear".to_string()],
+    };
+    run_helper_vec(args).map_err(map_helper_error)?;
+  }
+  store.set_bandwidth_limit(limit_mbps).map_err(map_error)?;
+  let message = match limit_mbps {
+    Some(limit) => format!("Bandwidth limit set to {limit} Mbit/s"),
+    None => "Bandwidth limit removed".to_string(),
+  };
+  append_log(&store.log_path(), &message).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+fn check_bandwidth_limit(limit_mbps: Option<u32>) -> Result<(), CommandError> {
+  if limit_mbps.is_some_and(|limit| !(1..=BANDWIDTH_LIMIT_MAX_MBPS).contains(&limit)) {
+    return Err(CommandError::new(
+      ErrorCode::InvalidInput,
+      format!("Bandwidth limit must be 1-{BANDWIDTH_LIMIT_MAX_MBPS} Mbit/s"),
+    ));
+  }
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_randomize_subnet(enabled: bool, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  store.set_randomize_subnet(enabled).map_err(map_error)?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_kill_apps_on_disable(
+  enabled: bool,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store.set_kill_apps_on_disable(enabled).map_err(map_error)?;
+  append_log(
+    &store.log_path(),
+    if enabled {
+      "Apps will be killed when VPN is disabled"
+    } else {
+      "Apps will keep running when VPN is disabled"
+    },
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_config_via_stdin(enabled: bool, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  store.set_config_via_stdin(enabled).map_err(map_error)?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_extra_env_keys(keys: Vec<String>, store: State<'_, AppStateStore>) -> Result<(), CommandError> {
+  let normalized = normalize_env_keys(keys)?;
+  store.set_extra_env_keys(normalized).map_err(map_error)?;
+  Ok(())
+}
+
+fn normalize_env_keys(keys: Vec<String>) -> Result<Vec<String>, CommandError> {
+  let mut normalized: Vec<String> = Vec::new();
+  for key in keys {
+    let key = key.trim().to_string();
+    if key.is_empty() {
+      continue;
+    }
+    if !is_valid_env_key(&key) {
+      return Err(CommandError::new(
+        ErrorCode::InvalidInput,
+        format!("Invalid environment variable name: {key}"),
+      ));
+    }
+    if !normalized.contains(&key) {
+      normalized.push(key);
+    }
+  }
+  Ok(normalized)
+}
+
+fn is_valid_env_key(key: &str) -> bool {
+  let mut chars = key.chars();
+  let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+  valid
+    && !key.starts_with("LD_")
+    && !key.starts_with("MALLOC_")
+    && !matches!(key, "GCONV_PATH" | "GLIBC_TUNABLES" | "LOCPATH" | "NLSPATH")
+}
+
+#[tauri::command]
+pub fn set_network_change_action(
+  action: NetworkChangeAction,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store.set_network_change_action(action).map_err(map_error)?;
+  Ok(())
+}
+
+#[tauri::command]
+pub fn set_suspend_action(
+  action: SuspendAction,
+  store: State<'_, AppStateStore>,
+) -> Result<(), CommandError> {
+  store.set_suspend_action(action).map_err(map_error)?;
+  Ok(())
+}
+
 #[derive(Debug, Serialize)]
 pub struct PolkitStatus {
   pub running: bool,
@@ -425,7 +3293,7 @@ pub struct PolkitStatus {
 }
 
 #[tauri::command]
-pub fn check_polkit_agent() -> Result<PolkitStatus, String> {
+pub fn check_polkit_agent() -> Result<PolkitStatus, CommandError> {
   let patterns = [
     "polkit-kde-authentication-agent-1",
     "polkit-gnome-authentication-agent-1",
@@ -451,12 +3319,458 @@ pub fn check_polkit_agent() -> Result<PolkitStatus, String> {
   Ok(PolkitStatus { running, detail })
 }
 
+#[derive(Debug, Serialize)]
+pub struct GroupMembership {
+  pub group: String,
+  pub user: Option<String>,
+  pub exists: bool,
+  pub member: bool,
+  pub active_in_session: bool,
+  pub detail: String,
+}
+
+#[tauri::command]
+pub fn check_privilege_group(group: String) -> Result<GroupMembership, CommandError> {
+  let group = group.trim().to_string();
+  if group.is_empty()
+    || !group
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+  {
+    return Err(CommandError::new(ErrorCode::InvalidInput, "Invalid group name"));
+  }
+  let uid = unsafe { libc::getuid() };
+  let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+  let account = passwd.lines().find_map(|line| {
+    let fields: Vec<&str> = line.split(':').collect();
+    (fields.len() > 3 && fields[2].parse::<u32>().ok() == Some(uid))
+      .then(|| (fields[0].to_string(), fields[3].parse::<u32>().ok()))
+  });
+  let user = account
+    .as_ref()
+    .map(|(name, _)| name.clone())
+    .or_else(|| std::env::var("USER").ok());
+  let groups = std::fs::read_to_string("/etc/group").unwrap_or_default();
+  let entry = groups.lines().find_map(|line| {
+    let fields: Vec<&str> = line.split(':').collect();
+    (fields.len() > 3 && fields[0] == group).then(|| {
+      let gid = fields[2].parse::<u32>().ok();
+      let members: Vec<String> = fields[3]
+        .split(',')
+        .filter(|member| !member.is_empty())
+        .map(str::to_string)
+        .collect();
+      (gid, members)
+    })
+  });
+  let session_groups = session_group_ids();
+  let (exists, member, active_in_session) = match &entry {
+    Some((gid, members)) => {
+      let primary = gid.is_some() && account.as_ref().and_then(|(_, primary)| *primary) == *gid;
+      let listed = user.as_ref().is_some_and(|user| members.contains(user));
+      let active = gid.is_some_and(|gid| session_groups.contains(&gid));
+      (true, primary || listed || active, active)
+    }
+    None => (false, false, false),
+  };
+  let user_name = user.clone().unwrap_or_else(|| "$USER".to_string());
+  let detail = if !exists {
+    format!("Group {group} does not exist on this system")
+  } else if active_in_session {
+    format!("{user_name} is a member of {group}")
+  } else if member {
+    format!("{user_name} was added to {group}; log out and back in for it to take effect")
+  } else {
+    format!("{user_name} is not in {group}. Add it with: sudo usermod -aG {group} {user_name}")
+  };
+  Ok(GroupMembership {
+    group,
+    user,
+    exists,
+    member,
+    active_in_session,
+    detail,
+  })
+}
+
+fn session_group_ids() -> Vec<u32> {
+  let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+  if count <= 0 {
+    return Vec::new();
+  }
+  let mut ids = vec![0 as libc::gid_t; count as usize];
+  let count = unsafe { libc::getgroups(count, ids.as_mut_ptr()) };
+  ids.truncate(count.max(0) as usize);
+  ids.push(unsafe { libc::getgid() });
+  ids
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedStatus {
+  pub active: bool,
+  pub stub_symlink: bool,
+  pub resolv_conf_target: Option<String>,
+  pub namespace_dns_ok: bool,
+  pub suggest_resolvectl: bool,
+  pub detail: String,
+}
+
+#[tauri::command]
+pub fn check_resolved(
+  tunnel_id: Option<String>,
+  store: State<'_, AppStateStore>,
+) -> Result<ResolvedStatus, CommandError> {
+  let active = Command::new("systemctl")
+    .args(["is-active", "--quiet", "systemd-resolved"])
+    .status()
+    .map(|status| status.success())
+    .unwrap_or(false)
+    || Path::new("/run/systemd/resolve/io.systemd.Resolve").exists();
+  let resolv_conf_target = std::fs::read_link("/etc/resolv.conf")
+    .ok()
+    .map(|target| target.to_string_lossy().to_string());
+  let stub_symlink = resolv_conf_target
+    .as_deref()
+    .is_some_and(|target| target.ends_with("systemd/resolve/stub-resolv.conf"));
+  let tunnel_dns = match tunnel_id {
+    Some(tunnel_id) => {
+      let tunnel = store
+        .find_tunnel(&tunnel_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::TunnelNotFound, "Tunnel not found"))?;
+      let content = std::fs::read_to_string(&tunnel.path).unwrap_or_default();
+      Some(has_dns(&content) || tunnel.dns_mode != DnsMode::Tunnel)
+    }
+    None => None,
+  };
+  let namespace_dns_ok = !stub_symlink || tunnel_dns.unwrap_or(false);
+  let suggest_resolvectl = active && !namespace_dns_ok;
+  let detail = if !active {
+    "systemd-resolved не активен, отдельный resolv.conf в namespace будет работать".to_string()
+  } else if !stub_symlink {
+    "systemd-resolved активен, но /etc/resolv.conf не указывает на stub, resolv.conf в namespace будет работать".to_string()
+  } else if namespace_dns_ok {
+    "systemd-resolved управляет DNS; для этого туннеля в namespace будет записан отдельный resolv.conf".to_string()
+  } else {
+    "systemd-resolved управляет DNS через stub 127.0.0.53, который недоступен из namespace. Добавьте DNS= в конфигурацию, включите DNS хоста или используйте resolvectl для интерфейса туннеля".to_string()
+  };
+  Ok(ResolvedStatus {
+    active,
+    stub_symlink,
+    resolv_conf_target,
+    namespace_dns_ok,
+    suggest_resolvectl,
+    detail,
+  })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireguardBackend {
+  Kernel,
+  Userspace,
+  Missing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnvironmentStatus {
+  pub backend: WireguardBackend,
+  pub userspace_implementation: Option<String>,
+  pub missing_tools: Vec<String>,
+  pub amneziawg_available: bool,
+  pub detail: String,
+}
+
+#[tauri::command]
+pub fn check_environment() -> Result<EnvironmentStatus, CommandError> {
+  let missing_tools: Vec<String> = ["ip", "iptables", "wg", "wg-quick"]
+    .into_iter()
+    .filter(|tool| find_executable(tool).is_none())
+    .map(|tool| tool.to_string())
+    .collect();
+  let kernel = Path::new("/sys/module/wireguard").exists()
+    || Command::new("modprobe")
+      .args(["--dry-run", "--quiet", "wireguard"])
+      .status()
+      .map(|status| status.success())
+      .unwrap_or(false);
+  let userspace_implementation = ["wireguard-go", "boringtun-cli", "boringtun"]
+    .into_iter()
+    .find_map(find_executable)
+    .map(|path| path.to_string_lossy().to_string());
+  let (backend, detail) = if kernel {
+    (WireguardBackend::Kernel, "Используется модуль ядра wireguard".to_string())
+  } else if let Some(implementation) = &userspace_implementation {
+    (
+      WireguardBackend::Userspace,
+      format!("Модуль ядра wireguard недоступен, будет использован {implementation}"),
+    )
+  } else {
+    (
+      WireguardBackend::Missing,
+      "Модуль ядра wireguard недоступен. Установите пакет с модулем (wireguard-dkms или новое ядро) либо wireguard-go".to_string(),
+    )
+  };
+  let amneziawg_available =
+    find_executable("awg").is_some() && find_executable("awg-quick").is_some();
+  Ok(EnvironmentStatus {
+    backend,
+    userspace_implementation,
+    missing_tools,
+    amneziawg_available,
+    detail,
+  })
+}
+
+const MIN_PLAUSIBLE_TIME: i64 = 1_735_689_600;
+const MAX_PLAUSIBLE_TIME: i64 = 4_102_444_800;
+const CLOCK_SKEW_TOLERANCE_SECS: i64 = 120;
+const CLOCK_REFERENCE_URL: &str = "https://www.cloudflare.com";
+const MONTHS: [&str; 12] = [
+  "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Serialize)]
+pub struct ClockStatus {
+  pub system_time: i64,
+  pub ntp_synchronized: Option<bool>,
+  pub reference_time: Option<i64>,
+  pub skew_secs: Option<i64>,
+  pub clock_skew_suspected: bool,
+  pub detail: String,
+}
+
+#[tauri::command(async)]
+pub fn check_clock_skew() -> Result<ClockStatus, CommandError> {
+  Ok(clock_status(true))
+}
+
+fn clock_status(use_reference: bool) -> ClockStatus {
+  let system_time = time::OffsetDateTime::now_utc().unix_timestamp();
+  let ntp_synchronized = Command::new("timedatectl")
+    .args(["show", "-p", "NTPSynchronized", "--value"])
+    .output()
+    .ok()
+    .filter(|output| output.status.success())
+    .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "yes");
+  let reference_time = if use_reference {
+    reference_clock()
+  } else {
+    None
+  };
+  let skew_secs = reference_time.map(|reference| system_time - reference);
+  let plausible = (MIN_PLAUSIBLE_TIME..MAX_PLAUSIBLE_TIME).contains(&system_time);
+  let skewed = skew_secs.is_some_and(|skew| skew.abs() > CLOCK_SKEW_TOLERANCE_SECS);
+  let detail = if !plausible {
+    "Системное время явно неверное, рукопожатие WireGuard может не проходить. Синхронизируйте часы".to_string()
+  } else if let Some(skew) = skew_secs.filter(|_| skewed) {
+    format!("Системное время расходится с эталонным на {skew} с. Синхронизируйте часы (NTP)")
+  } else if reference_time.is_none() && ntp_synchronized == Some(false) {
+    "Эталонное время недоступно, часы не синхронизированы по NTP".to_string()
+  } else {
+    "Системное время в порядке".to_string()
+  };
+  ClockStatus {
+    system_time,
+    ntp_synchronized,
+    reference_time,
+    skew_secs,
+    clock_skew_suspected: !plausible || skewed,
+    detail,
+  }
+}
+
+fn reference_clock() -> Option<i64> {
+  let output = Command::new("curl")
+    .args(["-sI", "--max-time", "5", CLOCK_REFERENCE_URL])
+    .output()
+    .ok()?;
+  let headers = String::from_utf8_lossy(&output.stdout);
+  let value = headers.lines().find_map(|line| {
+    let (name, value) = line.split_once(':')?;
+    name.trim().eq_ignore_ascii_case("date").then(|| value.trim().to_string())
+  })?;
+  parse_http_date(&value)
+}
+
+fn parse_http_date(value: &str) -> Option<i64> {
+  let (_, rest) = value.split_once(',')?;
+  let parts: Vec<&str> = rest.split_whitespace().collect();
+  let [day, month, year, clock, ..] = parts.as_slice() else {
+    return None;
+  };
+  let month = MONTHS.iter().position(|name| name == month)? as u8 + 1;
+  let mut hms = clock.split(':').map(|part| part.parse::<u8>().ok());
+  let time = time::Time::from_hms(hms.next()??, hms.next()??, hms.next()??).ok()?;
+  let date = time::Date::from_calendar_date(
+    year.parse().ok()?,
+    time::Month::try_from(month).ok()?,
+    day.parse().ok()?,
+  )
+  .ok()?;
+  Some(date.with_time(time).assume_utc().unix_timestamp())
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+  let path = std::env::var("PATH").unwrap_or_default();
+  let found = path
+    .split(':')
+    .chain(["/usr/local/sbin", "/usr/local/bin", "/usr/sbin", "/sbin", "/usr/bin", "/bin"])
+    .map(|dir| Path::new(dir).join(name))
+    .find(|candidate| candidate.is_file());
+  found
+}
+
+#[tauri::command]
+pub fn is_another_instance_running() -> Result<InstanceStatus, CommandError> {
+  Ok(instance_status())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SetupStepStatus {
+  Ok,
+  Done,
+  Skipped,
+  Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetupStep {
+  pub id: String,
+  pub status: SetupStepStatus,
+  pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetupReport {
+  pub steps: Vec<SetupStep>,
+  pub ready: bool,
+}
+
+#[tauri::command(async)]
+pub fn run_first_time_setup(store: State<'_, AppStateStore>) -> Result<SetupReport, CommandError> {
+  let mut steps = Vec::new();
+  let step = |id: &str, status: SetupStepStatus, detail: String| SetupStep {
+    id: id.to_string(),
+    status,
+    detail,
+  };
+
+  let environment = check_environment()?;
+  steps.push(if !environment.missing_tools.is_empty() {
+    step(
+      "dependencies",
+      SetupStepStatus::Failed,
+      format!("Missing tools: {}", environment.missing_tools.join(", ")),
+    )
+  } else if matches!(environment.backend, WireguardBackend::Missing) {
+    step("dependencies", SetupStepStatus::Failed, environment.detail)
+  } else {
+    step("dependencies", SetupStepStatus::Ok, environment.detail)
+  });
+
+  let agent_running = if check_polkit_agent()?.running {
+    steps.push(step(
+      "polkit_agent",
+      SetupStepStatus::Ok,
+      "polkit-agent is running".to_string(),
+    ));
+    true
+  } else {
+    match start_polkit_agent() {
+      Ok(()) => {
+        std::thread::sleep(Duration::from_secs(1));
+        steps.push(step(
+          "polkit_agent",
+          SetupStepStatus::Done,
+          "Started polkit-agent".to_string(),
+        ));
+        true
+      }
+      Err(err) => {
+        steps.push(step("polkit_agent", SetupStepStatus::Failed, err.message));
+        false
+      }
+    }
+  };
+
+  steps.push(if !agent_running {
+    step(
+      "helper",
+      SetupStepStatus::Skipped,
+      "Installing the helper requires a running polkit-agent".to_string(),
+    )
+  } else {
+    match ensure_helper_installed() {
+      Ok(false) => step(
+        "helper",
+        SetupStepStatus::Ok,
+        format!("Helper {} is installed", env!("CARGO_PKG_VERSION")),
+      ),
+      Ok(true) => step(
+        "helper",
+        SetupStepStatus::Done,
+        format!("Installed helper {}", env!("CARGO_PKG_VERSION")),
+      ),
+      Err(err) => step(
+        "helper",
+        SetupStepStatus::Failed,
+        map_helper_error(err).message,
+      ),
+    }
+  });
+
+  steps.push(if polkit_autostart_path().is_some_and(|path| path.exists()) {
+    step(
+      "polkit_autostart",
+      SetupStepStatus::Ok,
+      "polkit-agent autostart is configured".to_string(),
+    )
+  } else if !Path::new(POLKIT_KDE_AGENT).exists() {
+    step(
+      "polkit_autostart",
+      SetupStepStatus::Skipped,
+      "No polkit agent with autostart support was found; rely on the desktop session".to_string(),
+    )
+  } else {
+    match enable_polkit_autostart() {
+      Ok(()) => step(
+        "polkit_autostart",
+        SetupStepStatus::Done,
+        "Enabled polkit-agent autostart".to_string(),
+      ),
+      Err(err) => step("polkit_autostart", SetupStepStatus::Failed, err.message),
+    }
+  });
+
+  let ready = !steps
+    .iter()
+    .any(|step| matches!(step.status, SetupStepStatus::Failed));
+  let summary: Vec<String> = steps
+    .iter()
+    .map(|step| format!("{}: {:?}", step.id, step.status))
+    .collect();
+  append_log(
+    &store.log_path(),
+    &format!("First-time setup: {}", summary.join(", ")),
+  )
+  .map_err(|e| e.to_string())?;
+  Ok(SetupReport { steps, ready })
+}
+
+const POLKIT_KDE_AGENT: &str = "/usr/lib/polkit-kde-authentication-agent-1";
+
+fn polkit_autostart_path() -> Option<PathBuf> {
+  let home = std::env::var("HOME").ok()?;
+  Some(PathBuf::from(home).join(".config/autostart/polkit-kde-agent.desktop"))
+}
+
 #[tauri::command]
-pub fn enable_polkit_autostart() -> Result<(), String> {
-  let home = std::env::var("HOME").map_err(|_| "HOME not set")?;
-  let autostart_dir = PathBuf::from(home).join(".config/autostart");
-  std::fs::create_dir_all(&autostart_dir).map_err(|e| e.to_string())?;
-  let desktop_path = autostart_dir.join("polkit-kde-agent.desktop");
+pub fn enable_polkit_autostart() -> Result<(), CommandError> {
+  let desktop_path = polkit_autostart_path().ok_or("HOME not set")?;
+  if let Some(autostart_dir) = desktop_path.parent() {
+    std::fs::create_dir_all(autostart_dir).map_err(|e| e.to_string())?;
+  }
   let contents = r#"[Desktop Entry]
 Type=Application
 Name=Polkit KDE Agent
@@ -468,9 +3782,9 @@ X-KDE-autostart-after=panel
 }
 
 #[tauri::command]
-pub fn start_polkit_agent() -> Result<(), String> {
+pub fn start_polkit_agent() -> Result<(), CommandError> {
   let candidates = [
-    "/usr/lib/polkit-kde-authentication-agent-1",
+    POLKIT_KDE_AGENT,
     "/usr/lib/polkit-gnome/polkit-gnome-authentication-agent-1",
     "/usr/bin/lxqt-policykit",
   ];
@@ -482,26 +3796,198 @@ pub fn start_polkit_agent() -> Result<(), String> {
       return Ok(());
     }
   }
-  Err("polkit agent not found".to_string())
+  Err("polkit agent not found".into())
 }
 
-fn map_error(err: StorageError) -> String {
-  err.to_string()
+fn map_error(err: StorageError) -> CommandError {
+  err.into()
 }
 
-fn map_helper_error(err: HelperError) -> String {
-  let message = err.to_string();
-  if message.contains("Error accessing")
-    || message.contains("Permission denied")
-    || message.contains("status 127")
-    || message.contains("install failed")
-  {
-    return "Недостаточно прав. Убедитесь, что pkexec и polkit-agent работают, затем повторите. При первом запуске потребуется установка helper в /usr/local/lib."
-      .to_string();
+fn map_helper_error(err: HelperError) -> CommandError {
+  err.into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::temp_dir;
+
+  fn matches_process(cmdline: &[u8], comm: &str, target: &Path) -> bool {
+    let dir = std::env::temp_dir().join(format!(
+      "sillyvpn-test-proc-{}-{:?}",
+      std::process::id(),
+      std::thread::current().id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("cmdline"), cmdline).unwrap();
+    std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+    let base = target.file_name().unwrap_or_default().to_os_string();
+    let lower = base.to_string_lossy().to_ascii_lowercase();
+    let matched = process_matches_path(&dir, target, &base, &lower);
+    std::fs::remove_dir_all(&dir).unwrap();
+    matched
   }
-  if message.contains("wg-quick error") && message.contains("resolvconf") {
-    return "Ошибка DNS: wg-quick попытался изменить DNS. Уберите DNS= из конфигурации или используйте systemd-resolved."
-      .to_string();
+
+  #[test]
+  fn appimage_is_used_only_for_processes_inside_its_mount() {
+    let environ = b"HOME=/home/u\0APPDIR=/tmp/.mount_Tool1\0APPIMAGE=/home/u/Tool.AppImage\0";
+    assert_eq!(
+      mounted_appimage(environ, Path::new("/tmp/.mount_Tool1/usr/bin/tool")),
+      Some(PathBuf::from("/home/u/Tool.AppImage"))
+    );
+    assert_eq!(mounted_appimage(environ, Path::new("/usr/bin/firefox")), None);
+    assert_eq!(mounted_appimage(environ, Path::new("/tmp/.mount_Tool10/usr/bin/tool")), None);
+    let inherited = b"APPIMAGE=/home/u/Tool.AppImage\0";
+    assert_eq!(mounted_appimage(inherited, Path::new("/tmp/.mount_Tool1/usr/bin/tool")), None);
+  }
+
+  #[test]
+  fn non_utf8_paths_match_by_bytes() {
+    let target = Path::new(OsStr::from_bytes(b"/opt/gam\xffe/run"));
+    assert!(matches_process(b"/opt/gam\xffe/run\0--fullscreen\0", "run", target));
+    assert!(matches_process(b"./run\0", "run", target));
+    assert!(!matches_process(b"/opt/gam\xffe/runner\0", "runner", target));
+  }
+
+  #[test]
+  fn paths_with_spaces_match_plain_and_joined_cmdlines() {
+    let target = Path::new("/opt/My Game/game.x86_64");
+    assert!(matches_process(b"/opt/My Game/game.x86_64\0-windowed\0", "game.x86_64", target));
+    assert!(matches_process(b"/opt/My Game/game.x86_64 --type=renderer\0", "game.x86_64", target));
+    assert!(matches_process(b"/home/user/Games/game.x86_64\0", "Main Thread", target));
+    assert!(!matches_process(b"/usr/bin/steam --launch /opt/My Game/game.x86_64\0", "steam", target));
+  }
+
+  #[test]
+  fn only_argv0_is_matched_by_basename() {
+    let target = Path::new("/usr/bin/firefox");
+    assert!(!matches_process(b"/usr/bin/python3\0--config=/usr/bin/firefox\0", "python3", target));
+    assert!(!matches_process(b"/usr/bin/python3\0/home/user/tools/firefox\0", "python3", target));
+    assert!(matches_process(b"/usr/bin/python3\0/usr/bin/firefox\0", "python3", target));
+    assert!(matches_process(b"/usr/lib/firefox/firefox\0-contentproc\0", "Isolated Web Co", target));
+  }
+
+  #[test]
+  fn unicode_names_match_by_basename() {
+    let target = Path::new("/opt/игры/Запуск");
+    assert!(matches_process("/home/user/игры/Запуск\0".as_bytes(), "Запуск", target));
+    assert!(matches_process("/opt/игры/Запуск\0-v\0".as_bytes(), "bash", target));
+    assert!(!matches_process("/opt/игры/запуск2\0".as_bytes(), "запуск2", target));
+  }
+
+  fn live_peer(public_key: &str, latest_handshake: u64) -> LivePeer {
+    LivePeer {
+      public_key: public_key.to_string(),
+      endpoint: Some("198.51.100.7:51820".to_string()),
+      allowed_ips: vec!["10.9.0.0/24".to_string()],
+      latest_handshake,
+      rx: 100,
+      tx: 200,
+    }
+  }
+
+  #[test]
+  fn peers_are_matched_by_public_key_and_reported_separately() {
+    let configured = parse_peers(
+      "[Interface]\nPrivateKey = key\n\n[Peer]\nPublicKey = both\nEndpoint = a.example.com:51820\nAllowedIPs = 0.0.0.0/0\n\n[Peer]\nPublicKey = config-only\nEndpoint = b.example.com:51820\nAllowedIPs = 10.8.0.0/24\n",
+    );
+    let live = [live_peer("live-only", 0), live_peer("both", 1_000)];
+    let peers = merge_peer_status(&configured, &live, 1_100, false);
+    let keys: Vec<&str> = peers.iter().map(|peer| peer.public_key.as_str()).collect();
+    assert_eq!(keys, ["both", "config-only", "live-only"]);
+
+    let both = &peers[0];
+    assert!(both.in_config && both.in_interface && both.online);
+    assert_eq!(both.endpoint.as_deref(), Some("a.example.com:51820"));
+    assert_eq!(both.live_endpoint.as_deref(), Some("198.51.100.7:51820"));
+    assert_eq!((both.rx, both.tx, both.last_handshake), (100, 200, Some(1_000)));
+
+    let config_only = &peers[1];
+    assert!(config_only.in_config && !config_only.in_interface && !config_only.online);
+    assert_eq!(config_only.allowed_ips, ["10.8.0.0/24"]);
+    assert_eq!((config_only.rx, config_only.last_handshake), (0, None));
+
+    let live_only = &peers[2];
+    assert!(!live_only.in_config && live_only.in_interface && !live_only.online);
+    assert_eq!(live_only.endpoint, None);
+    assert_eq!(live_only.allowed_ips, ["10.9.0.0/24"]);
+  }
+
+  #[test]
+  fn stale_handshakes_are_offline_and_flag_clock_skew_only_without_one() {
+    let configured = parse_peers("[Peer]\nPublicKey = old\n\n[Peer]\nPublicKey = never\n");
+    let live = [live_peer("old", 1_000), live_peer("never", 0)];
+    let peers = merge_peer_status(&configured, &live, 1_000 + PEER_ONLINE_WINDOW_SECS + 1, true);
+    assert!(!peers[0].online && !peers[0].clock_skew_suspected);
+    assert!(!peers[1].online && peers[1].clock_skew_suspected);
+  }
+
+  #[test]
+  fn dns_leak_risks_can_be_overridden_one_by_one() {
+    let dir = temp_dir("leaks");
+    let path = dir.join("tunnel.conf");
+    std::fs::write(
+      &path,
+      "[Interface]\nAddress = 10.8.0.2/32\nDNS = 10.8.0.1, 9.9.9.9, 1.1.1.1\n\n[Peer]\nAllowedIPs = 10.8.0.0/24\n",
+    )
+    .unwrap();
+    let tunnel: Tunnel = serde_json::from_value(serde_json::json!({
+      "id": "t1",
+      "name": "test",
+      "path": path.to_string_lossy(),
+      "dns_mode": "host",
+      "kill_switch": true,
+      "endpoints": [{ "host": "192.0.2.1", "port": 51820 }],
+    }))
+    .unwrap();
+    let codes: Vec<String> = leak_risks(&tunnel)
+      .into_iter()
+      .map(|risk| risk.code)
+      .filter(|code| code != "ipv6")
+      .collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(codes, ["dns_host", "dns_server:9.9.9.9", "dns_server:1.1.1.1"]);
+  }
+
+  #[test]
+  fn network_overrides_are_trimmed_and_validated() {
+    let parsed = |subnet: &str, table: Option<u32>, fwmark: &str| {
+      parse_network_overrides(Some(subnet.to_string()), table, Some(fwmark.to_string()))
+        .map_err(|err| err.code)
+    };
+    assert_eq!(parsed(" ", None, ""), Ok((None, None, None)));
+    assert_eq!(
+      parsed(" 10.201.0.0/24 ", Some(100), "0x52"),
+      Ok((Some("10.201.0.0/24".to_string()), Some(100), Some(0x52)))
+    );
+    assert_eq!(parsed("", None, "82"), Ok((None, None, Some(82))));
+    for (subnet, table, fwmark) in [
+      ("10.201.0.1/24", None, ""),
+      ("10.201.0.0/16", None, ""),
+      ("", Some(0), ""),
+      ("", Some(254), ""),
+      ("", None, "0"),
+      ("", None, "0xzz"),
+    ] {
+      let result = parsed(subnet, table, fwmark);
+      assert_eq!(result, Err(ErrorCode::InvalidInput), "{subnet} {table:?} {fwmark}");
+    }
+  }
+
+  #[test]
+  fn endpoint_probe_is_a_wireguard_handshake_initiation() {
+    let message = handshake_initiation_probe();
+    assert_eq!(message.len(), 148);
+    assert_eq!(message[..4], [1, 0, 0, 0]);
+    assert_ne!(message[4..], handshake_initiation_probe()[4..]);
+  }
+
+  #[test]
+  fn fingerprint_digest_matches_sha256() {
+    assert_eq!(
+      sha256_hex(b"abc"),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(sha256_hex(b"").len(), 64);
   }
-  message
 }
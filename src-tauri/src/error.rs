@@ -0,0 +1,111 @@
+use crate::helper_call::HelperError;
+use crate::storage::StorageError;
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+  TunnelNotFound,
+  TunnelDisabled,
+  AppNotFound,
+  AppDisabled,
+  AppRunning,
+  AppPinned,
+  VpnActive,
+  VpnInactive,
+  TargetNotEmpty,
+  Storage,
+  HelperMissing,
+  PermissionDenied,
+  DnsConflict,
+  LeakRisk,
+  HelperFailed,
+  InvalidInput,
+  TimedOut,
+  Cancelled,
+  Failed,
+}
+
+#[derive(Debug, Error, Serialize)]
+#[error("{message}")]
+pub struct CommandError {
+  pub code: ErrorCode,
+  pub message: String,
+}
+
+impl CommandError {
+  pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+impl From<String> for CommandError {
+  fn from(message: String) -> Self {
+    Self::new(ErrorCode::Failed, message)
+  }
+}
+
+impl From<&str> for CommandError {
+  fn from(message: &str) -> Self {
+    Self::new(ErrorCode::Failed, message)
+  }
+}
+
+impl From<StorageError> for CommandError {
+  fn from(err: StorageError) -> Self {
+    let code = match err {
+      StorageError::TunnelNotFound => ErrorCode::TunnelNotFound,
+      StorageError::AppNotFound => ErrorCode::AppNotFound,
+      StorageError::VpnActive => ErrorCode::VpnActive,
+      StorageError::TargetNotEmpty => ErrorCode::TargetNotEmpty,
+      StorageError::InvalidConfig(_) => ErrorCode::InvalidInput,
+      StorageError::MissingConfigDir | StorageError::Io(_) | StorageError::Json(_) => {
+        ErrorCode::Storage
+      }
+    };
+    Self::new(code, err.to_string())
+  }
+}
+
+impl From<HelperError> for CommandError {
+  fn from(err: HelperError) -> Self {
+    let message = err.to_string();
+    if matches!(err, HelperError::MissingHelper) {
+      return Self::new(ErrorCode::HelperMissing, message);
+    }
+    if message.contains("Error accessing")
+      || message.contains("Permission denied")
+      || message.contains("status 126")
+      || message.contains("status 127")
+      || message.contains("install failed")
+    {
+      return Self::new(
+        ErrorCode::PermissionDenied,
+        "Недостаточно прав. Убедитесь, что pkexec и polkit-agent работают, затем повторите. При первом запуске потребуется установка helper в /usr/local/lib.",
+      );
+    }
+    if message.contains("handshake timed out") {
+      return Self::new(
+        ErrorCode::TimedOut,
+        "Handshake с сервером не произошёл за отведённое время, туннель выключен и настройки откачены. Проверьте endpoint, ключи и не блокирует ли сеть порт WireGuard.",
+      );
+    }
+    if message.contains("AmneziaWG parameters") {
+      return Self::new(
+        ErrorCode::InvalidInput,
+        "Конфигурация использует параметры AmneziaWG (Jc, Jmin, Jmax, S1, S2, H1–H4). Установите amneziawg-tools (awg, awg-quick) и модуль ядра amneziawg или amneziawg-go.",
+      );
+    }
+    if message.contains("wg-quick error") && message.contains("resolvconf") {
+      return Self::new(
+        ErrorCode::DnsConflict,
+        "Ошибка DNS: wg-quick попытался изменить DNS. Уберите DNS= из конфигурации или используйте systemd-resolved.",
+      );
+    }
+    Self::new(ErrorCode::HelperFailed, message)
+  }
+}
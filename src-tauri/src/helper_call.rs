@@ -1,8 +1,26 @@
+use crate::logging::append_log;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::process::Command;
+use std::io::{Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+const DEBUG_FLAG: &str = "--debug";
+const HELPER_NAME: &str = "sillyvpn-helper";
+const HELPER_PATH_ENV: &str = "SILLYVPN_HELPER_PATH";
+
+static DEBUG_LOG: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Debug, Serialize)]
+pub struct CommandPreview {
+  pub argv: Vec<String>,
+  pub env: Vec<String>,
+}
+
 #[derive(Debug, Error)]
 pub enum HelperError {
   #[error("helper binary not found")]
@@ -13,24 +31,133 @@ pub enum HelperError {
   HelperFailed(String),
 }
 
-fn helper_path() -> Result<PathBuf, HelperError> {
-  let exe = std::env::current_exe()?;
-  let dir = exe
-    .parent()
-    .ok_or(HelperError::MissingHelper)?
-    .to_path_buf();
-  let helper = dir.join("sillyvpn-helper");
-  if helper.exists() {
-    Ok(helper)
-  } else {
-    Err(HelperError::MissingHelper)
+#[derive(Debug, Serialize)]
+pub struct HelperCandidate {
+  pub source: &'static str,
+  pub path: String,
+  pub exists: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HelperResolution {
+  pub path: Option<String>,
+  pub source: Option<&'static str>,
+  pub reason: String,
+  pub candidates: Vec<HelperCandidate>,
+  pub installed_path: String,
+  pub installed_version: Option<String>,
+}
+
+fn helper_candidates() -> Vec<(&'static str, PathBuf)> {
+  let mut candidates = Vec::new();
+  if let Some(path) = std::env::var_os(HELPER_PATH_ENV).filter(|path| !path.is_empty()) {
+    candidates.push(("env", PathBuf::from(path)));
+  }
+  if let Some(dir) = std::env::current_exe()
+    .ok()
+    .and_then(|exe| exe.parent().map(Path::to_path_buf))
+  {
+    candidates.push(("exe_dir", dir.join(HELPER_NAME)));
+  }
+  if let Some(appdir) = std::env::var_os("APPDIR").filter(|dir| !dir.is_empty()) {
+    candidates.push(("appdir", PathBuf::from(appdir).join("usr/bin").join(HELPER_NAME)));
   }
+  if cfg!(debug_assertions) {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    candidates.push(("target_debug", manifest_dir.join("target/debug").join(HELPER_NAME)));
+    candidates.push(("target_release", manifest_dir.join("target/release").join(HELPER_NAME)));
+  }
+  candidates
+}
+
+pub fn resolve_helper() -> HelperResolution {
+  let candidates: Vec<HelperCandidate> = helper_candidates()
+    .into_iter()
+    .map(|(source, path)| HelperCandidate {
+      source,
+      exists: path.is_file(),
+      path: path.to_string_lossy().to_string(),
+    })
+    .collect();
+  let chosen = candidates.iter().find(|candidate| candidate.exists);
+  let env_missing = candidates
+    .first()
+    .is_some_and(|candidate| candidate.source == "env" && !candidate.exists);
+  let reason = match chosen.map(|candidate| candidate.source) {
+    Some("env") => format!("{HELPER_PATH_ENV} is set"),
+    Some(source) if env_missing => {
+      format!("{HELPER_PATH_ENV} points to a missing file, fell back to {source}")
+    }
+    Some("exe_dir") => "found next to the application binary".to_string(),
+    Some("appdir") => "found in the AppImage APPDIR".to_string(),
+    Some(source) => format!("development build, found in {source}"),
+    None => "no candidate exists; build the helper or set SILLYVPN_HELPER_PATH".to_string(),
+  };
+  HelperResolution {
+    path: chosen.map(|candidate| candidate.path.clone()),
+    source: chosen.map(|candidate| candidate.source),
+    reason,
+    installed_path: installed_helper_path().to_string_lossy().to_string(),
+    installed_version: installed_helper_version(),
+    candidates,
+  }
+}
+
+fn helper_path() -> Result<PathBuf, HelperError> {
+  helper_candidates()
+    .into_iter()
+    .map(|(_, path)| path)
+    .find(|path| path.is_file())
+    .ok_or(HelperError::MissingHelper)
 }
 
 fn installed_helper_path() -> PathBuf {
   PathBuf::from("/usr/local/lib/sillyvpn/sillyvpn-helper")
 }
 
+pub fn run_helper_unprivileged(
+  args: &[String],
+  input: Option<&[u8]>,
+) -> Result<Output, HelperError> {
+  let installed = installed_helper_path();
+  let helper = if installed.exists() {
+    installed
+  } else {
+    helper_path()?
+  };
+  let mut cmd = Command::new(helper);
+  cmd.args(args);
+  output_with_input(cmd, input)
+}
+
+pub fn installed_helper_version() -> Option<String> {
+  let output = Command::new(installed_helper_path())
+    .arg("version")
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub fn ensure_helper_installed() -> Result<bool, HelperError> {
+  let expected = env!("CARGO_PKG_VERSION");
+  if installed_helper_version().as_deref() == Some(expected) {
+    return Ok(false);
+  }
+  install_helper(&installed_helper_path())?;
+  match installed_helper_version() {
+    Some(version) if version == expected => Ok(true),
+    Some(version) => Err(HelperError::HelperFailed(format!(
+      "installed helper reports version {version}, expected {expected}"
+    ))),
+    None => Err(HelperError::HelperFailed(
+      "installed helper does not run".to_string(),
+    )),
+  }
+}
+
 fn helper_exec_path() -> Result<PathBuf, HelperError> {
   let installed = installed_helper_path();
   if installed.exists() {
@@ -40,23 +167,81 @@ fn helper_exec_path() -> Result<PathBuf, HelperError> {
   Ok(installed)
 }
 
+const INSTALL_BIN: &str = "/usr/bin/install";
+
+fn install_command(temp_path: &Path, dest: &Path) -> Command {
+  let mut cmd = configure_pkexec(Command::new("pkexec"));
+  cmd
+    .arg(INSTALL_BIN)
+    .args(["-m", "755", "-D"])
+    .arg(temp_path)
+    .arg(dest);
+  cmd
+}
+
+fn helper_command(helper: &Path, args: &[String]) -> Command {
+  let mut cmd = configure_pkexec(Command::new("pkexec"));
+  cmd.arg(helper);
+  if debug_log().is_some() {
+    cmd.arg(DEBUG_FLAG);
+  }
+  cmd.args(args);
+  cmd
+}
+
+pub fn set_debug_log(path: Option<PathBuf>) {
+  *DEBUG_LOG.lock().expect("lock") = path;
+}
+
+fn debug_log() -> Option<PathBuf> {
+  DEBUG_LOG.lock().expect("lock").clone()
+}
+
+fn log_debug_stderr(args: &[String], stderr: &[u8]) {
+  let Some(path) = debug_log() else {
+    return;
+  };
+  let operation = args.first().map(String::as_str).unwrap_or("helper");
+  for line in String::from_utf8_lossy(stderr).lines() {
+    if !line.trim().is_empty() {
+      let _ = append_log(&path, &format!("[{operation}] {line}"));
+    }
+  }
+}
+
+pub fn preview_helper(args: &[String]) -> CommandPreview {
+  preview_command(&helper_command(&installed_helper_path(), args))
+}
+
+pub fn preview_install() -> CommandPreview {
+  let temp_path = std::env::temp_dir()
+    .join("sillyvpn-helper-install")
+    .join("sillyvpn-helper");
+  preview_command(&install_command(&temp_path, &installed_helper_path()))
+}
+
+fn preview_command(cmd: &Command) -> CommandPreview {
+  let mut argv = vec![cmd.get_program().to_string_lossy().to_string()];
+  argv.extend(cmd.get_args().map(|arg| arg.to_string_lossy().to_string()));
+  let env = cmd
+    .get_envs()
+    .filter_map(|(key, value)| {
+      value.map(|value| format!("{}={}", key.to_string_lossy(), value.to_string_lossy()))
+    })
+    .collect();
+  CommandPreview { argv, env }
+}
+
 fn install_helper(dest: &Path) -> Result<(), HelperError> {
   let helper = helper_path()?;
   let temp_dir = std::env::temp_dir().join("sillyvpn-helper-install");
   fs::create_dir_all(&temp_dir)?;
   let temp_path = temp_dir.join("sillyvpn-helper");
   fs::copy(&helper, &temp_path)?;
-  let install_bin = if PathBuf::from("/usr/bin/install").exists() {
-    "/usr/bin/install"
-  } else {
+  if !PathBuf::from(INSTALL_BIN).exists() {
     return Err(HelperError::MissingHelper);
-  };
-  let output = configure_pkexec(Command::new("pkexec"))
-    .arg(install_bin)
-    .args(["-m", "755", "-D"])
-    .arg(&temp_path)
-    .arg(dest)
-    .output()?;
+  }
+  let output = install_command(&temp_path, dest).output()?;
   if output.status.success() {
     Ok(())
   } else {
@@ -105,10 +290,8 @@ pub fn run_helper_with_path(args: &[&str], path: &Path) -> Result<(), HelperErro
 
 pub fn run_helper_vec(args: Vec<String>) -> Result<(), HelperError> {
   let helper = helper_exec_path()?;
-  let output = configure_pkexec(Command::new("pkexec"))
-    .arg(helper)
-    .args(args)
-    .output()?;
+  let output = helper_command(&helper, &args).output()?;
+  log_debug_stderr(&args, &output.stderr);
   if output.status.success() {
     Ok(())
   } else {
@@ -120,6 +303,135 @@ pub fn run_helper_vec(args: Vec<String>) -> Result<(), HelperError> {
   }
 }
 
+pub fn run_helper_with_input(args: Vec<String>, input: &[u8]) -> Result<(), HelperError> {
+  let helper = helper_exec_path()?;
+  let output = output_with_input(helper_command(&helper, &args), Some(input))?;
+  log_debug_stderr(&args, &output.stderr);
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(HelperError::HelperFailed(format!(
+      "{}{}",
+      String::from_utf8_lossy(&output.stderr),
+      String::from_utf8_lossy(&output.stdout)
+    )))
+  }
+}
+
+fn output_with_input(mut cmd: Command, input: Option<&[u8]>) -> Result<Output, HelperError> {
+  let Some(input) = input else {
+    return Ok(cmd.output()?);
+  };
+  let mut child = cmd
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(input)?;
+  }
+  Ok(child.wait_with_output()?)
+}
+
+pub fn run_helper_raw(args: Vec<String>) -> Result<Output, HelperError> {
+  let helper = helper_exec_path()?;
+  let output = helper_command(&helper, &args).output()?;
+  log_debug_stderr(&args, &output.stderr);
+  Ok(output)
+}
+
+pub enum ProbeOutcome {
+  Finished(String),
+  TimedOut,
+  Abandoned,
+}
+
+const PKEXEC_GRACE: Duration = Duration::from_secs(60);
+
+pub fn run_helper_probe(
+  args: Vec<String>,
+  timeout: Duration,
+  cancel: &AtomicBool,
+) -> Result<ProbeOutcome, HelperError> {
+  let helper = helper_exec_path()?;
+  let mut child = helper_command(&helper, &args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+  let stdout = child.stdout.take();
+  let stderr = child.stderr.take();
+  let stdout = std::thread::spawn(move || read_pipe(stdout));
+  let stderr = std::thread::spawn(move || read_pipe(stderr));
+  let deadline = Instant::now() + timeout + PKEXEC_GRACE;
+  let status = loop {
+    if let Some(status) = child.try_wait()? {
+      break status;
+    }
+    let outcome = if cancel.load(Ordering::SeqCst) {
+      ProbeOutcome::Abandoned
+    } else if Instant::now() >= deadline {
+      ProbeOutcome::TimedOut
+    } else {
+      std::thread::sleep(Duration::from_millis(100));
+      continue;
+    };
+    // The helper runs as root, so it cannot be killed from here; it exits on
+    // its own --timeout and is reaped in the background.
+    std::thread::spawn(move || {
+      let _ = child.wait();
+      let _ = stdout.join();
+      let _ = stderr.join();
+    });
+    return Ok(outcome);
+  };
+  let stdout = String::from_utf8_lossy(&stdout.join().unwrap_or_default()).to_string();
+  let stderr = stderr.join().unwrap_or_default();
+  log_debug_stderr(&args, &stderr);
+  let stderr = String::from_utf8_lossy(&stderr).to_string();
+  if status.success() {
+    Ok(ProbeOutcome::Finished(stdout))
+  } else if stderr.contains("timed out after") {
+    Ok(ProbeOutcome::TimedOut)
+  } else {
+    Err(HelperError::HelperFailed(format!("{stderr}{stdout}")))
+  }
+}
+
+pub fn spawn_helper_stream(args: Vec<String>) -> Result<Child, HelperError> {
+  let helper = helper_exec_path()?;
+  Ok(
+    helper_command(&helper, &args)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()?,
+  )
+}
+
+fn read_pipe<R: Read>(pipe: Option<R>) -> Vec<u8> {
+  let mut buf = Vec::new();
+  if let Some(mut pipe) = pipe {
+    let _ = pipe.read_to_end(&mut buf);
+  }
+  buf
+}
+
+pub fn run_helper_output(args: Vec<String>) -> Result<String, HelperError> {
+  let helper = helper_exec_path()?;
+  let output = helper_command(&helper, &args).output()?;
+  log_debug_stderr(&args, &output.stderr);
+  if output.status.success() {
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+  } else {
+    Err(HelperError::HelperFailed(format!(
+      "{}{}",
+      String::from_utf8_lossy(&output.stderr),
+      String::from_utf8_lossy(&output.stdout)
+    )))
+  }
+}
+
 fn configure_pkexec(mut cmd: Command) -> Command {
   cmd.arg("--disable-internal-agent");
   for key in [
@@ -135,5 +447,8 @@ fn configure_pkexec(mut cmd: Command) -> Command {
       cmd.env(key, value);
     }
   }
+  if debug_log().is_some() {
+    cmd.env("G_MESSAGES_DEBUG", "all");
+  }
   cmd
 }
@@ -0,0 +1,118 @@
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+const LOCK_FILE: &str = "sillyvpn.lock";
+const SOCKET_FILE: &str = "sillyvpn.sock";
+
+pub struct InstanceLock {
+  _file: File,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceStatus {
+  pub lock_held: bool,
+  pub other_pids: Vec<u32>,
+}
+
+fn runtime_dir() -> std::io::Result<PathBuf> {
+  if let Some(dir) = std::env::var("XDG_RUNTIME_DIR").ok().filter(|dir| !dir.is_empty()) {
+    return Ok(PathBuf::from(dir));
+  }
+  let uid = unsafe { libc::getuid() };
+  let dir = std::env::temp_dir().join(format!("sillyvpn-{uid}"));
+  match fs::DirBuilder::new().mode(0o700).create(&dir) {
+    Ok(()) => {}
+    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+    Err(err) => return Err(err),
+  }
+  let meta = fs::symlink_metadata(&dir)?;
+  if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+    return Err(std::io::Error::new(
+      std::io::ErrorKind::PermissionDenied,
+      format!("{} is not a private directory owned by uid {uid}", dir.display()),
+    ));
+  }
+  Ok(dir)
+}
+
+pub fn acquire() -> std::io::Result<Option<InstanceLock>> {
+  let mut file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(runtime_dir()?.join(LOCK_FILE))?;
+  let locked = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0;
+  if !locked {
+    return Ok(None);
+  }
+  file.set_len(0)?;
+  write!(file, "{}", std::process::id())?;
+  Ok(Some(InstanceLock { _file: file }))
+}
+
+pub fn notify_running_instance() -> bool {
+  runtime_dir()
+    .and_then(|dir| UnixStream::connect(dir.join(SOCKET_FILE)))
+    .and_then(|mut stream| stream.write_all(b"focus"))
+    .is_ok()
+}
+
+pub fn spawn_focus_listener(app: AppHandle) {
+  let Ok(dir) = runtime_dir() else {
+    return;
+  };
+  let path = dir.join(SOCKET_FILE);
+  let _ = fs::remove_file(&path);
+  let Ok(listener) = UnixListener::bind(&path) else {
+    return;
+  };
+  std::thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      let mut message = String::new();
+      let _ = stream.read_to_string(&mut message);
+      if message.trim() != "focus" {
+        continue;
+      }
+      if let Some(window) = app.get_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+      }
+      let _ = app.emit_all("second-instance", ());
+    }
+  });
+}
+
+pub fn instance_status() -> InstanceStatus {
+  let own_pid = std::process::id();
+  let lock_pid = runtime_dir()
+    .and_then(|dir| fs::read_to_string(dir.join(LOCK_FILE)))
+    .ok()
+    .and_then(|pid| pid.trim().parse::<u32>().ok());
+  let own_exe = std::env::current_exe().ok();
+  let mut other_pids = Vec::new();
+  if let (Some(own_exe), Ok(entries)) = (own_exe, fs::read_dir("/proc")) {
+    for entry in entries.flatten() {
+      let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) else {
+        continue;
+      };
+      if pid == own_pid {
+        continue;
+      }
+      if fs::read_link(entry.path().join("exe")).is_ok_and(|exe| exe == own_exe) {
+        other_pids.push(pid);
+      }
+    }
+  }
+  InstanceStatus {
+    lock_held: lock_pid == Some(own_pid),
+    other_pids,
+  }
+}
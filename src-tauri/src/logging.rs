@@ -1,10 +1,26 @@
 use crate::storage::AppStateStore;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
 use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+const LOG_MAX_BYTES: u64 = 1024 * 1024;
+const LOG_GENERATIONS: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct LogGeneration {
+  pub index: u32,
+  pub path: String,
+  pub size: u64,
+  pub lines: usize,
+}
+
 pub fn init_logger(store: &AppStateStore) -> io::Result<()> {
   if let Some(parent) = store.log_path().parent() {
     fs::create_dir_all(parent)?;
@@ -18,6 +34,7 @@ pub fn append_log(path: &Path, message: &str) -> io::Result<()> {
     .format(&Rfc3339)
     .unwrap_or_else(|_| "unknown-time".to_string());
 
+  rotate_log_file(path, LOG_MAX_BYTES, LOG_GENERATIONS, false)?;
   let mut file = OpenOptions::new()
     .create(true)
     .append(true)
@@ -25,3 +42,204 @@ pub fn append_log(path: &Path, message: &str) -> io::Result<()> {
   writeln!(file, "{} | {}", timestamp, message)?;
   Ok(())
 }
+
+pub fn log_generation(path: &Path, generation: usize, compressed: bool) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(format!(".{generation}"));
+  if compressed {
+    name.push(".gz");
+  }
+  PathBuf::from(name)
+}
+
+pub fn log_generations(path: &Path, generations: usize) -> Vec<(usize, PathBuf)> {
+  (1..=generations)
+    .flat_map(|generation| {
+      [
+        (generation, log_generation(path, generation, false)),
+        (generation, log_generation(path, generation, true)),
+      ]
+    })
+    .filter(|(_, path)| path.exists())
+    .collect()
+}
+
+pub fn rotate_log_file(
+  path: &Path,
+  limit: u64,
+  generations: usize,
+  compress: bool,
+) -> io::Result<bool> {
+  let len = match fs::metadata(path) {
+    Ok(meta) => meta.len(),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(err) => return Err(err),
+  };
+  if len <= limit {
+    return Ok(false);
+  }
+  for compressed in [false, true] {
+    let _ = fs::remove_file(log_generation(path, generations, compressed));
+  }
+  for generation in (1..generations).rev() {
+    for compressed in [false, true] {
+      let from = log_generation(path, generation, compressed);
+      if from.exists() {
+        fs::rename(&from, log_generation(path, generation + 1, compressed))?;
+      }
+    }
+  }
+  let mut log = OpenOptions::new().read(true).write(true).open(path)?;
+  let mut content = Vec::new();
+  log.read_to_end(&mut content)?;
+  let mut rotated = OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(0o600)
+    .open(log_generation(path, 1, compress))?;
+  if compress {
+    let mut encoder = GzEncoder::new(rotated, Compression::default());
+    encoder.write_all(&content)?;
+    encoder.finish()?;
+  } else {
+    rotated.write_all(&content)?;
+  }
+  log.set_len(0)?;
+  Ok(true)
+}
+
+pub fn read_log_generation(path: &Path) -> io::Result<String> {
+  let file = fs::File::open(path)?;
+  let mut content = Vec::new();
+  if path.extension().is_some_and(|ext| ext == "gz") {
+    GzDecoder::new(file).read_to_end(&mut content)?;
+  } else {
+    io::BufReader::new(file).read_to_end(&mut content)?;
+  }
+  Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+pub fn list_log_generations(path: &Path) -> io::Result<Vec<LogGeneration>> {
+  let mut files = log_generations(path, LOG_GENERATIONS);
+  files.reverse();
+  files.push((0, path.to_path_buf()));
+  let mut generations = Vec::new();
+  for (index, file) in files {
+    if !file.exists() {
+      continue;
+    }
+    let content = read_log_generation(&file)?;
+    generations.push(LogGeneration {
+      index: index as u32,
+      path: file.to_string_lossy().to_string(),
+      size: fs::metadata(&file)?.len(),
+      lines: content.lines().count(),
+    });
+  }
+  Ok(generations)
+}
+
+pub fn remove_log_generation(path: &Path, index: u32) -> io::Result<bool> {
+  if index == 0 {
+    fs::write(path, "")?;
+    return Ok(true);
+  }
+  let files: Vec<PathBuf> = log_generations(path, LOG_GENERATIONS)
+    .into_iter()
+    .filter(|(generation, _)| *generation == index as usize)
+    .map(|(_, file)| file)
+    .collect();
+  for file in &files {
+    fs::remove_file(file)?;
+  }
+  Ok(!files.is_empty())
+}
+
+pub fn export_logs(path: &Path, dest: &Path, header: &str) -> io::Result<()> {
+  let mut out = fs::File::create(dest)?;
+  writeln!(out, "{}", header)?;
+  for (_, generation) in log_generations(path, LOG_GENERATIONS).into_iter().rev() {
+    out.write_all(read_log_generation(&generation)?.as_bytes())?;
+  }
+  if path.exists() {
+    out.write_all(&fs::read(path)?)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::temp_dir;
+  use std::os::unix::fs::PermissionsExt;
+
+  #[test]
+  fn full_log_rotates_into_numbered_generations() {
+    let dir = temp_dir("rotation");
+    let log = dir.join("app.log");
+    for run in 0..LOG_GENERATIONS + 2 {
+      fs::write(&log, format!("run {run}\n")).unwrap();
+      rotate_log_file(&log, 1, LOG_GENERATIONS, false).unwrap();
+    }
+    fs::write(&log, "current\n").unwrap();
+    rotate_log_file(&log, 1024, LOG_GENERATIONS, false).unwrap();
+
+    let generations = list_log_generations(&log).unwrap();
+    let indices: Vec<u32> = generations.iter().map(|generation| generation.index).collect();
+    assert_eq!(indices, [5, 4, 3, 2, 1, 0]);
+    assert_eq!(fs::read_to_string(log_generation(&log, 1, false)).unwrap(), "run 6\n");
+    assert_eq!(fs::read_to_string(log_generation(&log, 5, false)).unwrap(), "run 2\n");
+    assert!(generations.iter().all(|generation| generation.lines == 1));
+
+    assert!(remove_log_generation(&log, 3).unwrap());
+    assert!(!remove_log_generation(&log, 3).unwrap());
+    assert!(!log_generation(&log, 3, false).exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn oversized_log_rotates_into_gzip_generations() {
+    let dir = temp_dir("app-log");
+    let log = dir.join("app.log");
+    fs::write(&log, "first run\n".repeat(20)).unwrap();
+    assert!(!rotate_log_file(&log, 1024, 3, true).unwrap());
+    assert!(rotate_log_file(&log, 100, 3, true).unwrap());
+    assert_eq!(fs::metadata(&log).unwrap().len(), 0);
+
+    fs::write(&log, "second run\n".repeat(20)).unwrap();
+    assert!(rotate_log_file(&log, 100, 3, false).unwrap());
+    let generations = log_generations(&log, 3);
+    assert_eq!(
+      generations,
+      [(1, log_generation(&log, 1, false)), (2, log_generation(&log, 2, true))]
+    );
+    assert!(read_log_generation(&generations[0].1).unwrap().starts_with("second run\n"));
+    assert_eq!(read_log_generation(&generations[1].1).unwrap(), "first run\n".repeat(20));
+    assert_eq!(fs::metadata(&generations[1].1).unwrap().permissions().mode() & 0o777, 0o600);
+
+    for _ in 0..3 {
+      fs::write(&log, "more\n".repeat(40)).unwrap();
+      rotate_log_file(&log, 100, 3, true).unwrap();
+    }
+    assert_eq!(log_generations(&log, 3).len(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn export_puts_generations_in_chronological_order() {
+    let dir = temp_dir("export");
+    let log = dir.join("app.log");
+    for run in ["oldest", "older", "newest"] {
+      fs::write(&log, format!("{run}\n")).unwrap();
+      rotate_log_file(&log, 1, LOG_GENERATIONS, false).unwrap();
+    }
+    fs::write(&log, "current\n").unwrap();
+    let dest = dir.join("export.log");
+    export_logs(&log, &dest, "sillyvpn 0.1.1").unwrap();
+    assert_eq!(
+      fs::read_to_string(&dest).unwrap(),
+      "sillyvpn 0.1.1\noldest\nolder\nnewest\ncurrent\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
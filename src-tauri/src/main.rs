@@ -1,36 +1,151 @@
+mod bundle;
 mod commands;
+mod error;
 mod helper_call;
+mod instance;
 mod logging;
 mod models;
+mod monitor;
 mod storage;
+#[cfg(test)]
+mod test_util;
+mod wg_config;
 
 use commands::*;
 use logging::init_logger;
 use storage::AppStateStore;
 
 fn main() {
+  let _instance_lock = match instance::acquire() {
+    Ok(Some(lock)) => Some(lock),
+    Ok(None) => {
+      if !instance::notify_running_instance() {
+        eprintln!("sillyvpn is already running");
+      }
+      return;
+    }
+    Err(err) => {
+      eprintln!("instance lock unavailable: {err}");
+      None
+    }
+  };
   let state_store = AppStateStore::new();
   init_logger(&state_store).expect("logger init");
+  warn_config_permissions(&state_store);
+  apply_privileged_debug(&state_store);
+  reconcile_vpn_state(&state_store);
 
   tauri::Builder::default()
     .manage(state_store)
+    .setup(|app| {
+      monitor::spawn_network_monitor(app.handle());
+      monitor::spawn_suspend_monitor(app.handle());
+      monitor::spawn_state_autosave(app.handle());
+      monitor::spawn_app_log_rotation(app.handle());
+      instance::spawn_focus_listener(app.handle());
+      Ok(())
+    })
     .invoke_handler(tauri::generate_handler![
       get_state,
       get_logs,
+      get_app_log,
       import_conf,
+      replace_tunnel_config,
+      tunnel_fingerprint,
+      export_bundle,
+      import_bundle,
+      verify_state_consistency,
+      check_config_permissions,
+      get_storage_usage,
+      fix_config_permissions,
       add_app,
+      list_candidate_processes,
+      add_app_from_pid,
+      inspect_binary,
       remove_app,
+      remove_tunnel,
+      update_app,
+      update_tunnel,
+      rename_tunnel,
+      batch_update_tunnels,
+      set_tunnel_bypass_routes,
+      set_tunnel_dns_mode,
+      set_tunnel_auto_mtu,
+      set_tunnel_connect_timeout,
+      set_tunnel_network,
+      set_tunnel_manage_routes_externally,
+      set_tunnel_masquerade,
+      set_tunnel_resolv_options,
       enable_vpn,
+      check_leak_risks,
       disable_vpn,
+      reconnect_vpn,
       run_app_via_vpn,
+      run_app_and_wait,
+      launch_autostart_apps,
+      set_launch_profile_member,
+      launch_tunnel_profile,
       set_last_tunnel,
       set_last_app,
+      set_network_change_action,
+      set_suspend_action,
+      set_extra_env_keys,
+      set_app_in_terminal,
+      set_app_capture_output,
+      set_app_limits,
+      get_app_live_output,
+      set_terminal,
+      set_vpn_hooks,
+      set_config_via_stdin,
+      set_randomize_subnet,
+      set_kill_apps_on_disable,
+      set_bandwidth_limit,
+      set_app_output_limit,
+      set_app_log_rotation,
+      set_privileged_debug,
+      set_data_dir,
+      preview_privileged_command,
+      get_helper_resolution,
       check_polkit_agent,
+      check_privilege_group,
+      check_resolved,
+      check_environment,
+      check_clock_skew,
+      is_another_instance_running,
       enable_polkit_autostart,
+      run_first_time_setup,
       kill_all_apps,
+      kill_app,
+      list_all_namespace_processes,
+      kill_all_everywhere,
+      set_app_kill_signal,
       start_polkit_agent,
       get_running_apps,
-      clear_logs
+      get_summary,
+      refresh_running_state,
+      clear_app_tracking,
+      get_tunnel_connections,
+      get_peer_status,
+      get_tunnel_coverage,
+      start_latency_monitor,
+      stop_latency_monitor,
+      get_last_operation,
+      check_conflicts,
+      check_nat,
+      check_endpoint_reachable,
+      get_wg_kernel_messages,
+      get_per_app_traffic,
+      tunnel_status,
+      get_transfer_stats,
+      dry_run_network_config,
+      run_diagnostic,
+      cancel_probe,
+      set_probe_settings,
+      wipe_runtime_secrets,
+      clear_logs,
+      list_log_generations,
+      remove_log_generation,
+      export_logs
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -1,10 +1,62 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tunnel {
   pub id: String,
   pub name: String,
   pub path: String,
+  #[serde(default)]
+  pub note: Option<String>,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  #[serde(default)]
+  pub bypass_routes: Vec<String>,
+  #[serde(default)]
+  pub dns_mode: DnsMode,
+  #[serde(default)]
+  pub auto_mtu: bool,
+  #[serde(default)]
+  pub endpoints: Vec<Endpoint>,
+  #[serde(default)]
+  pub manage_routes_externally: bool,
+  #[serde(default = "default_enabled")]
+  pub masquerade: bool,
+  #[serde(default)]
+  pub provider: Option<String>,
+  #[serde(default)]
+  pub region: Option<String>,
+  #[serde(default)]
+  pub launch_profile: Vec<String>,
+  #[serde(default)]
+  pub resolv_options: Option<String>,
+  #[serde(default)]
+  pub connect_count: u64,
+  #[serde(default)]
+  pub connect_timeout_secs: Option<u64>,
+  #[serde(default)]
+  pub veth_subnet: Option<String>,
+  #[serde(default)]
+  pub route_table: Option<u32>,
+  #[serde(default)]
+  pub fwmark: Option<u32>,
+  #[serde(default)]
+  pub kill_switch: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+  pub host: String,
+  pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsMode {
+  #[default]
+  Tunnel,
+  Host,
+  Resolved,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,13 +64,412 @@ pub struct AppItem {
   pub id: String,
   pub label: String,
   pub path: String,
+  #[serde(default)]
+  pub kind: BinaryKind,
+  #[serde(default)]
+  pub interpreter: Option<String>,
+  #[serde(default)]
+  pub note: Option<String>,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  #[serde(default)]
+  pub autostart: bool,
+  #[serde(default)]
+  pub preferred_tunnel_id: Option<String>,
+  #[serde(default)]
+  pub in_terminal: bool,
+  #[serde(default)]
+  pub capture_output: bool,
+  #[serde(default)]
+  pub limits: AppLimits,
+  #[serde(default)]
+  pub kill_signal: KillSignal,
+  #[serde(default)]
+  pub args: Vec<String>,
+  #[serde(default)]
+  pub cwd: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KillSignal {
+  #[default]
+  TermThenKill,
+  KillOnly,
+  TermOnly,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AppLimits {
+  #[serde(default)]
+  pub no_new_privs: bool,
+  #[serde(default)]
+  pub max_memory_mb: Option<u64>,
+  #[serde(default)]
+  pub max_processes: Option<u64>,
+  #[serde(default)]
+  pub max_open_files: Option<u64>,
+  #[serde(default)]
+  pub max_cpu_secs: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+fn default_probe_timeout() -> u64 {
+  10
+}
+
+fn default_app_output_limit() -> u64 {
+  256
+}
+
+fn default_app_log_limit() -> u64 {
+  1024
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryKind {
+  Elf,
+  Script,
+  #[default]
+  Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppStateFile {
   pub tunnels: Vec<Tunnel>,
   pub apps: Vec<AppItem>,
   pub last_tunnel_id: Option<String>,
   pub last_app_id: Option<String>,
   pub vpn_enabled: bool,
+  #[serde(default)]
+  pub active_tunnel_id: Option<String>,
+  #[serde(default)]
+  pub on_network_change: NetworkChangeAction,
+  #[serde(default)]
+  pub on_suspend: SuspendAction,
+  #[serde(default = "default_probe_timeout")]
+  pub probe_timeout_secs: u64,
+  #[serde(default)]
+  pub probe_retries: u32,
+  #[serde(default)]
+  pub extra_env_keys: Vec<String>,
+  #[serde(default)]
+  pub config_via_stdin: bool,
+  #[serde(default)]
+  pub terminal: Option<String>,
+  #[serde(default)]
+  pub on_connect_cmd: Option<String>,
+  #[serde(default)]
+  pub on_disconnect_cmd: Option<String>,
+  #[serde(default)]
+  pub privileged_debug: bool,
+  #[serde(default)]
+  pub randomize_subnet: bool,
+  #[serde(default = "default_app_output_limit")]
+  pub app_output_limit_kib: u64,
+  #[serde(default)]
+  pub bandwidth_limit_mbps: Option<u32>,
+  #[serde(default = "default_enabled")]
+  pub kill_apps_on_disable: bool,
+  #[serde(default = "default_app_log_limit")]
+  pub app_log_limit_kib: u64,
+  #[serde(default = "default_enabled")]
+  pub compress_app_logs: bool,
+}
+
+impl Default for AppStateFile {
+  fn default() -> Self {
+    Self {
+      tunnels: Vec::new(),
+      apps: Vec::new(),
+      last_tunnel_id: None,
+      last_app_id: None,
+      vpn_enabled: false,
+      active_tunnel_id: None,
+      on_network_change: NetworkChangeAction::default(),
+      on_suspend: SuspendAction::default(),
+      probe_timeout_secs: default_probe_timeout(),
+      probe_retries: 0,
+      extra_env_keys: Vec::new(),
+      config_via_stdin: false,
+      terminal: None,
+      on_connect_cmd: None,
+      on_disconnect_cmd: None,
+      privileged_debug: false,
+      randomize_subnet: false,
+      app_output_limit_kib: default_app_output_limit(),
+      bandwidth_limit_mbps: None,
+      kill_apps_on_disable: true,
+      app_log_limit_kib: default_app_log_limit(),
+      compress_app_logs: true,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TunnelOp {
+  Rename { tunnel_id: String, name: String },
+  Move { tunnel_id: String, index: usize },
+  SetEnabled { tunnel_id: String, enabled: bool },
+}
+
+impl TunnelOp {
+  pub fn tunnel_id(&self) -> &str {
+    match self {
+      TunnelOp::Rename { tunnel_id, .. }
+      | TunnelOp::Move { tunnel_id, .. }
+      | TunnelOp::SetEnabled { tunnel_id, .. } => tunnel_id,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateBundle {
+  pub format: String,
+  pub version: u32,
+  pub state: AppStateFile,
+  pub configs: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleImport {
+  pub tunnels: usize,
+  pub apps: usize,
+  pub settings_restored: bool,
+  pub skipped_on_connect_cmd: Option<String>,
+  pub skipped_on_disconnect_cmd: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SuspendAction {
+  #[default]
+  Off,
+  Reconnect,
+  Pause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkChangeAction {
+  #[default]
+  Off,
+  Notify,
+  Disable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigUsage {
+  pub tunnel_id: String,
+  pub name: String,
+  pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageUsage {
+  pub data_dir: String,
+  pub total_bytes: u64,
+  pub state_bytes: u64,
+  pub logs_bytes: u64,
+  pub configs: Vec<ConfigUsage>,
+  pub orphaned_config_bytes: u64,
+  pub other_bytes: u64,
+  pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigPermissionIssue {
+  pub path: String,
+  pub mode: String,
+  pub owner_uid: u32,
+  pub expected_uid: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelConnection {
+  pub proto: String,
+  pub state: String,
+  pub local: String,
+  pub peer: String,
+  pub process: Option<String>,
+  pub pids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingConflict {
+  pub source: String,
+  pub entry: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TunnelStatus {
+  pub interface_up: bool,
+  pub last_handshake_secs: Option<u64>,
+  pub rx_bytes: u64,
+  pub tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerTransfer {
+  pub public_key: String,
+  pub rx_bytes: u64,
+  pub tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransferStats {
+  pub peers: Vec<PeerTransfer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkPlan {
+  #[serde(default)]
+  pub subnet: String,
+  #[serde(default)]
+  pub table: u32,
+  #[serde(default)]
+  pub fwmark: String,
+  pub steps: Vec<String>,
+  pub conflicts: Vec<RoutingConflict>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LivePeer {
+  pub public_key: String,
+  pub endpoint: Option<String>,
+  pub allowed_ips: Vec<String>,
+  pub latest_handshake: u64,
+  pub rx: u64,
+  pub tx: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NamespaceProcess {
+  pub pid: u32,
+  pub comm: String,
+  pub exe: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NamespaceProcesses {
+  pub namespace: String,
+  pub processes: Vec<NamespaceProcess>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DisableReport {
+  pub killed: u32,
+  pub remaining: u32,
+  pub warning: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PurgeReport {
+  pub killed: u32,
+  pub namespaces_removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointReachability {
+  pub host: String,
+  pub port: u16,
+  pub address: Option<String>,
+  pub status: String,
+  pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelMessages {
+  pub source: String,
+  #[serde(default)]
+  pub ifname: String,
+  pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppTraffic {
+  pub app_id: String,
+  #[serde(default)]
+  pub label: String,
+  pub rx_bytes: u64,
+  pub tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NatCheck {
+  pub status: String,
+  pub masqueraded_packets: u64,
+  pub reply: bool,
+  #[serde(default)]
+  pub firewall: Option<String>,
+  #[serde(default)]
+  pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+  pub public_key: String,
+  pub endpoint: Option<String>,
+  pub live_endpoint: Option<String>,
+  pub allowed_ips: Vec<String>,
+  pub last_handshake: Option<u64>,
+  pub rx: u64,
+  pub tx: u64,
+  pub online: bool,
+  pub clock_skew_suspected: bool,
+  pub in_config: bool,
+  pub in_interface: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputLine {
+  pub seq: u64,
+  pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppLiveOutput {
+  pub lines: Vec<OutputLine>,
+  pub next_seq: u64,
+  pub running: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelCoverage {
+  pub full_tunnel_v4: bool,
+  pub full_tunnel_v6: bool,
+  pub routed_cidrs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticOutput {
+  pub exit_code: i32,
+  pub stdout: String,
+  pub stderr: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySample {
+  pub tunnel_id: String,
+  pub seq: u64,
+  pub rtt_ms: Option<f64>,
+  pub average_ms: Option<f64>,
+  pub jitter_ms: Option<f64>,
+  pub loss_percent: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastOperation {
+  pub operation: String,
+  pub ok: bool,
+  pub error: Option<String>,
+  pub failed_command: Option<String>,
+  pub exit_code: Option<i32>,
+  pub stderr_tail: Option<String>,
+  pub timestamp: u64,
 }
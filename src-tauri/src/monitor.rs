@@ -0,0 +1,396 @@
+use crate::commands::{reconnect_active, shutdown_vpn, start_vpn};
+use crate::logging::append_log;
+use crate::models::{LatencySample, NetworkChangeAction, SuspendAction};
+use crate::storage::AppStateStore;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+const IGNORED_PREFIXES: [&str; 2] = ["svpn", "lo"];
+const LATENCY_WINDOW: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkChange {
+  pub previous: Option<String>,
+  pub current: Option<String>,
+  pub action: NetworkChangeAction,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PowerChange {
+  pub sleeping: bool,
+  pub action: SuspendAction,
+}
+
+struct RawLatency {
+  seq: u64,
+  rtt_ms: Option<f64>,
+}
+
+pub fn spawn_ping(ping: &Path, host: &str, interval: u64) -> std::io::Result<Child> {
+  let mut cmd = Command::new(ping);
+  cmd
+    .args(["-n", "-O", "-i"])
+    .arg(interval.to_string())
+    .arg(host)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+  unsafe {
+    cmd.pre_exec(|| {
+      libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+      Ok(())
+    });
+  }
+  cmd.spawn()
+}
+
+pub fn spawn_latency_monitor(
+  app: AppHandle,
+  tunnel_id: String,
+  mut child: Child,
+  stop: Arc<AtomicBool>,
+) {
+  std::thread::spawn(move || {
+    let mut window: VecDeque<RawLatency> = VecDeque::with_capacity(LATENCY_WINDOW);
+    if let Some(stdout) = child.stdout.take() {
+      for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if stop.load(Ordering::SeqCst) {
+          break;
+        }
+        let Some(raw) = parse_ping_line(&line) else {
+          continue;
+        };
+        match window.iter_mut().find(|sample| sample.seq == raw.seq) {
+          Some(sample) => sample.rtt_ms = raw.rtt_ms,
+          None => {
+            if window.len() == LATENCY_WINDOW {
+              window.pop_front();
+            }
+            window.push_back(RawLatency {
+              seq: raw.seq,
+              rtt_ms: raw.rtt_ms,
+            });
+          }
+        }
+        let _ = app.emit_all(
+          "latency-sample",
+          latency_sample(&tunnel_id, raw.seq, raw.rtt_ms, &window),
+        );
+      }
+    }
+    if stop.load(Ordering::SeqCst) {
+      let _ = child.kill();
+    }
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+      let _ = pipe.read_to_string(&mut stderr);
+    }
+    let _ = child.wait();
+    let store = app.state::<AppStateStore>();
+    store.finish_latency_monitor(&stop);
+    if !stop.load(Ordering::SeqCst) {
+      let _ = append_log(
+        &store.log_path(),
+        &format!("Latency monitor stopped: {}", stderr.trim()),
+      );
+      let _ = app.emit_all("latency-stopped", tunnel_id);
+    }
+  });
+}
+
+fn parse_ping_line(line: &str) -> Option<RawLatency> {
+  let (_, rest) = line.split_once("icmp_seq=")?;
+  let seq = rest
+    .split(|c: char| !c.is_ascii_digit())
+    .next()?
+    .parse()
+    .ok()?;
+  if line.starts_with("no answer yet") {
+    return Some(RawLatency { seq, rtt_ms: None });
+  }
+  let (_, time) = rest.split_once("time=")?;
+  let rtt_ms = time.split_whitespace().next()?.parse().ok()?;
+  Some(RawLatency { seq, rtt_ms: Some(rtt_ms) })
+}
+
+fn latency_sample(
+  tunnel_id: &str,
+  seq: u64,
+  rtt_ms: Option<f64>,
+  window: &VecDeque<RawLatency>,
+) -> LatencySample {
+  let answered: Vec<f64> = window.iter().filter_map(|sample| sample.rtt_ms).collect();
+  let average_ms =
+    (!answered.is_empty()).then(|| answered.iter().sum::<f64>() / answered.len() as f64);
+  let deltas: Vec<f64> = answered
+    .windows(2)
+    .map(|pair| (pair[1] - pair[0]).abs())
+    .collect();
+  let jitter_ms = (!deltas.is_empty()).then(|| deltas.iter().sum::<f64>() / deltas.len() as f64);
+  let lost = window.len() - answered.len();
+  LatencySample {
+    tunnel_id: tunnel_id.to_string(),
+    seq,
+    rtt_ms,
+    average_ms,
+    jitter_ms,
+    loss_percent: lost as f64 * 100.0 / window.len().max(1) as f64,
+  }
+}
+
+pub fn spawn_state_autosave(app: AppHandle) {
+  std::thread::spawn(move || loop {
+    std::thread::sleep(AUTOSAVE_INTERVAL);
+    let store = app.state::<AppStateStore>();
+    match store.autosave_if_diverged() {
+      Ok(differences) => {
+        if !differences.is_empty() {
+          let _ = append_log(
+            &store.log_path(),
+            &format!("Autosaved state after drift: {}", differences.join("; ")),
+          );
+        }
+      }
+      Err(err) => {
+        let _ = append_log(&store.log_path(), &format!("State autosave failed: {err}"));
+      }
+    }
+  });
+}
+
+pub fn spawn_app_log_rotation(app: AppHandle) {
+  std::thread::spawn(move || loop {
+    std::thread::sleep(POLL_INTERVAL);
+    let store = app.state::<AppStateStore>();
+    for item in store.state_snapshot().apps {
+      match store.rotate_app_log(&item.id) {
+        Ok(true) => {
+          let _ = append_log(&store.log_path(), &format!("Rotated log of {}", item.label));
+        }
+        Ok(false) => {}
+        Err(err) => {
+          let _ = append_log(
+            &store.log_path(),
+            &format!("Failed to rotate log of {}: {err}", item.label),
+          );
+        }
+      }
+    }
+  });
+}
+
+pub fn spawn_suspend_monitor(app: AppHandle) {
+  std::thread::spawn(move || {
+    let mut cmd = Command::new("gdbus");
+    cmd
+      .args([
+        "monitor",
+        "--system",
+        "--dest",
+        "org.freedesktop.login1",
+        "--object-path",
+        "/org/freedesktop/login1",
+      ])
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null());
+    unsafe {
+      cmd.pre_exec(|| {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+        Ok(())
+      });
+    }
+    let Ok(mut child) = cmd.spawn() else {
+      return;
+    };
+    let Some(stdout) = child.stdout.take() else {
+      return;
+    };
+    let mut inhibitor = SleepInhibitor::acquire();
+    let mut paused_tunnel: Option<String> = None;
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+      if !line.contains("PrepareForSleep") {
+        continue;
+      }
+      let sleeping = line.contains("(true,)");
+      if !sleeping {
+        inhibitor = SleepInhibitor::acquire();
+      }
+      let store = app.state::<AppStateStore>();
+      let state = store.state_snapshot();
+      let result = match (state.on_suspend, sleeping) {
+        (SuspendAction::Pause, true) if state.vpn_enabled => {
+          let _ = append_log(&store.log_path(), "Pausing VPN for suspend");
+          let result = shutdown_vpn(&store);
+          if result.is_ok() {
+            paused_tunnel = state.active_tunnel_id.clone();
+          }
+          result
+        }
+        (SuspendAction::Pause, false) => match paused_tunnel
+          .take()
+          .and_then(|tunnel_id| store.find_tunnel(&tunnel_id))
+        {
+          Some(tunnel) => {
+            let _ = append_log(&store.log_path(), "Resuming VPN after suspend");
+            start_vpn(&store, &tunnel, &app)
+          }
+          None => Ok(()),
+        },
+        (SuspendAction::Reconnect, false) if state.vpn_enabled => {
+          let _ = append_log(&store.log_path(), "Reconnecting VPN after suspend");
+          reconnect_active(&store, &app)
+        }
+        _ => {
+          if sleeping {
+            inhibitor.release();
+          }
+          continue;
+        }
+      };
+      if sleeping {
+        inhibitor.release();
+      }
+      if let Err(err) = result {
+        let _ = append_log(
+          &store.log_path(),
+          &format!("Suspend handling failed: {err}"),
+        );
+      }
+      let _ = app.emit_all(
+        "power-changed",
+        PowerChange {
+          sleeping,
+          action: state.on_suspend,
+        },
+      );
+    }
+    let _ = child.wait();
+  });
+}
+
+/// Holds a logind `delay` sleep lock through `systemd-inhibit`, so the system
+/// waits for the tunnel teardown before it actually suspends.
+struct SleepInhibitor(Option<Child>);
+
+impl SleepInhibitor {
+  fn acquire() -> Self {
+    let mut cmd = Command::new("systemd-inhibit");
+    cmd
+      .args([
+        "--what=sleep",
+        "--mode=delay",
+        "--who=SillyVPN",
+        "--why=Pausing VPN before suspend",
+        "sleep",
+        "infinity",
+      ])
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null());
+    unsafe {
+      cmd.pre_exec(|| {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+        Ok(())
+      });
+    }
+    Self(cmd.spawn().ok())
+  }
+
+  fn release(&mut self) {
+    if let Some(mut child) = self.0.take() {
+      let _ = child.kill();
+      let _ = child.wait();
+    }
+  }
+}
+
+impl Drop for SleepInhibitor {
+  fn drop(&mut self) {
+    self.release();
+  }
+}
+
+pub fn spawn_network_monitor(app: AppHandle) {
+  std::thread::spawn(move || {
+    let mut last_iface = default_route_iface();
+    loop {
+      std::thread::sleep(POLL_INTERVAL);
+      let current = default_route_iface();
+      if current == last_iface {
+        continue;
+      }
+      let previous = std::mem::replace(&mut last_iface, current.clone());
+
+      let store = app.state::<AppStateStore>();
+      let state = store.state_snapshot();
+      if !state.vpn_enabled || state.on_network_change == NetworkChangeAction::Off {
+        continue;
+      }
+      let _ = append_log(
+        &store.log_path(),
+        &format!(
+          "Default route changed: {} -> {}",
+          previous.as_deref().unwrap_or("none"),
+          current.as_deref().unwrap_or("none")
+        ),
+      );
+      if state.on_network_change == NetworkChangeAction::Disable {
+        if let Err(err) = shutdown_vpn(&store) {
+          let _ = append_log(
+            &store.log_path(),
+            &format!("Failed to disable VPN after network change: {err}"),
+          );
+        }
+      }
+      let _ = app.emit_all(
+        "network-changed",
+        NetworkChange {
+          previous,
+          current,
+          action: state.on_network_change,
+        },
+      );
+    }
+  });
+}
+
+fn default_route_iface() -> Option<String> {
+  let content = std::fs::read_to_string("/proc/net/route").ok()?;
+  let mut best: Option<(u32, String)> = None;
+  for line in content.lines().skip(1) {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+      continue;
+    }
+    let iface = fields[0];
+    if IGNORED_PREFIXES.iter().any(|prefix| iface.starts_with(prefix)) || is_wireguard(iface) {
+      continue;
+    }
+    let metric: u32 = fields[6].parse().unwrap_or(u32::MAX);
+    let better = match &best {
+      Some((best_metric, _)) => metric < *best_metric,
+      None => true,
+    };
+    if better {
+      best = Some((metric, iface.to_string()));
+    }
+  }
+  best.map(|(_, iface)| iface)
+}
+
+fn is_wireguard(iface: &str) -> bool {
+  std::fs::read_to_string(format!("/sys/class/net/{iface}/uevent"))
+    .map(|uevent| uevent.lines().any(|line| line == "DEVTYPE=wireguard"))
+    .unwrap_or(false)
+}
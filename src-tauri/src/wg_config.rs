@@ -0,0 +1,753 @@
+use crate::models::Endpoint;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+const INCLUDE_DIRECTIVE: &str = "#sillyvpn-include";
+const SECRET_KEYS: [&str; 2] = ["privatekey", "presharedkey"];
+const LIST_KEYS: [&str; 3] = ["address", "allowedips", "dns"];
+const AMNEZIA_KEYS: [&str; 9] = ["jc", "jmin", "jmax", "s1", "s2", "h1", "h2", "h3", "h4"];
+
+pub fn validate_config(content: &str) -> Result<(), String> {
+  let mut section = String::new();
+  let mut has_interface = false;
+  let mut private_key = None;
+  let mut peers: Vec<(Option<String>, bool)> = Vec::new();
+  let mut junk_range = (None, None);
+  for line in content.lines() {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      section = trimmed.to_ascii_lowercase();
+      match section.as_str() {
+        "[interface]" => has_interface = true,
+        "[peer]" => peers.push((None, false)),
+        _ => {}
+      }
+      continue;
+    }
+    let Some((key, value)) = split_key_value(trimmed) else {
+      continue;
+    };
+    match (section.as_str(), key.as_str()) {
+      ("[interface]", "privatekey") => private_key = Some(value.to_string()),
+      ("[interface]", "listenport") => {
+        parse_listen_port(value)?;
+      }
+      ("[interface]", key) if AMNEZIA_KEYS.contains(&key) => {
+        let number = value
+          .parse::<u32>()
+          .map_err(|_| format!("Invalid AmneziaWG {key}: {value} (expected a non-negative number)"))?;
+        match key {
+          "jmin" => junk_range.0 = Some(number),
+          "jmax" => junk_range.1 = Some(number),
+          _ => {}
+        }
+      }
+      ("[peer]", "publickey") => {
+        if let Some(peer) = peers.last_mut() {
+          peer.0 = Some(value.to_string());
+        }
+      }
+      ("[peer]", "endpoint") => {
+        parse_endpoint(value)?;
+        if let Some(peer) = peers.last_mut() {
+          peer.1 = true;
+        }
+      }
+      _ => {}
+    }
+  }
+  if let (Some(jmin), Some(jmax)) = junk_range {
+    if jmin > jmax {
+      return Err(format!("Invalid AmneziaWG Jmin/Jmax: {jmin} > {jmax}"));
+    }
+  }
+  if !has_interface {
+    return Err("missing [Interface] section".into());
+  }
+  let private_key = private_key.ok_or("missing PrivateKey in [Interface]")?;
+  if !is_wg_key(&private_key) {
+    return Err("PrivateKey is not a valid WireGuard key (expected 44 base64 characters)".into());
+  }
+  if peers.is_empty() {
+    return Err("missing [Peer] section".into());
+  }
+  for (index, (public_key, _)) in peers.iter().enumerate() {
+    let public_key = public_key
+      .as_deref()
+      .ok_or_else(|| format!("missing PublicKey in [Peer] #{}", index + 1))?;
+    if !is_wg_key(public_key) {
+      return Err(format!(
+        "PublicKey in [Peer] #{} is not a valid WireGuard key (expected 44 base64 characters)",
+        index + 1
+      ));
+    }
+  }
+  if !peers.iter().any(|(_, has_endpoint)| *has_endpoint) {
+    return Err("no [Peer] has an Endpoint".into());
+  }
+  Ok(())
+}
+
+fn is_wg_key(value: &str) -> bool {
+  value.len() == 44
+    && value.ends_with('=')
+    && value[..43]
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+pub fn uses_amnezia(content: &str) -> bool {
+  let mut in_interface = false;
+  for line in content.lines() {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      in_interface = trimmed.eq_ignore_ascii_case("[interface]");
+      continue;
+    }
+    if let Some((key, _)) = split_key_value(trimmed) {
+      if in_interface && AMNEZIA_KEYS.contains(&key.as_str()) {
+        return true;
+      }
+    }
+  }
+  false
+}
+
+pub fn resolve_includes(content: &str, base_dir: &Path) -> Result<String, String> {
+  if !content.lines().any(|line| include_target(line).is_some()) {
+    return Ok(content.to_string());
+  }
+  let base = base_dir
+    .canonicalize()
+    .map_err(|e| format!("Cannot resolve config directory: {e}"))?;
+  let mut resolved = String::new();
+  for line in content.lines() {
+    let Some(target) = include_target(line) else {
+      resolved.push_str(line);
+      resolved.push('\n');
+      continue;
+    };
+    let path = base
+      .join(target)
+      .canonicalize()
+      .map_err(|_| format!("Included file not found: {target}"))?;
+    if !path.starts_with(&base) || !path.is_file() {
+      return Err(format!(
+        "Included file must be a local file next to the config: {target}"
+      ));
+    }
+    let included = fs::read_to_string(&path).map_err(|e| format!("Cannot read {target}: {e}"))?;
+    if included.lines().any(|line| include_target(line).is_some()) {
+      return Err(format!("Nested includes are not supported: {target}"));
+    }
+    resolved.push_str(&included);
+    if !included.ends_with('\n') {
+      resolved.push('\n');
+    }
+  }
+  Ok(resolved)
+}
+
+fn include_target(line: &str) -> Option<&str> {
+  let rest = line.trim().strip_prefix(INCLUDE_DIRECTIVE)?;
+  let target = rest.trim_start().strip_prefix('=')?.trim();
+  (!target.is_empty()).then_some(target)
+}
+
+pub fn peer_endpoints(content: &str) -> Result<Vec<Endpoint>, String> {
+  let mut section = String::new();
+  let mut endpoints = Vec::new();
+  for line in content.lines() {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      section = trimmed.to_ascii_lowercase();
+      continue;
+    }
+    let Some((key, value)) = split_key_value(trimmed) else {
+      continue;
+    };
+    if section == "[peer]" && key == "endpoint" {
+      endpoints.push(parse_endpoint(value)?);
+    }
+  }
+  Ok(endpoints)
+}
+
+pub struct ConfigPeer {
+  pub public_key: String,
+  pub endpoint: Option<String>,
+  pub allowed_ips: Vec<String>,
+}
+
+pub fn parse_peers(content: &str) -> Vec<ConfigPeer> {
+  let mut peers: Vec<ConfigPeer> = Vec::new();
+  let mut in_peer = false;
+  for line in content.lines() {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      in_peer = trimmed.eq_ignore_ascii_case("[peer]");
+      if in_peer {
+        peers.push(ConfigPeer {
+          public_key: String::new(),
+          endpoint: None,
+          allowed_ips: Vec::new(),
+        });
+      }
+      continue;
+    }
+    if !in_peer {
+      continue;
+    }
+    let (Some(peer), Some((key, value))) = (peers.last_mut(), split_key_value(trimmed)) else {
+      continue;
+    };
+    match key.as_str() {
+      "publickey" => peer.public_key = value.to_string(),
+      "endpoint" => peer.endpoint = Some(value.to_string()),
+      "allowedips" => peer.allowed_ips.extend(
+        value
+          .split(',')
+          .map(|ip| ip.trim().to_string())
+          .filter(|ip| !ip.is_empty()),
+      ),
+      _ => {}
+    }
+  }
+  peers
+}
+
+pub fn parse_endpoint(value: &str) -> Result<Endpoint, String> {
+  let value = value.trim();
+  let invalid = || format!("Invalid Endpoint: {value} (expected host:port)");
+  let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+    let (host, port) = rest.split_once("]:").ok_or_else(invalid)?;
+    host
+      .parse::<Ipv6Addr>()
+      .map_err(|_| format!("Invalid Endpoint: {value} (bad IPv6 address)"))?;
+    (host, port)
+  } else {
+    let (host, port) = value.rsplit_once(':').ok_or_else(invalid)?;
+    if host.contains(':') {
+      return Err(format!(
+        "Invalid Endpoint: {value} (IPv6 addresses must be in brackets)"
+      ));
+    }
+    if host.parse::<Ipv4Addr>().is_err() && !is_valid_hostname(host) {
+      return Err(format!("Invalid Endpoint: {value} (bad host)"));
+    }
+    (host, port)
+  };
+  let port = port
+    .parse::<u16>()
+    .ok()
+    .filter(|port| *port != 0)
+    .ok_or_else(|| format!("Invalid Endpoint port: {port} (expected 1-65535)"))?;
+  Ok(Endpoint {
+    host: host.to_string(),
+    port,
+  })
+}
+
+const KNOWN_PROVIDERS: [(&str, &str); 10] = [
+  ("mullvad", "Mullvad"),
+  ("proton", "Proton VPN"),
+  ("ivpn", "IVPN"),
+  ("airvpn", "AirVPN"),
+  ("azire", "AzireVPN"),
+  ("windscribe", "Windscribe"),
+  ("nordvpn", "NordVPN"),
+  ("surfshark", "Surfshark"),
+  ("privateinternetaccess", "Private Internet Access"),
+  ("ovpn", "OVPN"),
+];
+
+pub fn infer_provider(file_stem: &str, endpoints: &[Endpoint]) -> (Option<String>, Option<String>) {
+  let stem = file_stem.to_ascii_lowercase();
+  let hosts: Vec<String> = endpoints
+    .iter()
+    .map(|endpoint| endpoint.host.to_ascii_lowercase())
+    .collect();
+  let known = KNOWN_PROVIDERS.iter().find(|(key, _)| {
+    stem.contains(key) || hosts.iter().any(|host| host.contains(key))
+  });
+  let provider = known.map(|(_, name)| name.to_string()).or_else(|| {
+    hosts
+      .iter()
+      .filter(|host| host.parse::<Ipv4Addr>().is_err() && host.parse::<Ipv6Addr>().is_err())
+      .find_map(|host| {
+        let labels: Vec<&str> = host.trim_end_matches('.').split('.').collect();
+        (labels.len() >= 2).then(|| labels[labels.len() - 2..].join("."))
+      })
+  });
+  let region = stem
+    .split(|c: char| !c.is_ascii_alphanumeric())
+    .filter(|token| !known.is_some_and(|(key, _)| token.contains(key)))
+    .find(|token| token.len() == 2 && token.chars().all(|c| c.is_ascii_alphabetic()))
+    .map(|token| token.to_ascii_uppercase());
+  (provider, region)
+}
+
+fn is_valid_hostname(host: &str) -> bool {
+  !host.is_empty()
+    && host.len() <= 253
+    && host.trim_end_matches('.').split('.').all(|label| {
+      !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+pub fn looks_like_wg_config(content: &str) -> bool {
+  let content = content.trim_start_matches('\u{feff}');
+  let has_interface = content
+    .lines()
+    .any(|line| line.trim().eq_ignore_ascii_case("[interface]"));
+  has_interface
+    && content
+      .lines()
+      .filter_map(|line| split_key_value(line.trim()))
+      .any(|(key, _)| key == "privatekey")
+}
+
+pub fn normalized_config(content: &str) -> String {
+  let mut interface: Vec<String> = Vec::new();
+  let mut peers: Vec<Vec<String>> = Vec::new();
+  let mut in_peer = false;
+  for line in content.trim_start_matches('\u{feff}').lines() {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+      in_peer = trimmed.eq_ignore_ascii_case("[peer]");
+      if in_peer {
+        peers.push(Vec::new());
+      }
+      continue;
+    }
+    let Some((key, value)) = split_key_value(trimmed) else {
+      continue;
+    };
+    if SECRET_KEYS.contains(&key.as_str()) {
+      continue;
+    }
+    let section = match peers.last_mut() {
+      Some(peer) if in_peer => peer,
+      _ => &mut interface,
+    };
+    if LIST_KEYS.contains(&key.as_str()) {
+      section.extend(
+        value
+          .split(',')
+          .map(str::trim)
+          .filter(|item| !item.is_empty())
+          .map(|item| format!("{key}={}", item.to_ascii_lowercase())),
+      );
+    } else {
+      let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+      section.push(format!("{key}={value}"));
+    }
+  }
+  interface.sort();
+  interface.dedup();
+  let mut peers: Vec<String> = peers
+    .into_iter()
+    .map(|mut peer| {
+      peer.sort();
+      peer.dedup();
+      peer.join("\n")
+    })
+    .collect();
+  peers.sort();
+  let mut normalized = format!("[interface]\n{}\n", interface.join("\n"));
+  for peer in peers {
+    normalized.push_str(&format!("[peer]\n{peer}\n"));
+  }
+  normalized
+}
+
+pub fn has_save_config(content: &str) -> bool {
+  content
+    .lines()
+    .filter_map(|line| split_key_value(line.trim()))
+    .any(|(key, _)| key == "saveconfig")
+}
+
+pub fn private_key_digest(content: &str) -> Option<u64> {
+  let key = content
+    .lines()
+    .filter_map(|line| split_key_value(line.trim()))
+    .find(|(key, _)| key == "privatekey")
+    .map(|(_, value)| value.to_string())
+    .filter(|value| !value.is_empty())?;
+  let mut hasher = DefaultHasher::new();
+  key.hash(&mut hasher);
+  Some(hasher.finish())
+}
+
+pub fn has_dns(content: &str) -> bool {
+  content
+    .lines()
+    .filter_map(|line| split_key_value(line.trim()))
+    .any(|(key, value)| key == "dns" && !value.is_empty())
+}
+
+pub fn dns_servers(content: &str) -> Vec<IpAddr> {
+  content
+    .lines()
+    .filter_map(|line| split_key_value(line.trim()))
+    .filter(|(key, _)| key == "dns")
+    .flat_map(|(_, value)| {
+      value
+        .split(',')
+        .filter_map(|entry| entry.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>()
+    })
+    .collect()
+}
+
+pub fn cidr_contains(cidr: &str, ip: IpAddr) -> bool {
+  let (network, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+  let Ok(network) = network.trim().parse::<IpAddr>() else {
+    return false;
+  };
+  match (network, ip) {
+    (IpAddr::V4(network), IpAddr::V4(ip)) => {
+      let prefix = prefix.parse::<u32>().unwrap_or(32).min(32);
+      let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+      u32::from(network) & mask == u32::from(ip) & mask
+    }
+    (IpAddr::V6(network), IpAddr::V6(ip)) => {
+      let prefix = prefix.parse::<u32>().unwrap_or(128).min(128);
+      let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+      u128::from(network) & mask == u128::from(ip) & mask
+    }
+    _ => false,
+  }
+}
+
+pub fn aggregate_cidrs(cidrs: &[String]) -> (Vec<String>, Vec<String>) {
+  let mut v4 = Vec::new();
+  let mut v6 = Vec::new();
+  for cidr in cidrs {
+    let (network, prefix) = cidr.trim().split_once('/').unwrap_or((cidr.trim(), ""));
+    match network.parse::<IpAddr>() {
+      Ok(IpAddr::V4(network)) => {
+        let prefix = prefix.parse::<u32>().unwrap_or(32).min(32);
+        v4.push((u128::from(u32::from(network)), prefix));
+      }
+      Ok(IpAddr::V6(network)) => {
+        let prefix = prefix.parse::<u32>().unwrap_or(128).min(128);
+        v6.push((u128::from(network), prefix));
+      }
+      Err(_) => {}
+    }
+  }
+  let v4 = merge_networks(v4, 32)
+    .into_iter()
+    .map(|(network, prefix)| format!("{}/{prefix}", Ipv4Addr::from(network as u32)))
+    .collect();
+  let v6 = merge_networks(v6, 128)
+    .into_iter()
+    .map(|(network, prefix)| format!("{}/{prefix}", Ipv6Addr::from(network)))
+    .collect();
+  (v4, v6)
+}
+
+fn merge_networks(mut networks: Vec<(u128, u32)>, width: u32) -> Vec<(u128, u32)> {
+  let all = u128::MAX.checked_shr(128 - width).unwrap_or(0);
+  let mask = |prefix: u32| all ^ all.checked_shr(prefix).unwrap_or(0);
+  for (network, prefix) in &mut networks {
+    *network &= mask(*prefix);
+  }
+  networks.sort();
+  let mut merged: Vec<(u128, u32)> = Vec::new();
+  for (network, prefix) in networks {
+    if merged
+      .iter()
+      .any(|(outer, outer_prefix)| *outer_prefix <= prefix && network & mask(*outer_prefix) == *outer)
+    {
+      continue;
+    }
+    let mut current = (network, prefix);
+    while let Some(&(last, last_prefix)) = merged.last() {
+      let (network, prefix) = current;
+      let siblings = prefix > 0
+        && last_prefix == prefix
+        && last ^ network == 1u128 << (width - prefix);
+      if !siblings {
+        break;
+      }
+      merged.pop();
+      current = (last & mask(prefix - 1), prefix - 1);
+    }
+    merged.push(current);
+  }
+  merged
+}
+
+pub fn parse_listen_port(value: &str) -> Result<u16, String> {
+  value
+    .trim()
+    .parse::<u16>()
+    .map_err(|_| format!("Invalid ListenPort: {} (expected 0-65535)", value.trim()))
+}
+
+fn split_key_value(line: &str) -> Option<(String, &str)> {
+  if line.starts_with('#') || line.starts_with(';') {
+    return None;
+  }
+  let (key, value) = line.split_once('=')?;
+  Some((key.trim().to_ascii_lowercase(), value.trim()))
+}
+
+const IFNAME_MAX_LEN: usize = 15;
+const RESERVED_IFNAMES: [&str; 3] = ["lo", "svpn0", "svpn1"];
+
+pub fn sanitize_ifname(name: &str) -> String {
+  let mut out = String::new();
+  for c in name.trim().to_lowercase().chars() {
+    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+      out.push(c);
+    } else if !out.ends_with('-') {
+      out.push('-');
+    }
+  }
+  let out: String = out.trim_matches('-').chars().take(IFNAME_MAX_LEN).collect();
+  let out = out.trim_end_matches('-').to_string();
+  if out.is_empty() {
+    "wg".to_string()
+  } else {
+    out
+  }
+}
+
+pub fn unique_ifname(name: &str, id: &str, taken: &[String]) -> String {
+  let base = sanitize_ifname(name);
+  if !taken.contains(&base) && !RESERVED_IFNAMES.contains(&base.as_str()) {
+    return base;
+  }
+  let suffix = format!("-{:04x}", short_hash(id));
+  let keep = IFNAME_MAX_LEN - suffix.len();
+  let prefix: String = base.chars().take(keep).collect();
+  format!("{}{}", prefix.trim_end_matches('-'), suffix)
+}
+
+fn short_hash(value: &str) -> u16 {
+  let mut hash: u32 = 0x811c9dc5;
+  for byte in value.bytes() {
+    hash ^= byte as u32;
+    hash = hash.wrapping_mul(0x01000193);
+  }
+  (hash ^ (hash >> 16)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::temp_dir;
+
+  #[test]
+  fn endpoints_split_into_host_and_port() {
+    let parsed = |value: &str| parse_endpoint(value).map(|endpoint| (endpoint.host, endpoint.port));
+    assert_eq!(parsed("198.51.100.7:51820"), Ok(("198.51.100.7".to_string(), 51820)));
+    assert_eq!(parsed(" vpn.example.com:443 "), Ok(("vpn.example.com".to_string(), 443)));
+    assert_eq!(parsed("[2001:db8::1]:51820"), Ok(("2001:db8::1".to_string(), 51820)));
+    assert_eq!(parsed("[::ffff:192.0.2.1]:1"), Ok(("::ffff:192.0.2.1".to_string(), 1)));
+  }
+
+  #[test]
+  fn malformed_endpoints_are_rejected() {
+    for bad in [
+      "198.51.100.7:0",
+      "198.51.100.7:65536",
+      "198.51.100.7",
+      "vpn.example.com:",
+      "2001:db8::1:51820",
+      "[2001:db8::1]",
+      "[not-an-ip]:51820",
+      "[2001:db8::1]:0",
+      "-bad-.example.com:51820",
+      "under_score.example.com:51820",
+    ] {
+      assert!(parse_endpoint(bad).is_err(), "{bad}");
+    }
+  }
+
+  const KEY: &str = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=";
+
+  fn config(interface: &str, peer: &str) -> String {
+    format!(
+      "{interface}\nPrivateKey = {KEY}\nAddress = 10.0.0.2/32\n\n{peer}\nAllowedIPs = 0.0.0.0/0\n"
+    )
+  }
+
+  #[test]
+  fn complete_config_is_valid() {
+    let peer = format!("[Peer]\nPublicKey = {KEY}\nEndpoint = vpn.example.com:51820");
+    assert_eq!(validate_config(&config("[Interface]", &peer)), Ok(()));
+  }
+
+  #[test]
+  fn config_without_interface_is_rejected() {
+    let peer = format!("[Peer]\nPublicKey = {KEY}\nEndpoint = vpn.example.com:51820");
+    let err = validate_config(&config("# no interface", &peer)).unwrap_err();
+    assert!(err.contains("[Interface]"), "{err}");
+  }
+
+  #[test]
+  fn keys_of_the_wrong_length_are_rejected() {
+    let short = &KEY[1..];
+    let peer = format!("[Peer]\nPublicKey = {short}\nEndpoint = vpn.example.com:51820");
+    let err = validate_config(&config("[Interface]", &peer)).unwrap_err();
+    assert!(err.contains("PublicKey in [Peer] #1"), "{err}");
+
+    let peer = format!("[Peer]\nPublicKey = {KEY}\nEndpoint = vpn.example.com:51820");
+    let content = config("[Interface]", &peer).replacen(KEY, &format!("{KEY}A"), 1);
+    assert!(validate_config(&content).unwrap_err().contains("PrivateKey"));
+  }
+
+  #[test]
+  fn peer_without_public_key_is_rejected() {
+    let peer = "[Peer]\nEndpoint = vpn.example.com:51820";
+    let err = validate_config(&config("[Interface]", peer)).unwrap_err();
+    assert!(err.contains("missing PublicKey in [Peer] #1"), "{err}");
+  }
+
+  #[test]
+  fn config_without_any_endpoint_is_rejected() {
+    let peer = format!("[Peer]\nPublicKey = {KEY}");
+    let err = validate_config(&config("[Interface]", &peer)).unwrap_err();
+    assert!(err.contains("Endpoint"), "{err}");
+  }
+
+  fn aggregated(cidrs: &[&str]) -> (Vec<String>, Vec<String>) {
+    aggregate_cidrs(&cidrs.iter().map(|cidr| cidr.to_string()).collect::<Vec<_>>())
+  }
+
+  #[test]
+  fn contained_cidrs_are_dropped() {
+    let (v4, _) = aggregated(&["10.1.2.0/24", "10.0.0.0/8", "10.200.0.7/32"]);
+    assert_eq!(v4, ["10.0.0.0/8"]);
+  }
+
+  #[test]
+  fn sibling_cidrs_merge_into_their_parent() {
+    let (v4, _) = aggregated(&["192.168.1.0/25", "192.168.0.0/24", "192.168.1.128/25"]);
+    assert_eq!(v4, ["192.168.0.0/23"]);
+    let (v4, _) = aggregated(&["192.168.0.0/24", "192.168.2.0/24"]);
+    assert_eq!(v4, ["192.168.0.0/24", "192.168.2.0/24"]);
+  }
+
+  #[test]
+  fn split_default_routes_count_as_full_tunnel() {
+    assert_eq!(aggregated(&["0.0.0.0/1", "128.0.0.0/1"]).0, ["0.0.0.0/0"]);
+    assert_eq!(aggregated(&["::/1", "8000::/1"]).1, ["::/0"]);
+  }
+
+  #[test]
+  fn includes_are_inlined_from_the_config_directory() {
+    let dir = temp_dir("include-local");
+    fs::write(dir.join("keys.conf"), "PrivateKey = secret").unwrap();
+    let resolved = resolve_includes("[Interface]\n#sillyvpn-include = keys.conf\n", &dir).unwrap();
+    assert_eq!(resolved, "[Interface]\nPrivateKey = secret\n");
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn includes_outside_the_config_directory_are_rejected() {
+    let root = temp_dir("include-escape");
+    let dir = root.join("configs");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(root.join("outside.conf"), "PrivateKey = stolen\n").unwrap();
+    std::os::unix::fs::symlink(root.join("outside.conf"), dir.join("link.conf")).unwrap();
+    for target in ["../outside.conf", "link.conf"] {
+      let err = resolve_includes(&format!("#sillyvpn-include = {target}\n"), &dir).unwrap_err();
+      assert!(err.contains("must be a local file"), "{target}: {err}");
+    }
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn nested_includes_are_rejected() {
+    let dir = temp_dir("include-nested");
+    fs::write(dir.join("outer.conf"), "#sillyvpn-include = inner.conf\n").unwrap();
+    fs::write(dir.join("inner.conf"), "PrivateKey = secret\n").unwrap();
+    let err = resolve_includes("#sillyvpn-include = outer.conf\n", &dir).unwrap_err();
+    assert!(err.contains("Nested includes"), "{err}");
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn malformed_amnezia_parameters_are_rejected() {
+    let peer = format!("[Peer]\nPublicKey = {KEY}\nEndpoint = vpn.example.com:51820");
+    let with = |params: &str| config(&format!("[Interface]\n{params}"), &peer);
+    assert_eq!(validate_config(&with("Jc = 4\nJmin = 40\nJmax = 70")), Ok(()));
+    let err = validate_config(&with("Jc = four")).unwrap_err();
+    assert!(err.contains("Invalid AmneziaWG jc: four"), "{err}");
+    let err = validate_config(&with("Jmin = 70\nJmax = 40")).unwrap_err();
+    assert!(err.contains("Jmin/Jmax: 70 > 40"), "{err}");
+  }
+
+  #[test]
+  fn amnezia_is_detected_from_interface_keys_only() {
+    assert!(uses_amnezia("[Interface]\nJc = 4\n"));
+    assert!(!uses_amnezia("[Interface]\n# Jc = 4\n"));
+    assert!(!uses_amnezia("[Peer]\nJc = 4\n"));
+    assert!(!uses_amnezia("[Interface\nJc = 4\n"));
+  }
+
+  #[test]
+  fn save_config_is_detected_in_any_case() {
+    assert!(has_save_config("[Interface]\nSaveConfig = true\n"));
+    assert!(has_save_config("[Interface]\r\nsaveconfig=true\r\n"));
+    assert!(!has_save_config("[Interface]\n# SaveConfig = true\n"));
+    assert!(!has_save_config("[Interface]\nPrivateKey = key\n"));
+  }
+
+  #[test]
+  fn listen_port_accepts_the_full_u16_range_only() {
+    assert_eq!(parse_listen_port("0"), Ok(0));
+    assert_eq!(parse_listen_port(" 51820 "), Ok(51820));
+    assert_eq!(parse_listen_port("65535"), Ok(65535));
+    for bad in ["65536", "-1", "", "port", "51820 # fixed"] {
+      assert!(parse_listen_port(bad).is_err(), "{bad}");
+    }
+  }
+
+  #[test]
+  fn long_names_are_cut_without_a_trailing_dash() {
+    assert_eq!(sanitize_ifname("my-vpn-provider-de"), "my-vpn-provider");
+    assert_eq!(sanitize_ifname("netherlands-home_office"), "netherlands-hom");
+    assert_eq!(sanitize_ifname("office-vpn-1234-x"), "office-vpn-1234");
+    assert_eq!(sanitize_ifname("abcdefghijklmn-opq"), "abcdefghijklmn");
+  }
+
+  #[test]
+  fn unicode_names_fall_back_to_ascii() {
+    assert_eq!(sanitize_ifname("Мой VPN"), "vpn");
+    assert_eq!(sanitize_ifname("дом"), "wg");
+    assert_eq!(sanitize_ifname("  Офис 2 "), "2");
+  }
+
+  #[test]
+  fn colliding_and_reserved_names_get_a_suffix() {
+    let taken = vec!["office".to_string()];
+    let first = unique_ifname("office", "id-1", &taken);
+    let second = unique_ifname("office", "id-2", &taken);
+    assert!(first.starts_with("office-") && first.len() <= IFNAME_MAX_LEN);
+    assert_ne!(first, second);
+    assert_eq!(unique_ifname("home", "id-1", &taken), "home");
+
+    for reserved in ["svpn0", "SVPN1", "lo"] {
+      let name = unique_ifname(reserved, "id-1", &[]);
+      assert_ne!(name, reserved.to_lowercase());
+      assert!(name.len() <= IFNAME_MAX_LEN);
+    }
+    let long = unique_ifname("a-very-long-tunnel-name", "id-1", &["a-very-long-tun".to_string()]);
+    assert_eq!(long.len(), IFNAME_MAX_LEN);
+    assert!(!long.contains("--"));
+  }
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "netns-tests")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const HELPER: &str = env!("CARGO_BIN_EXE_sillyvpn-helper");
+const TABLE: &str = "51999";
+const FWMARK: &str = "0x5199";
+const SUBNET: &str = "10.213.77.0/24";
+
+struct Namespace {
+  name: String,
+  ifname: String,
+  dir: PathBuf,
+}
+
+impl Namespace {
+  fn new() -> Self {
+    let id = std::process::id() % 100_000;
+    let dir = std::env::temp_dir().join(format!("sillyvpn-netns-{id}"));
+    fs::create_dir_all(&dir).unwrap();
+    Namespace {
+      name: format!("svt{id}"),
+      ifname: format!("svtwg{id}"),
+      dir,
+    }
+  }
+
+  fn helper(&self, args: &[&str]) -> Output {
+    Command::new(HELPER)
+      .args(args)
+      .env("SILLYVPN_TEST_NETNS", &self.name)
+      .output()
+      .unwrap()
+  }
+
+  fn config(&self) -> PathBuf {
+    let path = self.dir.join("tunnel.conf");
+    fs::write(
+      &path,
+      "[Interface]\n\
+       PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n\
+       Address = 10.99.0.2/32\n\
+       \n\
+       [Peer]\n\
+       PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+       AllowedIPs = 0.0.0.0/0\n\
+       Endpoint = 192.0.2.1:51820\n",
+    )
+    .unwrap();
+    path
+  }
+}
+
+impl Drop for Namespace {
+  fn drop(&mut self) {
+    self.helper(&["disable"]);
+    let _ = fs::remove_dir_all(&self.dir);
+  }
+}
+
+fn stdout(cmd: &str, args: &[&str]) -> String {
+  let output = Command::new(cmd).args(args).output().unwrap();
+  String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn is_root() -> bool {
+  unsafe { libc::geteuid() == 0 }
+}
+
+#[test]
+fn enable_and_disable_in_a_throwaway_namespace() {
+  if !is_root() {
+    eprintln!("skipping: netns tests need root");
+    return;
+  }
+  let ns = Namespace::new();
+  let config = ns.config();
+  let output = ns.helper(&[
+    "enable",
+    "--config",
+    config.to_str().unwrap(),
+    "--ifname",
+    &ns.ifname,
+    "--subnet",
+    SUBNET,
+    "--table",
+    TABLE,
+    "--fwmark",
+    FWMARK,
+  ]);
+  assert!(
+    output.status.success(),
+    "enable failed: {}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  assert!(Path::new("/var/run/netns").join(&ns.name).exists());
+  assert!(Path::new("/sys/class/net").join(&ns.ifname).exists());
+  assert!(Path::new("/sys/class/net").join(format!("{}h", ns.name)).exists());
+  let routes = stdout("ip", &["netns", "exec", &ns.name, "ip", "route"]);
+  assert!(routes.contains("default via 10.213.77.1"), "{routes}");
+  let rules = stdout("ip", &["rule"]);
+  assert!(rules.contains(&format!("fwmark {FWMARK} lookup {TABLE}")), "{rules}");
+
+  let output = ns.helper(&["disable"]);
+  assert!(
+    output.status.success(),
+    "disable failed: {}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert!(!Path::new("/var/run/netns").join(&ns.name).exists());
+  assert!(!Path::new("/sys/class/net").join(&ns.ifname).exists());
+  assert!(!Path::new("/sys/class/net").join(format!("{}h", ns.name)).exists());
+  assert!(!stdout("ip", &["rule"]).contains(&format!("lookup {TABLE}")));
+}
@@ -1,7 +1,21 @@
 import { useEffect, useMemo, useState } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
-import { open } from "@tauri-apps/api/dialog";
-import { AppItem, AppState, PolkitStatus, Tunnel } from "./types";
+import { open, save } from "@tauri-apps/api/dialog";
+import { listen } from "@tauri-apps/api/event";
+import {
+  AppItem,
+  AppLaunchResult,
+  AppState,
+  CommandError,
+  ConfigPermissionIssue,
+  DisableReport,
+  NetworkChange,
+  PowerChange,
+  PolkitStatus,
+  RoutingConflict,
+  TransferStats,
+  Tunnel
+} from "./types";
 
 const LOG_POLL_MS = 1500;
 
@@ -10,9 +24,47 @@ const emptyState: AppState = {
   apps: [],
   last_tunnel_id: null,
   last_app_id: null,
-  vpn_enabled: false
+  vpn_enabled: false,
+  active_tunnel_id: null,
+  on_network_change: "off",
+  on_suspend: "off",
+  probe_timeout_secs: 10,
+  probe_retries: 0,
+  extra_env_keys: [],
+  config_via_stdin: false,
+  terminal: null,
+  on_connect_cmd: null,
+  on_disconnect_cmd: null,
+  privileged_debug: false,
+  randomize_subnet: false,
+  app_output_limit_kib: 256,
+  kill_apps_on_disable: true,
+  app_log_limit_kib: 1024,
+  compress_app_logs: true
 };
 
+function errorMessage(err: unknown) {
+  if (typeof err === "object" && err !== null && "message" in err) {
+    const { code, message } = err as CommandError;
+    if (code === "HELPER_MISSING") {
+      return `${message}. Rebuild or reinstall sillyvpn so the helper is bundled.`;
+    }
+    return message;
+  }
+  return String(err);
+}
+
+function formatBytes(bytes: number) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
 function basename(path: string) {
   const parts = path.split("/").filter(Boolean);
   return parts[parts.length - 1] ?? path;
@@ -26,12 +78,22 @@ export default function App() {
   const [logs, setLogs] = useState<string[]>([]);
   const [busy, setBusy] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  const [transfer, setTransfer] = useState<TransferStats | null>(null);
 
   const selectedTunnel: Tunnel | undefined = useMemo(
     () => state.tunnels.find((t) => t.id === selectedTunnelId),
     [state.tunnels, selectedTunnelId]
   );
 
+  const tunnelGroups: [string, Tunnel[]][] = useMemo(() => {
+    const groups = new Map<string, Tunnel[]>();
+    for (const tunnel of state.tunnels.filter((t) => t.enabled)) {
+      const provider = tunnel.provider ?? "Other";
+      groups.set(provider, [...(groups.get(provider) ?? []), tunnel]);
+    }
+    return [...groups.entries()];
+  }, [state.tunnels]);
+
   const refreshState = async () => {
     const next = await invoke<AppState>("get_state");
     setState(next);
@@ -51,6 +113,26 @@ export default function App() {
   useEffect(() => {
     refreshState().catch(console.error);
     refreshLogs().catch(console.error);
+    invoke<ConfigPermissionIssue[]>("check_config_permissions")
+      .then((issues) => {
+        if (issues.length > 0) {
+          const proceed = window.confirm(
+            `${issues.length} stored config(s) are readable by others or have the wrong owner. Fix permissions now?`
+          );
+          if (proceed) {
+            invoke<ConfigPermissionIssue[]>("fix_config_permissions")
+              .then((remaining) => {
+                if (remaining.length > 0) {
+                  setError(
+                    `Could not fix: ${remaining.map((issue) => issue.path).join(", ")}`
+                  );
+                }
+              })
+              .catch((err) => setError(errorMessage(err)));
+          }
+        }
+      })
+      .catch(console.error);
     invoke<PolkitStatus>("check_polkit_agent")
       .then((status) => {
         if (!status.running) {
@@ -76,9 +158,27 @@ export default function App() {
     const timer = setInterval(() => {
       refreshLogs().catch(console.error);
     }, LOG_POLL_MS);
+    const unlistenNetwork = listen<NetworkChange>("network-changed", (event) => {
+      if (event.payload.action === "disable") {
+        setError("Network changed, VPN was disabled.");
+      }
+      refreshState().catch(console.error);
+    });
+    const unlistenPower = listen<PowerChange>("power-changed", () => {
+      refreshState().catch(console.error);
+    });
+    const unlistenLaunch = listen<AppLaunchResult>("app-launch", (event) => {
+      const result = event.payload;
+      if (result.status === "failed" || result.status === "unconfirmed") {
+        setError(`${result.label}: ${result.detail ?? result.status}`);
+      }
+    });
 
     return () => {
       clearInterval(timer);
+      unlistenNetwork.then((unlisten) => unlisten());
+      unlistenLaunch.then((unlisten) => unlisten());
+      unlistenPower.then((unlisten) => unlisten());
       window.removeEventListener("resize", updateScale);
     };
   }, []);
@@ -92,10 +192,13 @@ export default function App() {
     if (!selected || Array.isArray(selected)) return;
     setBusy(true);
     try {
-      await invoke("import_conf", { path: selected });
+      const warning = await invoke<string | null>("import_conf", { path: selected });
       await refreshState();
+      if (warning) {
+        setError(warning);
+      }
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
     } finally {
       setBusy(false);
     }
@@ -110,11 +213,17 @@ export default function App() {
     setBusy(true);
     try {
       const label = basename(manualAppPath.trim());
-      await invoke("add_app", { path: manualAppPath.trim(), label });
+      const warning = await invoke<string | null>("add_app", {
+        path: manualAppPath.trim(),
+        label
+      });
       setManualAppPath("");
       await refreshState();
+      if (warning) {
+        setError(warning);
+      }
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
     } finally {
       setBusy(false);
     }
@@ -127,7 +236,44 @@ export default function App() {
       await invoke("remove_app", { appId: app.id });
       await refreshState();
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  const onRefreshTransfer = async () => {
+    setError(null);
+    try {
+      setTransfer(await invoke<TransferStats>("get_transfer_stats"));
+    } catch (err) {
+      setError(errorMessage(err));
+    }
+  };
+
+  const onRemoveTunnel = async () => {
+    if (!selectedTunnelId) return;
+    setError(null);
+    setBusy(true);
+    try {
+      await invoke("remove_tunnel", { tunnelId: selectedTunnelId });
+      setSelectedTunnelId("");
+      await refreshState();
+    } catch (err) {
+      setError(errorMessage(err));
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  const onToggleApp = async (app: AppItem) => {
+    setError(null);
+    setBusy(true);
+    try {
+      await invoke("update_app", { appId: app.id, enabled: !app.enabled });
+      await refreshState();
+    } catch (err) {
+      setError(errorMessage(err));
     } finally {
       setBusy(false);
     }
@@ -141,10 +287,20 @@ export default function App() {
     }
     setBusy(true);
     try {
+      const conflicts = await invoke<RoutingConflict[]>("check_conflicts");
+      if (conflicts.length > 0) {
+        const details = conflicts
+          .map((conflict) => `${conflict.source}: ${conflict.entry}`)
+          .join("\n");
+        const proceed = window.confirm(
+          `Another VPN seems to use the same routing table or fwmark:\n${details}\n\nEnable anyway?`
+        );
+        if (!proceed) return;
+      }
       await invoke("enable_vpn", { tunnelId: selectedTunnelId });
       await refreshState();
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
     } finally {
       setBusy(false);
     }
@@ -154,21 +310,52 @@ export default function App() {
     setError(null);
     setBusy(true);
     try {
-      await invoke("disable_vpn");
+      const report = await invoke<DisableReport>("disable_vpn", {
+        killApps: state.kill_apps_on_disable
+      });
       await refreshState();
+      if (report.warning) {
+        setError(report.warning);
+      }
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
     } finally {
       setBusy(false);
     }
   };
 
+  const onPinApp = async (app: AppItem, tunnelId: string) => {
+    setError(null);
+    try {
+      await invoke("update_app", { appId: app.id, preferredTunnelId: tunnelId });
+      await refreshState();
+    } catch (err) {
+      setError(errorMessage(err));
+    }
+  };
+
   const onRun = async (app: AppItem) => {
     setError(null);
+    let switchTunnel = false;
+    const pinned = state.tunnels.find(
+      (tunnel) => tunnel.id === app.preferred_tunnel_id
+    );
+    if (
+      pinned &&
+      (!state.vpn_enabled || state.active_tunnel_id !== pinned.id)
+    ) {
+      switchTunnel = window.confirm(
+        `${app.label} is pinned to ${pinned.name}. Switch the VPN to this tunnel?`
+      );
+      if (!switchTunnel) return;
+    }
     try {
-      await invoke("run_app_via_vpn", { appId: app.id });
+      await invoke("run_app_via_vpn", { appId: app.id, switchTunnel });
+      if (switchTunnel) {
+        await refreshState();
+      }
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
     }
   };
 
@@ -178,7 +365,18 @@ export default function App() {
       await invoke("clear_logs");
       setLogs([]);
     } catch (err) {
-      setError(String(err));
+      setError(errorMessage(err));
+    }
+  };
+
+  const onExportLogs = async () => {
+    setError(null);
+    const dest = await save({ defaultPath: "sillyvpn.log" });
+    if (!dest) return;
+    try {
+      await invoke("export_logs", { destPath: dest });
+    } catch (err) {
+      setError(errorMessage(err));
     }
   };
 
@@ -210,6 +408,18 @@ export default function App() {
                 {state.vpn_enabled ? "via namespace" : "-"}
               </p>
             </div>
+            <div>
+              <p className="label">Transfer</p>
+              <p className="value">
+                {state.vpn_enabled && transfer
+                  ? `↓ ${formatBytes(
+                      transfer.peers.reduce((sum, peer) => sum + peer.rx_bytes, 0)
+                    )} / ↑ ${formatBytes(
+                      transfer.peers.reduce((sum, peer) => sum + peer.tx_bytes, 0)
+                    )}`
+                  : "-"}
+              </p>
+            </div>
           </div>
           {error && <div className="error">{error}</div>}
           <div className="status-actions">
@@ -220,6 +430,13 @@ export default function App() {
             >
               {state.vpn_enabled ? "Disable VPN" : "Enable VPN"}
             </button>
+            <button
+              className="ghost"
+              onClick={onRefreshTransfer}
+              disabled={busy || !state.vpn_enabled}
+            >
+              Refresh transfer
+            </button>
           </div>
         </section>
 
@@ -229,6 +446,13 @@ export default function App() {
             <button onClick={onImport} className="ghost" disabled={busy}>
               Import .conf
             </button>
+            <button
+              onClick={onRemoveTunnel}
+              className="danger"
+              disabled={busy || !selectedTunnelId}
+            >
+              Remove
+            </button>
           </div>
           <div className="field">
             <label>Available tunnels</label>
@@ -243,10 +467,14 @@ export default function App() {
               }}
             >
               <option value="">Select tunnel</option>
-              {state.tunnels.map((tunnel) => (
-                <option key={tunnel.id} value={tunnel.id}>
-                  {tunnel.name}
-                </option>
+              {tunnelGroups.map(([provider, tunnels]) => (
+                <optgroup key={provider} label={provider}>
+                  {tunnels.map((tunnel) => (
+                    <option key={tunnel.id} value={tunnel.id}>
+                      {tunnel.region ? `${tunnel.name} (${tunnel.region})` : tunnel.name}
+                    </option>
+                  ))}
+                </optgroup>
               ))}
             </select>
           </div>
@@ -294,18 +522,45 @@ export default function App() {
                 <div className="app-info">
                   <p className="value">{app.label}</p>
                   <p className="muted">{app.path}</p>
+                  {app.note && <p className="muted">{app.note}</p>}
                 </div>
                 <div className="row-actions">
+                  <select
+                    value={app.preferred_tunnel_id ?? ""}
+                    onClick={(event) => event.stopPropagation()}
+                    onChange={(event) => onPinApp(app, event.target.value)}
+                    disabled={busy}
+                  >
+                    <option value="">Any tunnel</option>
+                    {state.tunnels.map((tunnel) => (
+                      <option key={tunnel.id} value={tunnel.id}>
+                        {tunnel.name}
+                      </option>
+                    ))}
+                  </select>
                   <button
                     className="ghost"
                     onClick={(event) => {
                       event.stopPropagation();
                       onRun(app);
                     }}
-                    disabled={!state.vpn_enabled}
+                    disabled={
+                      (!state.vpn_enabled && !app.preferred_tunnel_id) ||
+                      !app.enabled
+                    }
                   >
                     Run via VPN
                   </button>
+                  <button
+                    className="ghost"
+                    onClick={(event) => {
+                      event.stopPropagation();
+                      onToggleApp(app);
+                    }}
+                    disabled={busy}
+                  >
+                    {app.enabled ? "Disable" : "Enable"}
+                  </button>
                   <button
                     className="danger"
                     onClick={(event) => {
@@ -327,6 +582,9 @@ export default function App() {
             <h2>Logs</h2>
             <div className="log-actions">
               <span className="muted">last {logs.length} lines</span>
+              <button className="ghost" onClick={onExportLogs}>
+                Export
+              </button>
               <button className="ghost" onClick={onClearLogs}>
                 Clear
               </button>
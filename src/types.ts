@@ -2,12 +2,98 @@ export type Tunnel = {
   id: string;
   name: string;
   path: string;
+  note?: string | null;
+  enabled: boolean;
+  bypass_routes: string[];
+  dns_mode: DnsMode;
+  auto_mtu: boolean;
+  endpoints: Endpoint[];
+  manage_routes_externally: boolean;
+  masquerade: boolean;
+  launch_profile: string[];
+  resolv_options?: string | null;
+  connect_count: number;
+  connect_timeout_secs?: number | null;
+  veth_subnet?: string | null;
+  route_table?: number | null;
+  fwmark?: number | null;
+  kill_switch?: boolean;
+  provider?: string | null;
+  region?: string | null;
 };
 
+export type ConfigUsage = {
+  tunnel_id: string;
+  name: string;
+  bytes: number;
+};
+
+export type StorageUsage = {
+  data_dir: string;
+  total_bytes: number;
+  state_bytes: number;
+  logs_bytes: number;
+  configs: ConfigUsage[];
+  orphaned_config_bytes: number;
+  other_bytes: number;
+  suggestion: string | null;
+};
+
+export type ConfigPermissionIssue = {
+  path: string;
+  mode: string;
+  owner_uid: number;
+  expected_uid: number;
+};
+
+export type Endpoint = {
+  host: string;
+  port: number;
+};
+
+export type DnsMode = "tunnel" | "host" | "resolved";
+
+export type BinaryKind = "elf" | "script" | "unknown";
+
 export type AppItem = {
   id: string;
   label: string;
   path: string;
+  kind: BinaryKind;
+  interpreter?: string | null;
+  note?: string | null;
+  enabled: boolean;
+  autostart: boolean;
+  in_terminal: boolean;
+  capture_output: boolean;
+  limits?: AppLimits;
+  kill_signal?: KillSignal;
+  preferred_tunnel_id?: string | null;
+  args?: string[];
+  cwd?: string | null;
+};
+
+export type KillSignal = "term_then_kill" | "kill_only" | "term_only";
+
+export type AppLimits = {
+  no_new_privs: boolean;
+  max_memory_mb?: number | null;
+  max_processes?: number | null;
+  max_open_files?: number | null;
+  max_cpu_secs?: number | null;
+};
+
+export type CandidateProcess = {
+  pid: number;
+  comm: string;
+  exe: string;
+};
+
+export type BinaryInfo = {
+  kind: BinaryKind;
+  interpreter?: string | null;
+  executable: boolean;
+  warning?: string | null;
 };
 
 export type AppState = {
@@ -16,9 +102,347 @@ export type AppState = {
   last_tunnel_id?: string | null;
   last_app_id?: string | null;
   vpn_enabled: boolean;
+  active_tunnel_id?: string | null;
+  on_network_change: NetworkChangeAction;
+  on_suspend: SuspendAction;
+  probe_timeout_secs: number;
+  probe_retries: number;
+  extra_env_keys: string[];
+  config_via_stdin: boolean;
+  terminal?: string | null;
+  on_connect_cmd?: string | null;
+  on_disconnect_cmd?: string | null;
+  privileged_debug?: boolean;
+  randomize_subnet?: boolean;
+  app_output_limit_kib: number;
+  bandwidth_limit_mbps?: number | null;
+  kill_apps_on_disable: boolean;
+  app_log_limit_kib: number;
+  compress_app_logs: boolean;
+};
+
+export type NetworkChangeAction = "off" | "notify" | "disable";
+
+export type SuspendAction = "off" | "reconnect" | "pause";
+
+export type PowerChange = {
+  sleeping: boolean;
+  action: SuspendAction;
+};
+
+export type NetworkChange = {
+  previous?: string | null;
+  current?: string | null;
+  action: NetworkChangeAction;
 };
 
 export type PolkitStatus = {
   running: boolean;
   detail: string;
 };
+
+export type GroupMembership = {
+  group: string;
+  user: string | null;
+  exists: boolean;
+  member: boolean;
+  active_in_session: boolean;
+  detail: string;
+};
+
+export type SetupStep = {
+  id: "dependencies" | "polkit_agent" | "helper" | "polkit_autostart";
+  status: "ok" | "done" | "skipped" | "failed";
+  detail: string;
+};
+
+export type SetupReport = {
+  steps: SetupStep[];
+  ready: boolean;
+};
+
+export type InstanceStatus = {
+  lock_held: boolean;
+  other_pids: number[];
+};
+
+export type ResolvedStatus = {
+  active: boolean;
+  stub_symlink: boolean;
+  resolv_conf_target?: string | null;
+  namespace_dns_ok: boolean;
+  suggest_resolvectl: boolean;
+  detail: string;
+};
+
+export type TunnelConnection = {
+  proto: string;
+  state: string;
+  local: string;
+  peer: string;
+  process?: string | null;
+  pids: number[];
+};
+
+export type CommandPreview = {
+  argv: string[];
+  env: string[];
+};
+
+export type HelperCandidate = {
+  source: "env" | "exe_dir" | "appdir" | "target_debug" | "target_release";
+  path: string;
+  exists: boolean;
+};
+
+export type HelperResolution = {
+  path: string | null;
+  source: HelperCandidate["source"] | null;
+  reason: string;
+  candidates: HelperCandidate[];
+  installed_path: string;
+  installed_version: string | null;
+};
+
+export type RoutingConflict = {
+  source: "rule" | "route";
+  entry: string;
+};
+
+export type EnvironmentStatus = {
+  backend: "kernel" | "userspace" | "missing";
+  userspace_implementation: string | null;
+  missing_tools: string[];
+  amneziawg_available: boolean;
+  detail: string;
+};
+
+export type BundleImport = {
+  tunnels: number;
+  apps: number;
+  settings_restored: boolean;
+  skipped_on_connect_cmd: string | null;
+  skipped_on_disconnect_cmd: string | null;
+};
+
+export type PeerStatus = {
+  public_key: string;
+  endpoint: string | null;
+  live_endpoint: string | null;
+  allowed_ips: string[];
+  last_handshake: number | null;
+  rx: number;
+  tx: number;
+  online: boolean;
+  clock_skew_suspected: boolean;
+  in_config: boolean;
+  in_interface: boolean;
+};
+
+export type OutputLine = {
+  seq: number;
+  text: string;
+};
+
+export type AppLiveOutput = {
+  lines: OutputLine[];
+  next_seq: number;
+  running: boolean;
+};
+
+export type TunnelCoverage = {
+  full_tunnel_v4: boolean;
+  full_tunnel_v6: boolean;
+  routed_cidrs: string[];
+};
+
+export type ClockStatus = {
+  system_time: number;
+  ntp_synchronized: boolean | null;
+  reference_time: number | null;
+  skew_secs: number | null;
+  clock_skew_suspected: boolean;
+  detail: string;
+};
+
+export type LatencySample = {
+  tunnel_id: string;
+  seq: number;
+  rtt_ms: number | null;
+  average_ms: number | null;
+  jitter_ms: number | null;
+  loss_percent: number;
+};
+
+export type LastOperation = {
+  operation: string;
+  ok: boolean;
+  error: string | null;
+  failed_command: string | null;
+  exit_code: number | null;
+  stderr_tail: string | null;
+  timestamp: number;
+};
+
+export type LeakRisk = {
+  code:
+    | "ipv6"
+    | "dns_host"
+    | `dns_server:${string}`
+    | "kill_switch"
+    | `endpoint_route:${string}`;
+  detail: string;
+};
+
+export type TunnelOp =
+  | { op: "rename"; tunnel_id: string; name: string }
+  | { op: "move"; tunnel_id: string; index: number }
+  | { op: "set_enabled"; tunnel_id: string; enabled: boolean };
+
+export type DiagnosticOutput = {
+  exit_code: number;
+  stdout: string;
+  stderr: string;
+};
+
+export type LogGeneration = {
+  index: number;
+  path: string;
+  size: number;
+  lines: number;
+};
+
+export type AppExitStatus = {
+  app_id: string;
+  code?: number | null;
+  success: boolean;
+  stdout: string;
+  stderr: string;
+};
+
+export type AppLaunchResult = {
+  app_id: string;
+  label: string;
+  status: "skipped" | "failed" | "confirmed" | "unconfirmed";
+  detail?: string | null;
+  pid?: number | null;
+};
+
+export type EnablePlan = {
+  steps: string[];
+  errors: string[];
+};
+
+export type ErrorCode =
+  | "TUNNEL_NOT_FOUND"
+  | "TUNNEL_DISABLED"
+  | "APP_NOT_FOUND"
+  | "APP_DISABLED"
+  | "APP_RUNNING"
+  | "APP_PINNED"
+  | "VPN_ACTIVE"
+  | "VPN_INACTIVE"
+  | "TARGET_NOT_EMPTY"
+  | "STORAGE"
+  | "HELPER_MISSING"
+  | "PERMISSION_DENIED"
+  | "DNS_CONFLICT"
+  | "LEAK_RISK"
+  | "HELPER_FAILED"
+  | "INVALID_INPUT"
+  | "TIMED_OUT"
+  | "CANCELLED"
+  | "FAILED";
+
+export type CommandError = {
+  code: ErrorCode;
+  message: string;
+};
+
+export type NamespaceProcess = {
+  pid: number;
+  comm: string;
+  exe: string | null;
+};
+
+export type NamespaceProcesses = {
+  namespace: string;
+  processes: NamespaceProcess[];
+};
+
+export type DisableReport = {
+  killed: number;
+  remaining: number;
+  warning: string | null;
+};
+
+export type PurgeReport = {
+  killed: number;
+  namespaces_removed: string[];
+};
+
+export type EndpointReachability = {
+  host: string;
+  port: number;
+  address: string | null;
+  status: "ok" | "no_response" | "unresolved" | "refused" | "no_route" | "unreachable";
+  detail: string;
+};
+
+export type KernelMessages = {
+  source: "journal" | "dmesg";
+  ifname: string;
+  lines: string[];
+};
+
+export type AppTraffic = {
+  app_id: string;
+  label: string;
+  rx_bytes: number;
+  tx_bytes: number;
+};
+
+export type TunnelStatus = {
+  interface_up: boolean;
+  last_handshake_secs: number | null;
+  rx_bytes: number;
+  tx_bytes: number;
+};
+
+export type PeerTransfer = {
+  public_key: string;
+  rx_bytes: number;
+  tx_bytes: number;
+};
+
+export type TransferStats = {
+  peers: PeerTransfer[];
+};
+
+export type NetworkPlan = {
+  subnet: string;
+  table: number;
+  fwmark: string;
+  steps: string[];
+  conflicts: RoutingConflict[];
+};
+
+export type NatCheck = {
+  status: "ok" | "no_reply" | "nat_broken" | "masquerade_disabled";
+  masqueraded_packets: number;
+  reply: boolean;
+  firewall: string | null;
+  detail: string;
+};
+
+export type Summary = {
+  tunnels: number;
+  apps: number;
+  running_apps: number | null;
+  active_tunnel: string | null;
+  session: { rx_bytes: number; tx_bytes: number } | null;
+  total_connects: number;
+  helper_version: string | null;
+  helper_up_to_date: boolean;
+  nat_check: NatCheck | null;
+};